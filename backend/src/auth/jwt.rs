@@ -1,8 +1,11 @@
-use jsonwebtoken::{encode, Header, EncodingKey};
+use axum::{extract::FromRequestParts, http::{header, request::Parts, StatusCode}};
+use jsonwebtoken::{decode, encode, DecodingKey, Header, EncodingKey, Validation};
 use serde::{Serialize, Deserialize};
 use chrono::{Utc, Duration};
 use uuid::Uuid;
 
+use crate::{ApiError, api_error};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,           
@@ -29,3 +32,28 @@ pub fn create_jwt(user_id: Uuid) -> String {
     )
     .unwrap()
 }
+
+pub fn verify_jwt(token: &str) -> Option<Uuid> {
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    decode::<Claims>(token, &DecodingKey::from_secret(secret.as_ref()), &Validation::default())
+        .ok()
+        .map(|data| data.claims.sub)
+}
+
+//the user behind the `Authorization: Bearer <token>` header issued by signin
+pub struct AuthUser(pub Uuid);
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .and_then(verify_jwt)
+            .map(AuthUser)
+            .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "missing or invalid token"))
+    }
+}
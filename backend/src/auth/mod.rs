@@ -1,2 +1,2 @@
-pub mod jwt;
+mod jwt;
 pub use jwt::*;
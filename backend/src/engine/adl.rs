@@ -0,0 +1,111 @@
+// Auto-deleveraging queue. When a liquidation cannot be closed and the insurance fund
+// cannot pay for it, the most profitable positions on the other side are closed against
+// it at the bankruptcy price, highest score first:
+//   score = unrealized PnL / entry notional * leverage   (only positions in profit)
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{LIQUIDATION_ACCOUNT, Position, Price, Quantity, Side, Symbol, UserId};
+
+pub fn adl_score(position: &Position, mark: Price) -> Option<Decimal> {
+    let pnl = position.unrealized_pnl(mark);
+    let entry_notional = position.notional(position.entry_price);
+    if pnl <= Decimal::ZERO || entry_notional.is_zero() {
+        return None;
+    }
+    Some((pnl / entry_notional * position.leverage).round_dp(8).normalize())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdlCandidate {
+    pub user_id: UserId,
+    pub size: Quantity,
+    pub score: Decimal,
+}
+
+//positions of `side` on `symbol` in deleveraging order. Ties go by user id so the
+//order never depends on hash map iteration
+pub fn adl_queue<'a>(positions: impl Iterator<Item = &'a Position>, symbol: Symbol, side: Side, mark: Price) -> Vec<AdlCandidate> {
+    let mut queue: Vec<AdlCandidate> = positions
+        .filter(|p| p.symbol == symbol && p.side() == Some(side) && p.user_id != LIQUIDATION_ACCOUNT)
+        .filter_map(|p| adl_score(p, mark).map(|score| AdlCandidate { user_id: p.user_id, size: p.size, score }))
+        .collect();
+    queue.sort_by(|a, b| b.score.cmp(&a.score).then(a.user_id.cmp(&b.user_id)));
+    queue
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AdlRank {
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub score: Option<Decimal>,  //None: not in profit, never deleveraged
+    pub rank: Option<usize>,     //1 = first in line
+    pub queue_len: usize,
+    pub quantile: u8,            //1-5 like the usual ADL lights, 5 = most at risk, 0 = not in the queue
+}
+
+pub fn adl_rank(position: &Position, queue: &[AdlCandidate]) -> AdlRank {
+    let rank = queue.iter().position(|c| c.user_id == position.user_id).map(|i| i + 1);
+    let quantile = match rank {
+        Some(rank) => (5 - ((rank - 1) * 5 / queue.len())) as u8,
+        None => 0,
+    };
+    AdlRank {
+        user_id: position.user_id,
+        symbol: position.symbol,
+        side: position.side().unwrap_or(Side::Buy),
+        score: rank.map(|i| queue[i - 1].score),
+        rank,
+        queue_len: queue.len(),
+        quantile,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn position(user: u128, symbol: &str, size: Decimal, entry_price: Price, leverage: Decimal) -> Position {
+        Position {
+            user_id: Uuid::from_u128(user),
+            symbol: Symbol::new(symbol).unwrap(),
+            size,
+            entry_price,
+            realized_pnl: Decimal::ZERO,
+            leverage,
+            updated_at: 0,
+        }
+    }
+
+    //shorts at mark 90: 10% up at 10x scores 1, 10% up at 5x scores 0.5
+    #[test]
+    fn the_queue_holds_profitable_positions_of_one_side_highest_score_first() {
+        let positions = [
+            position(30, "BTC-PERP", dec!(-1), dec!(100), dec!(5)),
+            position(20, "BTC-PERP", dec!(-2), dec!(100), dec!(5)),
+            position(10, "BTC-PERP", dec!(-1), dec!(100), dec!(10)),
+            position(40, "BTC-PERP", dec!(-1), dec!(80), dec!(20)),   //losing
+            position(1, "BTC-PERP", dec!(-1), dec!(100), dec!(10)),   //liquidation account
+            position(50, "BTC-PERP", dec!(1), dec!(80), dec!(10)),    //long
+            position(60, "ETH-PERP", dec!(-1), dec!(100), dec!(10)),  //other symbol
+        ];
+        let queue = adl_queue(positions.iter(), Symbol::new("BTC-PERP").unwrap(), Side::Sell, dec!(90));
+        let order: Vec<_> = queue.iter().map(|c| (c.user_id, c.size, c.score)).collect();
+        assert_eq!(
+            order,
+            [
+                (Uuid::from_u128(10), dec!(-1), dec!(1)),
+                (Uuid::from_u128(20), dec!(-2), dec!(0.5)),
+                (Uuid::from_u128(30), dec!(-1), dec!(0.5)),
+            ]
+        );
+
+        let ranks: Vec<_> = positions[..4].iter().map(|p| adl_rank(p, &queue)).map(|r| (r.rank, r.quantile, r.score)).collect();
+        assert_eq!(ranks, [(Some(3), 2, Some(dec!(0.5))), (Some(2), 4, Some(dec!(0.5))), (Some(1), 5, Some(dec!(1))), (None, 0, None)]);
+    }
+}
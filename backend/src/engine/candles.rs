@@ -0,0 +1,294 @@
+// Candles: OHLCV bars per symbol for every interval, from fills (trade) and mark prices (mark).
+// The consumer keeps the open bar of each series in memory; a bar closes when the first event of a
+// later bar arrives, closed bars go to a writer task. Quiet periods leave no bar.
+// On startup trade bars are rebuilt from the persisted trades after the last stored bar of every
+// interval, mark bars start fresh since mark prices are not stored.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::DateTime;
+use db::{Candle, Db, TradeRecord};
+use rust_decimal::Decimal;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::{CandleInterval, CandleKind, Event, EventConsumer, Price, Quantity, Symbol};
+
+type SeriesKey = (Symbol, CandleKind, CandleInterval);
+
+#[derive(Default)]
+pub struct CandleBook {
+    open: HashMap<SeriesKey, Candle>,
+    last_closed: HashMap<SeriesKey, Candle>,  //may not be stored yet, served until it is
+}
+
+pub type SharedCandles = Arc<RwLock<CandleBook>>;
+
+impl CandleBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //`quantity` is None for mark prices. Returns the bars this update closed
+    pub fn update(&mut self, symbol: Symbol, kind: CandleKind, price: Price, quantity: Option<Quantity>, time: i64) -> Vec<(CandleInterval, Candle)> {
+        let mut closed = Vec::new();
+        for interval in CandleInterval::ALL {
+            let key = (symbol, kind, interval);
+            let open_time = interval.open_time(time);
+            //an event stamped slightly before the open bar still counts towards it
+            if let Some(bar) = self.open.get(&key).filter(|bar| bar.open_time < open_time) {
+                let bar = bar.clone();
+                self.last_closed.insert(key, bar.clone());
+                closed.push((interval, bar));
+                self.open.remove(&key);
+            }
+            let bar = self.open.entry(key).or_insert_with(|| Candle {
+                symbol: symbol.to_string(),
+                kind: kind.as_str().to_string(),
+                interval: interval.as_str().to_string(),
+                open_time,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: Decimal::ZERO,
+                quote_volume: Decimal::ZERO,
+                trades: 0,
+            });
+            bar.high = bar.high.max(price);
+            bar.low = bar.low.min(price);
+            bar.close = price;
+            if let Some(quantity) = quantity {
+                bar.volume = (bar.volume + quantity).normalize();
+                bar.quote_volume = (bar.quote_volume + quantity * price).normalize();
+                bar.trades += 1;
+            }
+        }
+        closed
+    }
+
+    pub fn open_candle(&self, symbol: Symbol, kind: CandleKind, interval: CandleInterval) -> Option<&Candle> {
+        self.open.get(&(symbol, kind, interval))
+    }
+
+    pub fn last_closed(&self, symbol: Symbol, kind: CandleKind, interval: CandleInterval) -> Option<&Candle> {
+        self.last_closed.get(&(symbol, kind, interval))
+    }
+}
+
+//rebuilds trade bars from the persisted trades and stores the closed ones.
+//every interval restarts at the bar after its newest stored one; bars before that are kept as stored
+pub async fn backfill_candles(db: &Db, book: &mut CandleBook, symbol: Symbol) -> anyhow::Result<usize> {
+    let kind = CandleKind::Trade;
+    let stored: HashMap<String, i64> = db.get_last_candle_times(symbol.as_str(), kind.as_str()).await?.into_iter().collect();
+    let resume: HashMap<CandleInterval, Option<i64>> = CandleInterval::ALL
+        .into_iter()
+        .map(|i| (i, stored.get(i.as_str()).map(|t| t + i.millis())))
+        .collect();
+    //None anywhere means that interval was never stored: rebuild from the first trade
+    let since = resume.values().copied().collect::<Option<Vec<_>>>().and_then(|t| t.into_iter().min());
+    let since = match since {
+        Some(ms) => Some(DateTime::from_timestamp_millis(ms).ok_or_else(|| anyhow::anyhow!("invalid bar time {ms}"))?),
+        None => None,
+    };
+
+    let trades = db.get_trades_since(symbol.as_str(), since).await?;
+    let closed = replay_trades(book, symbol, &resume, &trades);
+    db.upsert_candles(&closed).await?;
+    println!(" [CANDLES] {} rebuilt from {} trades", symbol, trades.len());
+    Ok(trades.len())
+}
+
+//`resume` per interval: the first bar to keep, None to keep them all. Returns the closed bars to store
+fn replay_trades(book: &mut CandleBook, symbol: Symbol, resume: &HashMap<CandleInterval, Option<i64>>, trades: &[TradeRecord]) -> Vec<Candle> {
+    let kind = CandleKind::Trade;
+    let keep = |c: &Candle, interval: CandleInterval| resume[&interval].is_none_or(|t| c.open_time >= t);
+    let mut closed = Vec::new();
+    for t in trades {
+        for (interval, bar) in book.update(symbol, kind, t.price, Some(t.quantity), t.traded_at.timestamp_millis()) {
+            if keep(&bar, interval) {
+                closed.push(bar);
+            }
+        }
+    }
+    //an open bar that started before its interval resumes only saw part of its trades
+    for interval in CandleInterval::ALL {
+        let key = (symbol, kind, interval);
+        if book.open.get(&key).is_some_and(|bar| !keep(bar, interval)) {
+            book.open.remove(&key);
+        }
+    }
+    closed
+}
+
+pub struct CandleConsumer {
+    book: SharedCandles,
+    closed: Vec<Candle>,
+    tx: mpsc::Sender<Vec<Candle>>,
+}
+
+impl CandleConsumer {
+    pub fn new(book: SharedCandles, tx: mpsc::Sender<Vec<Candle>>) -> Self {
+        Self { book, closed: Vec::new(), tx }
+    }
+
+    fn update(&mut self, symbol: Symbol, kind: CandleKind, price: Price, quantity: Option<Quantity>, timestamp: u128) {
+        let time = (timestamp / 1_000_000) as i64;
+        let closed = self.book.write().unwrap_or_else(|e| e.into_inner()).update(symbol, kind, price, quantity, time);
+        self.closed.extend(closed.into_iter().map(|(_, bar)| bar));
+    }
+
+    //never waits for the writer: while its queue is full the bars stay here until a later batch
+    fn send(&mut self) {
+        if self.closed.is_empty() {
+            return;
+        }
+        match self.tx.try_send(mem::take(&mut self.closed)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(bars)) => self.closed = bars,
+            Err(TrySendError::Closed(_)) => println!(" [CANDLES] writer is gone, closed bars are no longer stored"),
+        }
+    }
+}
+
+impl EventConsumer for CandleConsumer {
+    fn name(&self) -> &'static str {
+        "candles"
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        match event {
+            Event::Fill(fill) => self.update(fill.symbol, CandleKind::Trade, fill.price, Some(fill.quantity), fill.timestamp_),
+            Event::MarkPrice { symbol, price, timestamp } => self.update(*symbol, CandleKind::Mark, *price, None, *timestamp),
+            _ => {}
+        }
+    }
+
+    fn end_batch(&mut self) {
+        self.send();
+    }
+
+    //shutdown: waits for room so no closed bar is lost. Open bars are not stored, after a restart
+    //the backfill rebuilds them from trades
+    fn flush(&mut self) {
+        if !self.closed.is_empty() && self.tx.blocking_send(mem::take(&mut self.closed)).is_err() {
+            println!(" [CANDLES] writer is gone, closed bars are no longer stored");
+        }
+    }
+}
+
+pub fn spawn_candle_writer(db: Db, mut rx: mpsc::Receiver<Vec<Candle>>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(candles) = rx.recv().await {
+            let mut backoff = Duration::from_millis(100);
+            while let Err(e) = db.upsert_candles(&candles).await {
+                println!(" [CANDLES] storing {} bars failed, retrying in {backoff:?}: {e}", candles.len());
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(5));
+            }
+        }
+        println!(" [CANDLES] writer stopped");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use super::*;
+
+    const MINUTE: i64 = 60_000;
+
+    fn symbol() -> Symbol {
+        Symbol::new("BTC-PERP").unwrap()
+    }
+
+    fn trade(time: i64, price: Price) -> TradeRecord {
+        TradeRecord {
+            trade_id: time,
+            symbol: symbol().to_string(),
+            price,
+            quantity: dec!(1),
+            taker_side: "buy".to_string(),
+            maker_order_id: Uuid::nil(),
+            taker_order_id: Uuid::nil(),
+            traded_at: DateTime::from_timestamp_millis(time).unwrap(),
+        }
+    }
+
+    #[test]
+    fn a_bar_closes_on_the_first_event_of_a_later_bar() {
+        let mut book = CandleBook::new();
+        let (trade, m1) = (CandleKind::Trade, CandleInterval::M1);
+        assert!(book.update(symbol(), trade, dec!(100), Some(dec!(1)), 10_000).is_empty());
+        assert!(book.update(symbol(), trade, dec!(104), Some(dec!(2)), 50_000).is_empty());
+        assert!(book.update(symbol(), CandleKind::Mark, dec!(101), None, 55_000).is_empty());
+
+        let closed = book.update(symbol(), trade, dec!(99), Some(dec!(1)), MINUTE + 500);
+        assert_eq!(closed.len(), 1);
+        let (interval, bar) = &closed[0];
+        assert_eq!((*interval, bar.open_time), (m1, 0));
+        assert_eq!((bar.open, bar.high, bar.low, bar.close), (dec!(100), dec!(104), dec!(100), dec!(104)));
+        assert_eq!((bar.volume, bar.quote_volume, bar.trades), (dec!(3), dec!(308), 2));
+        assert_eq!(book.last_closed(symbol(), trade, m1).map(|c| c.open_time), Some(0));
+
+        //stamped just before the open bar, still counted towards it
+        assert!(book.update(symbol(), trade, dec!(98), Some(dec!(1)), MINUTE - 100).is_empty());
+        let open = book.open_candle(symbol(), trade, m1).unwrap();
+        assert_eq!((open.open_time, open.low, open.close, open.trades), (MINUTE, dec!(98), dec!(98), 2));
+
+        let mark = book.open_candle(symbol(), CandleKind::Mark, m1).unwrap();
+        assert_eq!((mark.close, mark.volume, mark.trades), (dec!(101), dec!(0), 0));
+        assert_eq!(book.open_candle(symbol(), trade, CandleInterval::M5).map(|c| c.trades), Some(4));
+    }
+
+    //1m is stored up to the 10th minute, 5m up to the one starting at 5, 1h up to the first hour
+    #[test]
+    fn the_backfill_resumes_every_interval_after_its_newest_stored_bar() {
+        let mut resume: HashMap<CandleInterval, Option<i64>> = CandleInterval::ALL.into_iter().map(|i| (i, None)).collect();
+        resume.insert(CandleInterval::M1, Some(11 * MINUTE));
+        resume.insert(CandleInterval::M5, Some(10 * MINUTE));
+        resume.insert(CandleInterval::H1, Some(60 * MINUTE));
+        let trades = [
+            trade(9 * MINUTE + 50_000, dec!(100)),
+            trade(10 * MINUTE + 50_000, dec!(101)),
+            trade(11 * MINUTE + 10_000, dec!(103)),
+            trade(12 * MINUTE + 5_000, dec!(102)),
+        ];
+
+        let mut book = CandleBook::new();
+        let closed = replay_trades(&mut book, symbol(), &resume, &trades);
+        let closed: Vec<_> = closed.iter().map(|c| (c.interval.as_str(), c.open_time, c.close)).collect();
+        assert_eq!(closed, [("1m", 11 * MINUTE, dec!(103))]);
+
+        let open = |interval| book.open_candle(symbol(), CandleKind::Trade, interval).map(|c| (c.open_time, c.trades));
+        assert_eq!(open(CandleInterval::M1), Some((12 * MINUTE, 1)));
+        assert_eq!(open(CandleInterval::M5), Some((10 * MINUTE, 3)));
+        assert_eq!(open(CandleInterval::M15), Some((0, 4)));
+        //started before it resumes, it would miss the stored part of the hour
+        assert_eq!(open(CandleInterval::H1), None);
+    }
+
+    #[test]
+    fn closed_bars_wait_while_the_writer_queue_is_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut consumer = CandleConsumer::new(Arc::new(RwLock::new(CandleBook::new())), tx);
+        let mark = |minute: i64| Event::MarkPrice { symbol: symbol(), price: dec!(100), timestamp: (minute * MINUTE) as u128 * 1_000_000 };
+
+        consumer.on_event(&mark(0));
+        consumer.on_event(&mark(1));
+        consumer.end_batch();
+        consumer.on_event(&mark(2));
+        consumer.end_batch();
+        assert_eq!(consumer.closed.len(), 1);
+
+        assert_eq!(rx.try_recv().unwrap().len(), 1);
+        consumer.on_event(&mark(3));
+        consumer.end_batch();
+        assert!(consumer.closed.is_empty());
+        assert_eq!(rx.try_recv().unwrap().iter().map(|c| c.open_time).collect::<Vec<_>>(), [MINUTE, 2 * MINUTE]);
+    }
+}
@@ -0,0 +1,363 @@
+// Lock-free bounded MPSC ring for commands going from the HTTP gateway into the engine.
+// Same layout ideas as RingBuffer (power-of-2 capacity, bitmask wraparound, cache-line aligned
+// indices) but producers claim slots with a CAS, and every slot carries a sequence number
+// telling whether it is free, written, or still being written by a producer.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{AlignedUsize, WaitStrategy};
+
+struct Slot<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub struct MpscRing<T> {
+    buffer: Box<[Slot<T>]>,
+    capacity: usize,
+    mask: usize,
+    //unlike RingBuffer these never wrap at capacity, the slot is picked with `idx & mask`
+    write_idx: AlignedUsize,
+    read_idx: AlignedUsize,
+}
+
+unsafe impl<T: Send> Send for MpscRing<T> {}
+unsafe impl<T: Send> Sync for MpscRing<T> {}
+
+impl<T> MpscRing<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two(), "Capacity must be power of 2");
+        assert!(capacity > 1, "Capacity must be > 1");
+
+        //slot i starts with seq i: "free for the producer that claims index i"
+        let buffer = (0..capacity)
+            .map(|i| Slot {
+                seq: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+
+        Self {
+            buffer,
+            capacity,
+            mask: capacity - 1,
+            write_idx: AlignedUsize(AtomicUsize::new(0)),
+            read_idx: AlignedUsize(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        let write = self.write_idx.0.load(Ordering::Acquire);
+        let read = self.read_idx.0.load(Ordering::Acquire);
+        write.wrapping_sub(read).min(self.capacity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //safe to call from any number of threads, hands the item back when full
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        let mut pos = self.write_idx.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos) as isize;
+
+            if diff == 0 {
+                //slot is free for index `pos`, try to claim it
+                match self.write_idx.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(item) };
+                        //publish: consumer waits for seq == pos + 1
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                //consumer has not freed this slot yet: ring is full
+                return Err(item);
+            } else {
+                //another producer took it, reload
+                pos = self.write_idx.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    //single consumer only
+    pub fn try_pop(&self) -> Option<T> {
+        let pos = self.read_idx.0.load(Ordering::Relaxed);
+        let slot = &self.buffer[pos & self.mask];
+        let seq = slot.seq.load(Ordering::Acquire);
+
+        //empty, or a producer claimed the slot but has not finished writing
+        if seq != pos.wrapping_add(1) {
+            return None;
+        }
+
+        let item = unsafe { (*slot.value.get()).assume_init_read() };
+        //free the slot for the producer one lap ahead
+        slot.seq.store(pos.wrapping_add(self.capacity), Ordering::Release);
+        self.read_idx.0.store(pos.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    //batched read, stops early at a slot that is still being written so order is kept
+    pub fn drain_into(&self, out: &mut Vec<T>, max_items: usize) -> usize {
+        let mut taken = 0;
+        while taken < max_items {
+            match self.try_pop() {
+                Some(item) => {
+                    out.push(item);
+                    taken += 1;
+                }
+                None => break,
+            }
+        }
+        taken
+    }
+}
+
+impl<T> Drop for MpscRing<T> {
+    fn drop(&mut self) {
+        //drop whatever is still queued (responders inside commands must be dropped so callers wake up)
+        while self.try_pop().is_some() {}
+    }
+}
+
+struct Shared<T> {
+    ring: MpscRing<T>,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+    //sends that got past the closed check and may still be pushing
+    in_flight: AtomicUsize,
+}
+
+pub enum TrySendError<T> {
+    Full(T),    //load shedding: engine is behind, caller should back off
+    Closed(T),  //engine is gone or shutting down
+}
+
+#[derive(Debug)]
+pub struct RecvError;
+
+pub struct RingSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct RingReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+//like std::sync::mpsc::sync_channel but lock-free and never blocks the sender
+pub fn mpsc_ring<T>(capacity: usize) -> (RingSender<T>, RingReceiver<T>) {
+    let shared = Arc::new(Shared {
+        ring: MpscRing::new(capacity),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        in_flight: AtomicUsize::new(0),
+    });
+    (
+        RingSender { shared: shared.clone() },
+        RingReceiver { shared },
+    )
+}
+
+impl<T> RingSender<T> {
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        //announce the send before looking at `closed` (both SeqCst): a receiver that saw closed and
+        //then no send in flight knows every later send sees closed too
+        self.shared.in_flight.fetch_add(1, Ordering::SeqCst);
+        let result = if self.shared.closed.load(Ordering::SeqCst) {
+            Err(TrySendError::Closed(item))
+        } else {
+            self.shared.ring.try_push(item).map_err(TrySendError::Full)
+        };
+        self.shared.in_flight.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.ring.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.ring.capacity()
+    }
+
+    //stop intake: every sender gets Closed from now on, the receiver still drains what is queued
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T> Clone for RingSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for RingSender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<T> RingReceiver<T> {
+    //waits (per `wait`) until at least one item is queued, then drains up to `max_items`.
+    //Err once the ring is closed (or every sender is gone) and nothing is left to drain.
+    pub fn recv_batch(&self, out: &mut Vec<T>, max_items: usize, wait: WaitStrategy) -> Result<usize, RecvError> {
+        loop {
+            let taken = self.shared.ring.drain_into(out, max_items);
+            if taken > 0 {
+                return Ok(taken);
+            }
+            if self.shared.closed.load(Ordering::SeqCst) || self.shared.senders.load(Ordering::Acquire) == 0 {
+                //sends that passed the closed check before it was set finish their push first
+                while self.shared.in_flight.load(Ordering::SeqCst) > 0 {
+                    std::hint::spin_loop();
+                }
+                return match self.shared.ring.drain_into(out, max_items) {
+                    0 => Err(RecvError),
+                    n => Ok(n),
+                };
+            }
+            wait.wait();
+        }
+    }
+
+    pub fn try_drain(&self, out: &mut Vec<T>, max_items: usize) -> usize {
+        self.shared.ring.drain_into(out, max_items)
+    }
+}
+
+impl<T> Drop for RingReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use super::*;
+
+    const PRODUCERS: u64 = 4;
+    const PER_PRODUCER: u64 = 20_000;
+
+    #[test]
+    fn concurrent_producers_keep_their_order() {
+        //a small ring so producers keep hitting Full
+        let (tx, rx) = mpsc_ring::<u64>(64);
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        let mut item = (p << 32) | i;
+                        loop {
+                            match tx.try_send(item) {
+                                Ok(()) => break,
+                                Err(TrySendError::Full(back)) => {
+                                    item = back;
+                                    thread::yield_now();
+                                }
+                                Err(TrySendError::Closed(_)) => panic!("ring closed"),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut next = [0u64; PRODUCERS as usize];
+        let mut batch = Vec::new();
+        while rx.recv_batch(&mut batch, 256, WaitStrategy::Yield).is_ok() {
+            for item in batch.drain(..) {
+                let (p, i) = ((item >> 32) as usize, item & 0xffff_ffff);
+                assert_eq!(i, next[p], "producer {p} out of order");
+                next[p] += 1;
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert!(next.iter().all(|&n| n == PER_PRODUCER));
+    }
+
+    #[test]
+    fn close_rejects_new_sends_and_drains_queued() {
+        let (tx, rx) = mpsc_ring::<u32>(8);
+        assert!(tx.try_send(1).is_ok());
+        assert!(tx.try_send(2).is_ok());
+        tx.close();
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Closed(3))));
+
+        let mut out = Vec::new();
+        assert_eq!(rx.recv_batch(&mut out, 16, WaitStrategy::BusySpin).unwrap(), 2);
+        assert_eq!(out, vec![1, 2]);
+        assert!(rx.recv_batch(&mut out, 16, WaitStrategy::BusySpin).is_err());
+    }
+
+    #[test]
+    fn every_accepted_send_is_received_across_close() {
+        for _ in 0..20 {
+            let (tx, rx) = mpsc_ring::<u64>(1024);
+            let stop = Arc::new(AtomicBool::new(false));
+            let producers: Vec<_> = (0..PRODUCERS)
+                .map(|_| {
+                    let tx = tx.clone();
+                    let stop = stop.clone();
+                    thread::spawn(move || {
+                        let mut accepted = 0u64;
+                        while !stop.load(Ordering::Relaxed) {
+                            match tx.try_send(1) {
+                                Ok(()) => accepted += 1,
+                                Err(TrySendError::Full(_)) => thread::yield_now(),
+                                Err(TrySendError::Closed(_)) => break,
+                            }
+                        }
+                        accepted
+                    })
+                })
+                .collect();
+
+            let mut received = 0u64;
+            let mut batch = Vec::new();
+            for _ in 0..50 {
+                received += rx.try_drain(&mut batch, 1024) as u64;
+                batch.clear();
+                thread::yield_now();
+            }
+            tx.close();
+            while let Ok(n) = rx.recv_batch(&mut batch, 1024, WaitStrategy::Yield) {
+                received += n as u64;
+                batch.clear();
+            }
+            stop.store(true, Ordering::Relaxed);
+            let accepted: u64 = producers.into_iter().map(|p| p.join().unwrap()).sum();
+            assert_eq!(received, accepted);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+// Custody: where deposits come from and withdrawals go to.
+// A tokio task polls the provider: new deposits are recorded (idempotent by custody ref) and
+// credited, approved withdrawals are sent, sent ones are followed until confirmed or failed.
+// The wallet holds withdrawal amounts locked from the request until completion or rejection.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use db::{Db, Withdrawal};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::{SharedWallet, UserId, WithdrawalStatus, now_millis};
+
+pub struct IncomingDeposit {
+    pub custody_ref: String,
+    pub user_id: UserId,
+    pub asset: String,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CustodyState {
+    Pending,
+    Confirmed,
+    Failed(String),
+}
+
+pub trait CustodyProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    //deposits seen since the last poll
+    fn poll_deposits(&self) -> Vec<IncomingDeposit>;
+    //returns the provider's reference for the transfer
+    fn send_withdrawal(&self, withdrawal_id: Uuid, address: &str, asset: &str, amount: Decimal) -> Result<String, String>;
+    fn withdrawal_state(&self, custody_ref: &str) -> Result<CustodyState, String>;
+}
+
+#[derive(Default)]
+struct SimulatedState {
+    incoming: VecDeque<IncomingDeposit>,
+    sent: HashMap<String, (u32, CustodyState)>,  //polls so far, final state
+    next_ref: u64,
+}
+
+//in-process stand-in for a custodian: deposits are injected by hand, a withdrawal confirms after
+//`confirmations` polls, withdrawals to an address starting with "fail" are refused on-chain
+pub struct SimulatedCustody {
+    confirmations: u32,
+    state: Mutex<SimulatedState>,
+}
+
+impl SimulatedCustody {
+    pub fn new(confirmations: u32) -> Self {
+        Self { confirmations, state: Mutex::new(SimulatedState::default()) }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("CUSTODY_CONFIRMATIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(2))
+    }
+
+    fn next_ref(state: &mut SimulatedState, prefix: &str) -> String {
+        state.next_ref += 1;
+        format!("sim-{prefix}-{}-{}", now_millis(), state.next_ref)
+    }
+
+    pub fn simulate_deposit(&self, user_id: UserId, asset: &str, amount: Decimal) -> String {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let custody_ref = Self::next_ref(&mut state, "dep");
+        state.incoming.push_back(IncomingDeposit { custody_ref: custody_ref.clone(), user_id, asset: asset.to_string(), amount });
+        custody_ref
+    }
+}
+
+impl CustodyProvider for SimulatedCustody {
+    fn name(&self) -> &'static str {
+        "simulated"
+    }
+
+    fn poll_deposits(&self) -> Vec<IncomingDeposit> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).incoming.drain(..).collect()
+    }
+
+    fn send_withdrawal(&self, _withdrawal_id: Uuid, address: &str, _asset: &str, _amount: Decimal) -> Result<String, String> {
+        if address.trim().is_empty() {
+            return Err("empty address".to_string());
+        }
+        let outcome = if address.starts_with("fail") {
+            CustodyState::Failed("rejected by network".to_string())
+        } else {
+            CustodyState::Confirmed
+        };
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let custody_ref = Self::next_ref(&mut state, "wd");
+        state.sent.insert(custody_ref.clone(), (0, outcome));
+        Ok(custody_ref)
+    }
+
+    fn withdrawal_state(&self, custody_ref: &str) -> Result<CustodyState, String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((polls, outcome)) = state.sent.get_mut(custody_ref) else {
+            return Err(format!("unknown transfer {custody_ref}"));
+        };
+        *polls += 1;
+        if *polls < self.confirmations {
+            return Ok(CustodyState::Pending);
+        }
+        Ok(outcome.clone())
+    }
+}
+
+pub struct CustodyService {
+    provider: Arc<dyn CustodyProvider>,
+    db: Db,
+    wallet: SharedWallet,
+}
+
+impl CustodyService {
+    pub fn new(provider: Arc<dyn CustodyProvider>, db: Db, wallet: SharedWallet) -> Self {
+        Self { provider, db, wallet }
+    }
+
+    pub async fn credit_deposits(&self) {
+        for deposit in self.provider.poll_deposits() {
+            match self.db.record_deposit(deposit.user_id, &deposit.asset, deposit.amount, &deposit.custody_ref).await {
+                Ok(Some(_)) => {
+                    self.wallet.lock().unwrap_or_else(|e| e.into_inner()).credit(deposit.user_id, deposit.amount);
+                    println!(" [CUSTODY] deposit {} of {} credited to {}", deposit.custody_ref, deposit.amount, deposit.user_id);
+                }
+                Ok(None) => {}
+                //not credited, the provider will not report it again: this needs an operator
+                Err(e) => println!(" [CUSTODY] failed to record deposit {}: {e}", deposit.custody_ref),
+            }
+        }
+    }
+
+    pub async fn send_approved(&self) -> anyhow::Result<()> {
+        let approved = self.db.get_withdrawals(None, Some(WithdrawalStatus::Approved.as_str()), 100).await?;
+        for w in approved {
+            //losses since the request may have eaten into the balance, nothing leaves that it does not cover
+            let balance = self.wallet.lock().unwrap_or_else(|e| e.into_inner()).balance_view(&w.user_id).balance;
+            if balance < w.amount {
+                self.reject(&w, &[WithdrawalStatus::Approved], "insufficient balance").await?;
+                continue;
+            }
+            match self.provider.send_withdrawal(w.id, &w.address, &w.asset, w.amount) {
+                Ok(custody_ref) => {
+                    let (from, to) = (WithdrawalStatus::Approved.as_str(), WithdrawalStatus::Processing.as_str());
+                    self.db.transition_withdrawal(w.id, &[from], to, Some(&custody_ref), None).await?;
+                    println!(" [CUSTODY] withdrawal {} sent as {custody_ref}", w.id);
+                }
+                Err(e) => {
+                    self.reject(&w, &[WithdrawalStatus::Approved], &e).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn follow_processing(&self) -> anyhow::Result<()> {
+        let processing = self.db.get_withdrawals(None, Some(WithdrawalStatus::Processing.as_str()), 100).await?;
+        for w in processing {
+            let Some(custody_ref) = w.custody_ref.as_deref() else { continue };
+            match self.provider.withdrawal_state(custody_ref) {
+                Ok(CustodyState::Pending) => {}
+                Ok(CustodyState::Confirmed) => {
+                    if self.db.complete_withdrawal(w.id).await?.is_some() {
+                        match self.wallet.lock().unwrap_or_else(|e| e.into_inner()).settle_withdrawal(w.user_id, w.amount) {
+                            Ok(()) => println!(" [CUSTODY] withdrawal {} completed", w.id),
+                            //the ledger has it, the wallet catches up from it at the next start
+                            Err(e) => println!(" [CUSTODY] withdrawal {} completed but not settled: {e}", w.id),
+                        }
+                    }
+                }
+                Ok(CustodyState::Failed(reason)) => {
+                    self.reject(&w, &[WithdrawalStatus::Processing], &reason).await?;
+                }
+                Err(e) => println!(" [CUSTODY] status of withdrawal {} unknown: {e}", w.id),
+            }
+        }
+        Ok(())
+    }
+
+    //the lock goes back only if this call is the one that moved it to rejected
+    pub async fn reject(&self, w: &Withdrawal, from: &[WithdrawalStatus], reason: &str) -> anyhow::Result<bool> {
+        let from: Vec<&str> = from.iter().map(|s| s.as_str()).collect();
+        let rejected = self
+            .db
+            .transition_withdrawal(w.id, &from, WithdrawalStatus::Rejected.as_str(), None, Some(reason))
+            .await?
+            .is_some();
+        if rejected {
+            self.wallet.lock().unwrap_or_else(|e| e.into_inner()).unlock_withdrawal(w.user_id, w.amount);
+            println!(" [CUSTODY] withdrawal {} rejected: {reason}", w.id);
+        }
+        Ok(rejected)
+    }
+}
+
+pub fn spawn_custody(service: Arc<CustodyService>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        println!(" [CUSTODY] provider {}", service.provider.name());
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            service.credit_deposits().await;
+            if let Err(e) = service.send_approved().await {
+                println!(" [CUSTODY] sending withdrawals failed: {e}");
+            }
+            if let Err(e) = service.follow_processing().await {
+                println!(" [CUSTODY] following withdrawals failed: {e}");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn simulated_deposits_are_reported_once() {
+        let custody = SimulatedCustody::new(2);
+        let user = Uuid::from_u128(7);
+        let first = custody.simulate_deposit(user, "USDT", dec!(500));
+        let second = custody.simulate_deposit(user, "USDT", dec!(25));
+        assert_ne!(first, second);
+
+        let polled = custody.poll_deposits();
+        assert_eq!(polled.iter().map(|d| d.custody_ref.clone()).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!(polled[0].amount, dec!(500));
+        assert_eq!(polled[0].user_id, user);
+        assert!(custody.poll_deposits().is_empty());
+    }
+
+    #[test]
+    fn simulated_withdrawals_settle_after_the_confirmations() {
+        let custody = SimulatedCustody::new(2);
+        let sent = custody.send_withdrawal(Uuid::from_u128(1), "0xabc", "USDT", dec!(10)).unwrap();
+        let refused = custody.send_withdrawal(Uuid::from_u128(2), "fail-0xabc", "USDT", dec!(10)).unwrap();
+
+        assert_eq!(custody.withdrawal_state(&sent), Ok(CustodyState::Pending));
+        assert_eq!(custody.withdrawal_state(&sent), Ok(CustodyState::Confirmed));
+        assert_eq!(custody.withdrawal_state(&refused), Ok(CustodyState::Pending));
+        assert_eq!(custody.withdrawal_state(&refused), Ok(CustodyState::Failed("rejected by network".to_string())));
+        assert!(custody.withdrawal_state("sim-wd-unknown").is_err());
+        assert!(custody.send_withdrawal(Uuid::from_u128(3), " ", "USDT", dec!(10)).is_err());
+    }
+}
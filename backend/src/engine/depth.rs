@@ -0,0 +1,73 @@
+// L2 depth: the matching thread publishes the aggregated top levels after every batch into a
+// single slot, readers load the latest snapshot without a lock and never touch the book.
+// Every batch that changed levels also emits a DepthUpdate diff; clients stitch diffs onto a
+// snapshot by update id and compare checksums to detect a desync.
+
+use std::sync::Arc;
+use std::fmt::Write;
+
+use arc_swap::ArcSwap;
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+use crate::{DepthLevel, DepthSnapshot, Symbol};
+
+pub const DEFAULT_DEPTH_LEVELS: usize = 100;
+pub const CHECKSUM_LEVELS: usize = 25;
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+pub type SharedDepth = Arc<ArcSwap<DepthSnapshot>>;
+
+pub fn shared_depth(symbol: Symbol) -> SharedDepth {
+    Arc::new(ArcSwap::from_pointee(DepthSnapshot::empty(symbol)))
+}
+
+//CRC32 (IEEE) of "bid1price:bid1qty:ask1price:ask1qty:bid2price:..." over the best CHECKSUM_LEVELS
+//levels of each side (longer slices are cut), numbers as they are serialized; a side that runs out is skipped
+pub fn depth_checksum(bids: &[DepthLevel], asks: &[DepthLevel]) -> u32 {
+    let mut parts = String::new();
+    for i in 0..CHECKSUM_LEVELS {
+        for level in [bids.get(i), asks.get(i)].into_iter().flatten() {
+            if !parts.is_empty() {
+                parts.push(':');
+            }
+            let _ = write!(parts, "{}:{}", level.price, level.quantity);
+        }
+    }
+    CRC32.checksum(parts.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn level(price: &str, quantity: &str) -> DepthLevel {
+        DepthLevel { price: Decimal::from_str(price).unwrap(), quantity: Decimal::from_str(quantity).unwrap(), orders: 1 }
+    }
+
+    #[test]
+    fn crc_is_ieee() {
+        assert_eq!(CRC32.checksum(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn checksum_interleaves_sides() {
+        //"100.5:2:101:3:100:1", the ask side runs out after one level
+        let bids = [level("100.5", "2"), level("100", "1")];
+        let asks = [level("101", "3")];
+        assert_eq!(depth_checksum(&bids, &asks), 0xF630_85A4);
+        assert_eq!(depth_checksum(&[], &[]), 0);
+    }
+
+    #[test]
+    fn checksum_ignores_levels_past_the_limit() {
+        let bids: Vec<_> = (0..CHECKSUM_LEVELS + 5).map(|i| level(&(1000 - i).to_string(), "1")).collect();
+        let asks: Vec<_> = (0..CHECKSUM_LEVELS).map(|i| level(&(1001 + i).to_string(), "1")).collect();
+        assert_eq!(depth_checksum(&bids, &asks), depth_checksum(&bids[..CHECKSUM_LEVELS], &asks));
+        assert_ne!(depth_checksum(&bids, &asks), depth_checksum(&bids[1..], &asks));
+    }
+}
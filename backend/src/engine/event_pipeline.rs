@@ -0,0 +1,70 @@
+// Consumer side of the event ring.
+// The ring is SPSC, so one pipeline thread drains it and hands every event,
+// in engine order, to each registered consumer.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{Event, RingBuffer, WaitStrategy};
+
+pub const PIPELINE_BATCH: usize = 1024;
+
+pub trait EventConsumer: Send {
+    fn name(&self) -> &'static str;
+
+    fn on_event(&mut self, event: &Event);
+
+    //called after every drained batch, consumers that write in bulk hand their batch off here
+    fn end_batch(&mut self) {}
+
+    //called once on shutdown, after the engine stopped and the ring is empty
+    fn flush(&mut self) {}
+}
+
+pub struct EventPipeline {
+    ring: Arc<RingBuffer<Event>>,
+    consumers: Vec<Box<dyn EventConsumer>>,
+    idle_wait: WaitStrategy,
+}
+
+impl EventPipeline {
+    pub fn new(ring: Arc<RingBuffer<Event>>, idle_wait: WaitStrategy) -> Self {
+        Self {
+            ring,
+            consumers: Vec::new(),
+            idle_wait,
+        }
+    }
+
+    pub fn add_consumer(&mut self, consumer: Box<dyn EventConsumer>) {
+        self.consumers.push(consumer);
+    }
+
+    //runs until `stop` is set and the ring has been fully drained
+    pub fn run(&mut self, stop: &AtomicBool) {
+        loop {
+            let batch = self.ring.drain_batch(PIPELINE_BATCH);
+            if batch.is_empty() {
+                //stop is only set once the engine thread has exited, so an empty ring is final
+                if stop.load(Ordering::Acquire) && self.ring.is_empty() {
+                    break;
+                }
+                self.idle_wait.wait();
+                continue;
+            }
+            for event in batch.iter() {
+                for consumer in self.consumers.iter_mut() {
+                    consumer.on_event(event);
+                }
+            }
+            for consumer in self.consumers.iter_mut() {
+                consumer.end_batch();
+            }
+        }
+
+        for consumer in self.consumers.iter_mut() {
+            consumer.flush();
+            println!(" [PIPELINE] flushed {}", consumer.name());
+        }
+    }
+}
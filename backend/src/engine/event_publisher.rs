@@ -0,0 +1,283 @@
+// Back-pressure between the matching engine and the event ring.
+// The engine must never lose an event (a dropped Fill means a wrong position),
+// so a full ring is handled by the configured overflow policy instead of dropping.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::{BackpressureConfig, EngineState, Event, OverflowPolicy, RingBuffer};
+
+//counters shared with whoever wants to watch the engine (health checks, metrics)
+#[derive(Default)]
+pub struct EventBufferStats {
+    published: AtomicU64,
+    ring_full: AtomicU64,
+    wait_loops: AtomicU64,
+    spilled: AtomicU64,
+    spill_depth: AtomicU64,
+    state: AtomicU8,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EventBufferSnapshot {
+    pub published: u64,
+    pub ring_full: u64,
+    pub wait_loops: u64,
+    pub spilled: u64,
+    pub spill_depth: u64,
+    pub state: EngineState,
+}
+
+impl EventBufferStats {
+    pub fn state(&self) -> EngineState {
+        match self.state.load(Ordering::Acquire) {
+            0 => EngineState::Running,
+            _ => EngineState::Halted,
+        }
+    }
+
+    pub fn snapshot(&self) -> EventBufferSnapshot {
+        EventBufferSnapshot {
+            published: self.published.load(Ordering::Relaxed),
+            ring_full: self.ring_full.load(Ordering::Relaxed),
+            wait_loops: self.wait_loops.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            spill_depth: self.spill_depth.load(Ordering::Relaxed),
+            state: self.state(),
+        }
+    }
+}
+
+pub struct EventPublisher {
+    ring: Arc<RingBuffer<Event>>,
+    config: BackpressureConfig,
+    spill: VecDeque<Event>,
+    stats: Arc<EventBufferStats>,
+}
+
+impl EventPublisher {
+    pub fn new(ring: Arc<RingBuffer<Event>>, config: BackpressureConfig) -> Self {
+        Self {
+            ring,
+            config,
+            spill: VecDeque::new(),
+            stats: Arc::new(EventBufferStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<EventBufferStats> {
+        self.stats.clone()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.stats.state() == EngineState::Halted
+    }
+
+    pub fn publish(&mut self, event: Event) {
+        //spilled events go first, otherwise consumers would see them out of order
+        self.flush_spill();
+
+        let event = if self.spill.is_empty() {
+            match self.ring.try_push(event) {
+                Ok(()) => {
+                    self.stats.published.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Err(event) => event,
+            }
+        } else {
+            event
+        };
+        self.stats.ring_full.fetch_add(1, Ordering::Relaxed);
+
+        match self.config.overflow {
+            //halted means a wait already ran out, waiting again for every event cannot help
+            OverflowPolicy::Block if !self.is_halted() => self.block_on(event),
+            OverflowPolicy::Block | OverflowPolicy::Spill => self.spill_event(event),
+            OverflowPolicy::Halt => {
+                self.halt("event ring full");
+                self.spill_event(event);
+            }
+        }
+    }
+
+    //moves as much of the spill queue into the ring as fits, returns true when it is empty
+    pub fn flush_spill(&mut self) -> bool {
+        while let Some(event) = self.spill.pop_front() {
+            if let Err(event) = self.ring.try_push(event) {
+                self.spill.push_front(event);
+                break;
+            }
+            self.stats.published.fetch_add(1, Ordering::Relaxed);
+        }
+        self.stats.spill_depth.store(self.spill.len() as u64, Ordering::Relaxed);
+        self.spill.is_empty()
+    }
+
+    //used on shutdown: waits until every spilled event made it into the ring
+    pub fn flush_blocking(&mut self) {
+        while !self.flush_spill() {
+            self.config.wait.wait();
+        }
+    }
+
+    //the spill queue goes into the ring before the event does, whatever the consumers free up
+    fn block_on(&mut self, mut event: Event) {
+        let started = Instant::now();
+        loop {
+            if self.flush_spill() {
+                match self.ring.try_push(event) {
+                    Ok(()) => {
+                        self.stats.published.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Err(e) => event = e,
+                }
+            }
+            if started.elapsed() >= self.config.block_timeout {
+                //consumers are stuck: stop trading but keep the event
+                self.halt("blocked on full event ring past timeout");
+                self.spill_event(event);
+                return;
+            }
+            self.stats.wait_loops.fetch_add(1, Ordering::Relaxed);
+            self.config.wait.wait();
+        }
+    }
+
+    fn spill_event(&mut self, event: Event) {
+        self.spill.push_back(event);
+        self.stats.spilled.fetch_add(1, Ordering::Relaxed);
+        self.stats.spill_depth.store(self.spill.len() as u64, Ordering::Relaxed);
+        if self.spill.len() > self.config.max_spill {
+            self.halt("overflow queue exceeded max_spill");
+        }
+    }
+
+    fn halt(&self, reason: &str) {
+        if self.stats.state.swap(EngineState::Halted as u8, Ordering::AcqRel) != EngineState::Halted as u8 {
+            println!(" [ENGINE] FATAL: {reason}, trading halted (events kept: {})", self.spill.len() + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rust_decimal::Decimal;
+
+    use crate::{Symbol, WaitStrategy};
+
+    use super::*;
+
+    //a ring of 4 holds 3 events
+    fn publisher(overflow: OverflowPolicy, block_timeout: Duration) -> (EventPublisher, Arc<RingBuffer<Event>>) {
+        let ring = Arc::new(RingBuffer::new(4));
+        let config = BackpressureConfig { wait: WaitStrategy::Yield, overflow, max_spill: 4, block_timeout };
+        (EventPublisher::new(ring.clone(), config), ring)
+    }
+
+    fn event(n: i64) -> Event {
+        Event::MarkPrice { symbol: Symbol::new("BTC-PERP").unwrap(), price: Decimal::from(n), timestamp: 0 }
+    }
+
+    fn numbers(events: Vec<Event>) -> Vec<i64> {
+        events
+            .into_iter()
+            .map(|e| match e {
+                Event::MarkPrice { price, .. } => price.mantissa() as i64,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_waits_for_the_consumer() {
+        let (mut publisher, ring) = publisher(OverflowPolicy::Block, Duration::from_secs(5));
+        let consumer = {
+            let ring = ring.clone();
+            std::thread::spawn(move || {
+                let mut seen = Vec::new();
+                while seen.len() < 6 {
+                    std::thread::sleep(Duration::from_millis(5));
+                    seen.extend(ring.drain_batch(1));
+                }
+                numbers(seen)
+            })
+        };
+        for n in 1..=6 {
+            publisher.publish(event(n));
+        }
+        assert_eq!(consumer.join().unwrap(), [1, 2, 3, 4, 5, 6]);
+        assert!(!publisher.is_halted());
+        assert!(publisher.stats().snapshot().wait_loops > 0);
+    }
+
+    #[test]
+    fn block_halts_once_and_keeps_later_events_behind_the_spill() {
+        let timeout = Duration::from_millis(50);
+        let (mut publisher, ring) = publisher(OverflowPolicy::Block, timeout);
+        for n in 1..=4 {
+            publisher.publish(event(n));
+        }
+        assert!(publisher.is_halted());
+
+        //a slot is free, but 4 is still waiting: 5 goes behind it, and without another wait
+        ring.drain_batch(1);
+        let started = Instant::now();
+        publisher.publish(event(5));
+        publisher.publish(event(6));
+        assert!(started.elapsed() < timeout);
+
+        let mut seen = ring.drain_batch(8);
+        publisher.flush_blocking();
+        seen.extend(ring.drain_batch(8));
+        assert_eq!(numbers(seen), [2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn spill_keeps_the_order_and_halts_past_max_spill() {
+        let (mut publisher, ring) = publisher(OverflowPolicy::Spill, Duration::from_secs(5));
+        for n in 1..=6 {
+            publisher.publish(event(n));
+        }
+        assert!(!publisher.is_halted());
+        assert_eq!(publisher.stats().snapshot().spill_depth, 3);
+
+        //4..6 take the freed slots, 7 waits behind them
+        let mut seen = ring.drain_batch(8);
+        publisher.publish(event(7));
+        assert_eq!(publisher.stats().snapshot().spill_depth, 1);
+        seen.extend(ring.drain_batch(8));
+        publisher.flush_spill();
+        seen.extend(ring.drain_batch(8));
+        assert_eq!(numbers(seen), [1, 2, 3, 4, 5, 6, 7]);
+
+        for n in 8..=15 {
+            publisher.publish(event(n));
+        }
+        assert!(publisher.is_halted());
+    }
+
+    #[test]
+    fn halt_stops_on_the_first_overflow_and_keeps_the_event() {
+        let (mut publisher, ring) = publisher(OverflowPolicy::Halt, Duration::from_secs(5));
+        for n in 1..=3 {
+            publisher.publish(event(n));
+        }
+        assert!(!publisher.is_halted());
+        publisher.publish(event(4));
+        assert!(publisher.is_halted());
+
+        let mut seen = ring.drain_batch(8);
+        publisher.flush_blocking();
+        seen.extend(ring.drain_batch(8));
+        assert_eq!(numbers(seen), [1, 2, 3, 4]);
+    }
+}
+
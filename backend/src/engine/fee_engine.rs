@@ -0,0 +1,149 @@
+// Fee engine: maker/taker fees per fill, run on the matching thread right after matching.
+// The rate comes from the user's tier, picked by traded notional over the last 30 days
+// (daily buckets). A negative maker rate is a rebate. Volume is counted from the engine's
+// own fills, so the fee of a fill only depends on the command sequence before it; at startup
+// the window is refilled from the stored fills.
+
+use std::collections::{HashMap, VecDeque};
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{Fill, LIQUIDATION_ACCOUNT, SETTLEMENT_ASSET, UserId};
+
+//collects every fee paid and pays every rebate
+pub const FEE_ACCOUNT: UserId = Uuid::from_u128(2);
+
+pub const VOLUME_WINDOW_DAYS: u64 = 30;
+pub const NANOS_PER_DAY: u128 = 86_400 * 1_000_000_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeTier {
+    pub name: &'static str,
+    pub min_volume: Decimal,  //30 day notional needed to reach the tier
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeSchedule {
+    pub asset: &'static str,
+    pub tiers: Vec<FeeTier>,  //ascending min_volume, the first one starts at 0
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        let tier = |name, min_volume, maker_rate, taker_rate| FeeTier { name, min_volume, maker_rate, taker_rate };
+        Self {
+            asset: SETTLEMENT_ASSET,
+            tiers: vec![
+                tier("vip0", dec!(0), dec!(0.0002), dec!(0.0005)),
+                tier("vip1", dec!(1_000_000), dec!(0.00016), dec!(0.0004)),
+                tier("vip2", dec!(5_000_000), dec!(0.0001), dec!(0.00035)),
+                tier("vip3", dec!(25_000_000), dec!(0), dec!(0.0003)),
+                tier("vip4", dec!(100_000_000), dec!(-0.0001), dec!(0.00025)),
+            ],
+        }
+    }
+}
+
+impl FeeSchedule {
+    pub fn tier(&self, volume: Decimal) -> Option<&FeeTier> {
+        self.tiers.iter().rev().find(|t| volume >= t.min_volume)
+    }
+}
+
+#[derive(Default)]
+struct Volume {
+    days: VecDeque<(u64, Decimal)>,  //(day number, notional), oldest first
+    total: Decimal,
+}
+
+impl Volume {
+    fn expire(&mut self, today: u64) {
+        while let Some(&(day, notional)) = self.days.front() {
+            if day + VOLUME_WINDOW_DAYS > today {
+                break;
+            }
+            self.total -= notional;
+            self.days.pop_front();
+        }
+    }
+
+    fn add(&mut self, today: u64, notional: Decimal) {
+        self.expire(today);
+        match self.days.back_mut() {
+            Some((day, sum)) if *day == today => *sum += notional,
+            _ => self.days.push_back((today, notional)),
+        }
+        self.total += notional;
+    }
+}
+
+pub struct FeeEngine {
+    schedule: FeeSchedule,
+    volumes: HashMap<UserId, Volume>,
+}
+
+impl FeeEngine {
+    pub fn new(schedule: FeeSchedule) -> Self {
+        Self { schedule, volumes: HashMap::new() }
+    }
+
+    //(user, day, notional) with days in ascending order
+    pub fn restore(&mut self, volumes: impl IntoIterator<Item = (UserId, u64, Decimal)>) {
+        for (user_id, day, notional) in volumes {
+            self.volumes.entry(user_id).or_default().add(day, notional);
+        }
+    }
+
+    pub fn volume(&mut self, user_id: &UserId, timestamp: u128) -> Decimal {
+        let today = (timestamp / NANOS_PER_DAY) as u64;
+        self.volumes.get_mut(user_id).map(|v| { v.expire(today); v.total }).unwrap_or_default()
+    }
+
+    //(maker rate, taker rate); the liquidation account trades for the insurance fund and pays nothing
+    pub fn rates(&mut self, user_id: &UserId, timestamp: u128) -> (Decimal, Decimal) {
+        if *user_id == LIQUIDATION_ACCOUNT {
+            return (Decimal::ZERO, Decimal::ZERO);
+        }
+        let volume = self.volume(user_id, timestamp);
+        self.schedule.tier(volume).map(|t| (t.maker_rate, t.taker_rate)).unwrap_or_default()
+    }
+
+    //sets the fees on the fill, then counts it towards both users' volume
+    pub fn charge(&mut self, fill: &mut Fill) {
+        let notional = fill.quantity * fill.price;
+        let (maker_rate, _) = self.rates(&fill.maker_user_id, fill.timestamp_);
+        let (_, taker_rate) = self.rates(&fill.taker_user_id, fill.timestamp_);
+        fill.maker_fee = (notional * maker_rate).round_dp(8).normalize();
+        fill.taker_fee = (notional * taker_rate).round_dp(8).normalize();
+        fill.fee_asset = Some(self.schedule.asset);
+
+        let today = (fill.timestamp_ / NANOS_PER_DAY) as u64;
+        for user_id in [fill.maker_user_id, fill.taker_user_id] {
+            self.volumes.entry(user_id).or_default().add(today, notional);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restored_volume_sets_the_tier_until_it_leaves_the_window() {
+        let user = Uuid::from_u128(7);
+        let mut fees = FeeEngine::new(FeeSchedule::default());
+        fees.restore([(user, 100, dec!(600_000)), (user, 110, dec!(500_000))]);
+
+        let day = |d: u128| d * NANOS_PER_DAY;
+        assert_eq!(fees.volume(&user, day(110)), dec!(1_100_000));
+        assert_eq!(fees.rates(&user, day(110)), (dec!(0.00016), dec!(0.0004)));
+        //day 100 drops out once 30 days have passed
+        assert_eq!(fees.volume(&user, day(130)), dec!(500_000));
+        assert_eq!(fees.rates(&user, day(130)), (dec!(0.0002), dec!(0.0005)));
+    }
+}
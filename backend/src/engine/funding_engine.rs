@@ -0,0 +1,269 @@
+// Funding for one perpetual.
+//  premium  = (max(0, impact bid - index) - max(0, index - impact ask)) / index, sampled during the interval
+//  rate     = avg premium + clamp(interest - avg premium, ±interest_clamp), then clamped to ±max_rate
+//  payment  = -size * mark * rate: longs pay shorts when the rate is positive
+// Funding times are aligned to the interval (00:00 / 08:00 / 16:00 UTC for 8h).
+// Runs as a tokio task that samples and stores the rate. At a funding time it sends
+// OrderBookMessage::SettleFunding to the engine, which emits one Funding event per open position;
+// the wallet and the ledger follow from the event stream like for fills.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use db::{Db, FundingRate};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::{FundingState, OrderBookMessage, Price, Quantity, RingSender, SharedMarketPrices, Symbol, TrySendError, now_millis};
+
+pub type SharedFunding = Arc<RwLock<FundingState>>;
+
+pub struct FundingConfig {
+    pub symbol: Symbol,
+    pub interval: Duration,
+    pub sample_interval: Duration,
+    pub interest_rate: Decimal,   //per interval
+    pub interest_clamp: Decimal,
+    pub max_rate: Decimal,        //per instrument cap on |rate|
+}
+
+impl FundingConfig {
+    pub fn from_env(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            interval: Duration::from_secs(env_or("FUNDING_INTERVAL_SECS", 8 * 3600)),
+            sample_interval: Duration::from_secs(env_or("FUNDING_SAMPLE_SECS", 60)),
+            interest_rate: env_or("FUNDING_INTEREST_RATE", dec!(0.0001)),
+            interest_clamp: dec!(0.0005),
+            max_rate: env_or("FUNDING_MAX_RATE", dec!(0.0075)),
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+//what a position of signed `size` receives (> 0) or pays (< 0)
+pub fn funding_payment(size: Quantity, mark: Price, rate: Decimal) -> Decimal {
+    (-size * mark * rate).round_dp(8).normalize()
+}
+
+pub struct FundingEngine {
+    config: FundingConfig,
+    prices: SharedMarketPrices,
+    state: SharedFunding,
+    premium_sum: Decimal,
+    samples: u32,
+}
+
+impl FundingEngine {
+    pub fn new(config: FundingConfig, prices: SharedMarketPrices) -> Self {
+        let interval_ms = config.interval.as_millis() as u64;
+        let state = FundingState {
+            symbol: config.symbol,
+            predicted_rate: None,
+            premium_index: None,
+            samples: 0,
+            interval_secs: config.interval.as_secs(),
+            next_funding_time: (now_millis() / interval_ms + 1) * interval_ms,
+            last_rate: None,
+            last_funding_time: None,
+        };
+        Self {
+            config,
+            prices,
+            state: Arc::new(RwLock::new(state)),
+            premium_sum: Decimal::ZERO,
+            samples: 0,
+        }
+    }
+
+    pub fn state(&self) -> SharedFunding {
+        self.state.clone()
+    }
+
+    pub fn funding_rate(&self, premium: Decimal) -> Decimal {
+        let clamp = self.config.interest_clamp;
+        let rate = premium + (self.config.interest_rate - premium).clamp(-clamp, clamp);
+        rate.clamp(-self.config.max_rate, self.config.max_rate).round_dp(8)
+    }
+
+    //a thin book or a missing index skips the sample, it does not count as zero premium
+    pub fn sample(&mut self) {
+        let premium = {
+            let prices = self.prices.read().unwrap_or_else(|e| e.into_inner());
+            match (prices.index_price, prices.book.impact_bid, prices.book.impact_ask) {
+                (Some(index), Some(bid), Some(ask)) if index > Decimal::ZERO => {
+                    let premium = (bid - index).max(Decimal::ZERO) - (index - ask).max(Decimal::ZERO);
+                    Some(premium / index)
+                }
+                _ => None,
+            }
+        };
+        let Some(premium) = premium else { return };
+        self.premium_sum += premium;
+        self.samples += 1;
+
+        let average = (self.premium_sum / Decimal::from(self.samples)).round_dp(8);
+        let predicted = self.funding_rate(average);
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.premium_index = Some(average);
+        state.predicted_rate = Some(predicted);
+        state.samples = self.samples;
+    }
+
+    pub fn next_funding_time(&self) -> u64 {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).next_funding_time
+    }
+
+    //closes the interval ending at `funding_time`, returns the rate to store and settle
+    pub fn settle(&mut self, funding_time: u64) -> Option<FundingRate> {
+        let samples = std::mem::take(&mut self.samples);
+        let premium_sum = std::mem::take(&mut self.premium_sum);
+        let interval_ms = self.config.interval.as_millis() as u64;
+
+        let (index, mark) = {
+            let prices = self.prices.read().unwrap_or_else(|e| e.into_inner());
+            (prices.index_price, prices.mark_price.or(prices.index_price))
+        };
+        let settled = match (samples, index, mark) {
+            (1.., Some(index), Some(mark)) => {
+                let premium = (premium_sum / Decimal::from(samples)).round_dp(8);
+                let rate = self.funding_rate(premium);
+                Some((premium, rate, index, mark))
+            }
+            _ => None,
+        };
+
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.next_funding_time = funding_time + interval_ms;
+        state.premium_index = None;
+        state.predicted_rate = None;
+        state.samples = 0;
+        let Some((premium, rate, index, mark)) = settled else {
+            println!(" [FUNDING] {}: no premium samples or prices, funding at {funding_time} skipped", self.config.symbol);
+            return None;
+        };
+        state.last_rate = Some(rate);
+        state.last_funding_time = Some(funding_time);
+        drop(state);
+        println!(" [FUNDING] {}: rate {rate} at mark {mark}", self.config.symbol);
+
+        let record = FundingRate {
+            symbol: self.config.symbol.to_string(),
+            funding_time: DateTime::<Utc>::from_timestamp_millis(funding_time as i64).unwrap_or_default(),
+            rate,
+            premium_index: premium,
+            index_price: index,
+            mark_price: mark,
+        };
+        Some(record)
+    }
+}
+
+//the rate is stored first; the settlement is a command like any other, retried while the ring is full
+pub fn spawn_funding(mut engine: FundingEngine, db: Db, book_tx: RingSender<OrderBookMessage>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(engine.config.sample_interval);
+        loop {
+            ticker.tick().await;
+            engine.sample();
+
+            let funding_time = engine.next_funding_time();
+            if now_millis() < funding_time {
+                continue;
+            }
+            let Some(rate) = engine.settle(funding_time) else { continue };
+            if let Err(e) = db.record_funding(&rate).await {
+                println!(" [FUNDING] failed to store the rate at {funding_time}: {e}");
+            }
+            let mut msg = OrderBookMessage::SettleFunding {
+                symbol: engine.config.symbol,
+                funding_time,
+                rate: rate.rate,
+                mark_price: rate.mark_price,
+            };
+            loop {
+                match book_tx.try_send(msg) {
+                    Ok(()) => break,
+                    Err(TrySendError::Full(back)) => {
+                        msg = back;
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                    Err(TrySendError::Closed(_)) => return,
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MarketPrices;
+
+    use super::*;
+
+    fn engine() -> FundingEngine {
+        let symbol = Symbol::new("BTC-PERP").unwrap();
+        let config = FundingConfig {
+            symbol,
+            interval: Duration::from_secs(8 * 3600),
+            sample_interval: Duration::from_secs(60),
+            interest_rate: dec!(0.0001),
+            interest_clamp: dec!(0.0005),
+            max_rate: dec!(0.0075),
+        };
+        FundingEngine::new(config, Arc::new(RwLock::new(MarketPrices::new(symbol))))
+    }
+
+    #[test]
+    fn rate_is_pulled_to_interest_within_the_clamp() {
+        let engine = engine();
+        assert_eq!(engine.funding_rate(dec!(0)), dec!(0.0001));
+        assert_eq!(engine.funding_rate(dec!(0.0003)), dec!(0.0001));
+        assert_eq!(engine.funding_rate(dec!(-0.0004)), dec!(0.0001));
+        //further out the clamp only moves it by 0.05%
+        assert_eq!(engine.funding_rate(dec!(0.002)), dec!(0.0015));
+        assert_eq!(engine.funding_rate(dec!(-0.002)), dec!(-0.0015));
+    }
+
+    #[test]
+    fn rate_is_capped_at_max_rate() {
+        let engine = engine();
+        assert_eq!(engine.funding_rate(dec!(0.02)), dec!(0.0075));
+        assert_eq!(engine.funding_rate(dec!(-0.02)), dec!(-0.0075));
+    }
+
+    #[test]
+    fn longs_pay_shorts_on_a_positive_rate() {
+        assert_eq!(funding_payment(dec!(2), dec!(100), dec!(0.0001)), dec!(-0.02));
+        assert_eq!(funding_payment(dec!(-2), dec!(100), dec!(0.0001)), dec!(0.02));
+        assert_eq!(funding_payment(dec!(2), dec!(100), dec!(-0.0001)), dec!(0.02));
+    }
+
+    #[test]
+    fn settle_averages_the_samples_and_starts_a_new_interval() {
+        let mut engine = engine();
+        {
+            let mut prices = engine.prices.write().unwrap();
+            prices.index_price = Some(dec!(100));
+            prices.mark_price = Some(dec!(100.1));
+            prices.book.impact_bid = Some(dec!(100.2));
+            prices.book.impact_ask = Some(dec!(100.4));
+        }
+        engine.sample();
+        engine.prices.write().unwrap().book.impact_bid = Some(dec!(100.4));
+        engine.sample();
+
+        let funding_time = engine.next_funding_time();
+        let rate = engine.settle(funding_time).expect("two samples");
+        //premiums 0.002 and 0.004 average to 0.003, clamped down by 0.0005
+        assert_eq!(rate.premium_index, dec!(0.003));
+        assert_eq!(rate.rate, dec!(0.0025));
+        assert_eq!(rate.mark_price, dec!(100.1));
+        assert_eq!(engine.next_funding_time(), funding_time + 8 * 3600 * 1000);
+        assert!(engine.settle(engine.next_funding_time()).is_none(), "no samples in the new interval");
+    }
+}
@@ -0,0 +1,87 @@
+// Read side of the insurance fund. The fund itself is the liquidation account's wallet balance
+// (surpluses and deficits settle into it as that account's realized PnL); this keeps the history
+// of the entries the engine emits, for the API. The balance is as of the last event batch the
+// pipeline consumed, the engine may have emitted entries since.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{Event, EventConsumer, InsuranceFundEntry};
+
+const LEDGER_LEN: usize = 1000;
+
+#[derive(Clone, Default, Serialize)]
+pub struct InsuranceFund {
+    pub balance: Decimal,  //filled in from the liquidation account when served
+    pub total_surplus: Decimal,
+    pub total_deficit: Decimal,
+    pub entries: VecDeque<InsuranceFundEntry>,  //newest first, last LEDGER_LEN only
+}
+
+pub type SharedInsuranceFund = Arc<RwLock<InsuranceFund>>;
+
+impl InsuranceFund {
+    pub fn record(&mut self, entry: InsuranceFundEntry) {
+        if entry.amount > Decimal::ZERO {
+            self.total_surplus += entry.amount;
+        } else {
+            self.total_deficit -= entry.amount;
+        }
+        self.entries.push_front(entry);
+        self.entries.truncate(LEDGER_LEN);
+    }
+}
+
+pub struct InsuranceFundConsumer {
+    fund: SharedInsuranceFund,
+}
+
+impl InsuranceFundConsumer {
+    pub fn new(fund: SharedInsuranceFund) -> Self {
+        Self { fund }
+    }
+}
+
+impl EventConsumer for InsuranceFundConsumer {
+    fn name(&self) -> &'static str {
+        "insurance-fund"
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        if let Event::InsuranceFund(entry) = event {
+            self.fund.write().unwrap_or_else(|e| e.into_inner()).record(*entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::Symbol;
+
+    fn entry(amount: Decimal) -> InsuranceFundEntry {
+        InsuranceFundEntry { liquidation_id: Uuid::nil(), symbol: Symbol::new("BTC-PERP").unwrap(), amount, timestamp: 0 }
+    }
+
+    #[test]
+    fn surpluses_and_deficits_are_totalled_newest_first() {
+        let mut fund = InsuranceFund::default();
+        fund.record(entry(dec!(2)));
+        fund.record(entry(dec!(-1.5)));
+        fund.record(entry(dec!(0.5)));
+        assert_eq!((fund.total_surplus, fund.total_deficit), (dec!(2.5), dec!(1.5)));
+        assert_eq!(fund.entries.iter().map(|e| e.amount).collect::<Vec<_>>(), [dec!(0.5), dec!(-1.5), dec!(2)]);
+
+        for _ in 0..LEDGER_LEN {
+            fund.record(entry(dec!(1)));
+        }
+        assert_eq!(fund.entries.len(), LEDGER_LEN);
+        assert_eq!(fund.total_surplus, dec!(1002.5));
+    }
+}
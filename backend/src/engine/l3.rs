@@ -0,0 +1,164 @@
+// L3 feed: every resting order, in queue order, derived from the engine's events.
+// The consumer keeps a mirror of the book's PriceLevel queues and turns placements, fills and
+// cancels into add / execute / delete messages with anonymised order ids. The last
+// L3_JOURNAL_LEN messages are kept, so a client recovers with a snapshot (which carries the seq
+// it includes) and replays the messages after it.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use crate::{AnonOrderId, Event, EventConsumer, L3Event, L3Level, L3Message, L3Order, L3Snapshot, OrderId, Price, Quantity, Side, Symbol};
+
+pub const L3_JOURNAL_LEN: usize = 100_000;
+
+struct RestingOrder {
+    id: AnonOrderId,
+    side: Side,
+    price: Price,
+    remaining: Quantity,
+}
+
+pub struct L3Book {
+    symbol: Symbol,
+    bids: BTreeMap<Price, VecDeque<OrderId>>,
+    asks: BTreeMap<Price, VecDeque<OrderId>>,
+    orders: HashMap<OrderId, RestingOrder>,
+    next_id: AnonOrderId,
+    seq: u64,
+    journal: VecDeque<L3Event>,
+}
+
+pub type SharedL3 = Arc<RwLock<L3Book>>;
+
+impl L3Book {
+    pub fn new(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: HashMap::new(),
+            next_id: 0,
+            seq: 0,
+            journal: VecDeque::new(),
+        }
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        self.symbol
+    }
+
+    fn side(&mut self, side: Side) -> &mut BTreeMap<Price, VecDeque<OrderId>> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn record(&mut self, message: L3Message, timestamp: u128) {
+        self.seq += 1;
+        if self.journal.len() == L3_JOURNAL_LEN {
+            self.journal.pop_front();
+        }
+        self.journal.push_back(L3Event { seq: self.seq, timestamp, message });
+    }
+
+    fn unlink(&mut self, order_id: &OrderId, side: Side, price: Price) {
+        let levels = self.side(side);
+        if let Some(queue) = levels.get_mut(&price) {
+            queue.retain(|id| id != order_id);
+            if queue.is_empty() {
+                levels.remove(&price);
+            }
+        }
+    }
+
+    pub fn add(&mut self, order_id: OrderId, side: Side, price: Price, quantity: Quantity, timestamp: u128) {
+        self.next_id += 1;
+        let id = self.next_id;
+        let queue = self.side(side).entry(price).or_default();
+        let queue_position = queue.len();
+        queue.push_back(order_id);
+        self.orders.insert(order_id, RestingOrder { id, side, price, remaining: quantity });
+        let (price, quantity) = (price.normalize(), quantity.normalize());
+        self.record(L3Message::Add { order: id, side, price, quantity, queue_position }, timestamp);
+    }
+
+    //fills of orders that never rested (takers) are not part of the feed
+    pub fn execute(&mut self, order_id: OrderId, quantity: Quantity, timestamp: u128) {
+        let Some(order) = self.orders.get_mut(&order_id) else { return };
+        order.remaining -= quantity;
+        let (id, side, price, remaining) = (order.id, order.side, order.price, order.remaining);
+        if remaining <= Quantity::ZERO {
+            self.orders.remove(&order_id);
+            self.unlink(&order_id, side, price);
+        }
+        let message = L3Message::Execute {
+            order: id,
+            side,
+            price: price.normalize(),
+            quantity: quantity.normalize(),
+            remaining: remaining.max(Quantity::ZERO).normalize(),
+        };
+        self.record(message, timestamp);
+    }
+
+    pub fn delete(&mut self, order_id: OrderId, timestamp: u128) {
+        let Some(order) = self.orders.remove(&order_id) else { return };
+        self.unlink(&order_id, order.side, order.price);
+        self.record(L3Message::Delete { order: order.id, side: order.side, price: order.price.normalize() }, timestamp);
+    }
+
+    pub fn snapshot(&self) -> L3Snapshot {
+        let level = |(price, queue): (&Price, &VecDeque<OrderId>)| L3Level {
+            price: price.normalize(),
+            orders: queue
+                .iter()
+                .filter_map(|id| self.orders.get(id))
+                .map(|o| L3Order { order: o.id, quantity: o.remaining.normalize() })
+                .collect(),
+        };
+        L3Snapshot {
+            symbol: self.symbol,
+            seq: self.seq,
+            bids: self.bids.iter().rev().map(level).collect(),
+            asks: self.asks.iter().map(level).collect(),
+        }
+    }
+
+    //messages with seq > `after`, None when some of them are no longer kept
+    pub fn replay(&self, after: u64, limit: usize) -> Option<Vec<L3Event>> {
+        let first = self.journal.front().map(|e| e.seq).unwrap_or(self.seq + 1);
+        let next = after.saturating_add(1);
+        if next < first {
+            return None;
+        }
+        let skip = usize::try_from(next - first).unwrap_or(usize::MAX);
+        Some(self.journal.iter().skip(skip).take(limit).copied().collect())
+    }
+}
+
+pub struct L3Consumer {
+    book: SharedL3,
+}
+
+impl L3Consumer {
+    pub fn new(book: SharedL3) -> Self {
+        Self { book }
+    }
+}
+
+impl EventConsumer for L3Consumer {
+    fn name(&self) -> &'static str {
+        "l3"
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        let book = || self.book.write().unwrap_or_else(|e| e.into_inner());
+        match event {
+            Event::OrderPlaced { order_id, side, price, quantity, timestamp, .. } => book().add(*order_id, *side, *price, *quantity, *timestamp),
+            Event::Fill(fill) => book().execute(fill.maker_order_id, fill.quantity, fill.timestamp_),
+            Event::OrderCancelled { order_id, timestamp, .. } => book().delete(*order_id, *timestamp),
+            _ => {}
+        }
+    }
+}
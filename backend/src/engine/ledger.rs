@@ -0,0 +1,43 @@
+// Ledger postings for what the position consumer does to the wallet: fees and realized PnL of
+// every fill, the PnL of liquidation takeovers and ADL, and funding. Entries are referenced by the
+// fill (maker and taker order id, a pair that trades at most once), by the liquidation or by the
+// funding time, so posting one twice is a no-op. A writer task posts one batch per pipeline batch, in one transaction.
+
+use std::time::Duration;
+
+use db::{Db, JournalEntry, fee_entry, realized_pnl_entry};
+use tokio::sync::mpsc;
+
+use crate::{Fill, PositionChange, SETTLEMENT_ASSET};
+
+pub fn fill_entries(fill: &Fill, changes: &[PositionChange; 2], entries: &mut Vec<JournalEntry>) {
+    let fill_id = format!("{}:{}", fill.maker_order_id, fill.taker_order_id);
+    let sides = [("maker", fill.maker_fee, &changes[0]), ("taker", fill.taker_fee, &changes[1])];
+    for (liquidity, fee, change) in sides {
+        if !fee.is_zero() {
+            entries.push(fee_entry(&format!("fee:{fill_id}:{liquidity}"), change.user_id, SETTLEMENT_ASSET, fee));
+        }
+        pnl_entry(&format!("fill:{fill_id}:{liquidity}"), change, entries);
+    }
+}
+
+pub fn pnl_entry(reference: &str, change: &PositionChange, entries: &mut Vec<JournalEntry>) {
+    if !change.realized_pnl.is_zero() {
+        entries.push(realized_pnl_entry(&format!("pnl:{reference}"), change.user_id, SETTLEMENT_ASSET, change.realized_pnl));
+    }
+}
+
+//posts batches in order, a failed batch is retried and later ones wait behind it
+pub fn spawn_ledger_writer(db: Db, mut rx: mpsc::Receiver<Vec<JournalEntry>>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(entries) = rx.recv().await {
+            let mut backoff = Duration::from_millis(100);
+            while let Err(e) = db.post_entries(&entries).await {
+                println!(" [LEDGER] batch of {} entries failed, retrying in {backoff:?}: {e}", entries.len());
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(5));
+            }
+        }
+        println!(" [LEDGER] writer stopped");
+    })
+}
@@ -0,0 +1,134 @@
+// Leverage brackets: the larger the position notional, the lower the allowed leverage and the
+// higher the maintenance margin rate. Maintenance margin = notional * rate - maintenance amount,
+// the amount keeps it continuous across bracket boundaries.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+
+use crate::Symbol;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeverageBracket {
+    pub bracket: u32,                     //1 = smallest positions
+    pub notional_floor: Decimal,
+    pub notional_cap: Option<Decimal>,    //exclusive, None for the last bracket
+    pub max_leverage: Decimal,
+    pub maintenance_margin_rate: Decimal,
+    pub maintenance_amount: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BracketTable {
+    pub brackets: Vec<LeverageBracket>,
+}
+
+impl BracketTable {
+    //(notional cap, max leverage, maintenance margin rate) per bracket, ascending, the last cap is ignored
+    pub fn from_limits(limits: &[(Decimal, Decimal, Decimal)]) -> Self {
+        let mut brackets: Vec<LeverageBracket> = Vec::with_capacity(limits.len());
+        for (i, &(cap, max_leverage, rate)) in limits.iter().enumerate() {
+            let (floor, amount) = match brackets.last() {
+                Some(prev) => {
+                    let floor = prev.notional_cap.unwrap_or_default();
+                    (floor, (prev.maintenance_amount + floor * (rate - prev.maintenance_margin_rate)).normalize())
+                }
+                None => (Decimal::ZERO, Decimal::ZERO),
+            };
+            brackets.push(LeverageBracket {
+                bracket: i as u32 + 1,
+                notional_floor: floor,
+                notional_cap: (i + 1 < limits.len()).then_some(cap),
+                max_leverage,
+                maintenance_margin_rate: rate,
+                maintenance_amount: amount,
+            });
+        }
+        Self { brackets }
+    }
+
+    pub fn bracket(&self, notional: Decimal) -> &LeverageBracket {
+        self.brackets
+            .iter()
+            .find(|b| b.notional_cap.is_none_or(|cap| notional < cap))
+            .or(self.brackets.last())
+            .expect("bracket table is never empty")
+    }
+
+    pub fn maintenance_margin(&self, notional: Decimal) -> Decimal {
+        let bracket = self.bracket(notional);
+        (notional * bracket.maintenance_margin_rate - bracket.maintenance_amount).max(Decimal::ZERO).normalize()
+    }
+}
+
+impl Default for BracketTable {
+    fn default() -> Self {
+        Self::from_limits(&[
+            (dec!(50_000), dec!(125), dec!(0.004)),
+            (dec!(250_000), dec!(100), dec!(0.005)),
+            (dec!(1_000_000), dec!(50), dec!(0.01)),
+            (dec!(10_000_000), dec!(20), dec!(0.025)),
+            (dec!(20_000_000), dec!(10), dec!(0.05)),
+            (dec!(50_000_000), dec!(5), dec!(0.1)),
+            (dec!(100_000_000), dec!(4), dec!(0.125)),
+            (dec!(200_000_000), dec!(3), dec!(0.15)),
+            (dec!(300_000_000), dec!(2), dec!(0.25)),
+            (Decimal::MAX, dec!(1), dec!(0.5)),
+        ])
+    }
+}
+
+//per instrument, instruments without their own table use the default one
+#[derive(Debug, Clone, Default)]
+pub struct LeverageBrackets {
+    pub default: BracketTable,
+    pub instruments: HashMap<Symbol, BracketTable>,
+}
+
+impl LeverageBrackets {
+    pub fn table(&self, symbol: &Symbol) -> &BracketTable {
+        self.instruments.get(symbol).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_margin_is_continuous_at_boundaries() {
+        let table = BracketTable::default();
+        for pair in table.brackets.windows(2) {
+            let (prev, next) = (&pair[0], &pair[1]);
+            let cap = prev.notional_cap.expect("only the last bracket is open");
+            assert_eq!(next.notional_floor, cap);
+            assert_eq!(
+                cap * prev.maintenance_margin_rate - prev.maintenance_amount,
+                cap * next.maintenance_margin_rate - next.maintenance_amount,
+                "jump at {cap}"
+            );
+        }
+        assert_eq!(table.brackets[0].maintenance_amount, Decimal::ZERO);
+        assert!(table.brackets.last().unwrap().notional_cap.is_none());
+    }
+
+    #[test]
+    fn picks_bracket_by_notional() {
+        let table = BracketTable::from_limits(&[
+            (dec!(1_000), dec!(100), dec!(0.01)),
+            (dec!(5_000), dec!(50), dec!(0.02)),
+            (dec!(0), dec!(10), dec!(0.05)),
+        ]);
+        assert_eq!(table.bracket(dec!(999.99)).bracket, 1);
+        assert_eq!(table.bracket(dec!(1_000)).bracket, 2);
+        assert_eq!(table.bracket(dec!(5_000)).bracket, 3);
+        assert_eq!(table.bracket(Decimal::MAX).bracket, 3);
+        //amounts: 1000 * (0.02 - 0.01) = 10, then 10 + 5000 * (0.05 - 0.02) = 160
+        assert_eq!(table.brackets[1].maintenance_amount, dec!(10));
+        assert_eq!(table.brackets[2].maintenance_amount, dec!(160));
+        assert_eq!(table.maintenance_margin(dec!(500)), dec!(5));
+        assert_eq!(table.maintenance_margin(dec!(5_000)), dec!(90));
+    }
+}
@@ -0,0 +1,227 @@
+// Liquidation engine: on every mark price update checks each account holding a position.
+//  equity      = unrealized PnL at mark + the balance not locked in isolated positions (cross)
+//                                       + the position's own margin (isolated)
+//  maintenance = from the leverage bracket of |size| * mark
+//  margin ratio = maintenance / equity, liquidation once it reaches 1
+// Under-margined accounts are sent to the engine as OrderBookMessage::Liquidate at Critical priority
+// with the bankruptcy price (where equity would be zero). The engine does the takeover and the
+// closing order, so positions and wallets only ever change through the event stream.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{BracketTable, MarginMode, OrderBookMessage, Price, RingSender, SharedPositions, SharedWallet, Symbol, TrySendError, UserId, now_nanos};
+
+//takes over liquidated positions and closes them in the market, never trades through the API
+pub const LIQUIDATION_ACCOUNT: UserId = Uuid::from_u128(1);
+
+pub struct LiquidationConfig {
+    pub symbol: Symbol,
+    pub brackets: BracketTable,
+    pub retry_after: Duration,  //a request the engine has not acted on by then is sent again
+}
+
+impl LiquidationConfig {
+    pub fn new(symbol: Symbol, brackets: BracketTable) -> Self {
+        Self {
+            symbol,
+            brackets,
+            retry_after: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AccountHealth {
+    pub user_id: UserId,
+    pub mode: MarginMode,
+    pub size: Decimal,
+    pub equity: Decimal,
+    pub maintenance_margin: Decimal,
+}
+
+impl AccountHealth {
+    pub fn margin_ratio(&self) -> Decimal {
+        if self.equity <= Decimal::ZERO {
+            return Decimal::MAX;
+        }
+        self.maintenance_margin / self.equity
+    }
+
+    //price at which equity hits zero, everything else unchanged
+    pub fn bankruptcy_price(&self, mark: Price) -> Price {
+        (mark - self.equity / self.size).max(Decimal::ZERO).round_dp(8)
+    }
+}
+
+pub struct LiquidationEngine {
+    config: LiquidationConfig,
+    positions: SharedPositions,
+    wallet: SharedWallet,
+    book_tx: RingSender<OrderBookMessage>,
+    in_flight: HashMap<UserId, u128>,
+}
+
+impl LiquidationEngine {
+    pub fn new(config: LiquidationConfig, positions: SharedPositions, wallet: SharedWallet, book_tx: RingSender<OrderBookMessage>) -> Self {
+        Self { config, positions, wallet, book_tx, in_flight: HashMap::new() }
+    }
+
+    pub fn health(&self, mark: Price) -> Vec<AccountHealth> {
+        let open: Vec<_> = {
+            let positions = self.positions.read().unwrap_or_else(|e| e.into_inner());
+            positions
+                .open_positions()
+                .filter(|p| p.symbol == self.config.symbol && p.user_id != LIQUIDATION_ACCOUNT)
+                .map(|p| (p.user_id, p.size, p.unrealized_pnl(mark), p.notional(mark)))
+                .collect()
+        };
+        let wallet = self.wallet.lock().unwrap_or_else(|e| e.into_inner());
+        let symbol = self.config.symbol;
+        open.into_iter()
+            .map(|(user_id, size, upnl, notional)| {
+                let account = wallet.account(&user_id);
+                let mode = account.map(|a| a.margin_mode(&symbol)).unwrap_or_default();
+                let collateral = match mode {
+                    MarginMode::Cross => account.map(|a| a.cross_collateral()),
+                    MarginMode::Isolated => account.map(|a| a.position_margin(&symbol)),
+                };
+                AccountHealth {
+                    user_id,
+                    mode,
+                    size,
+                    equity: collateral.unwrap_or_default() + upnl,
+                    maintenance_margin: self.config.brackets.maintenance_margin(notional),
+                }
+            })
+            .collect()
+    }
+
+    //returns how many liquidations were submitted
+    pub fn evaluate(&mut self, mark: Price) -> usize {
+        let now = now_nanos();
+        let retry_after = self.config.retry_after.as_nanos();
+        let mut submitted = 0;
+
+        for account in self.health(mark) {
+            if account.margin_ratio() < Decimal::ONE {
+                self.in_flight.remove(&account.user_id);
+                continue;
+            }
+            if self.in_flight.get(&account.user_id).is_some_and(|&sent| now - sent < retry_after) {
+                continue;
+            }
+
+            let bankruptcy_price = account.bankruptcy_price(mark);
+            println!(
+                " [LIQUIDATION] {} ({:?}) margin ratio {} (equity {}, maintenance {}), bankruptcy price {bankruptcy_price}",
+                account.user_id,
+                account.mode,
+                account.margin_ratio().round_dp(4),
+                account.equity.round_dp(8),
+                account.maintenance_margin.round_dp(8)
+            );
+            let msg = OrderBookMessage::Liquidate {
+                user_id: account.user_id,
+                symbol: self.config.symbol,
+                mark_price: mark,
+                bankruptcy_price,
+            };
+            match self.book_tx.try_send(msg) {
+                Ok(()) => {
+                    self.in_flight.insert(account.user_id, now);
+                    submitted += 1;
+                }
+                //retried on the next mark price
+                Err(TrySendError::Full(_)) => println!(" [LIQUIDATION] command ring full, {} retried later", account.user_id),
+                Err(TrySendError::Closed(_)) => break,
+            }
+        }
+        submitted
+    }
+}
+
+pub fn spawn_liquidation(mut engine: LiquidationEngine, mut marks: watch::Receiver<Option<Price>>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while marks.changed().await.is_ok() {
+            let mark = *marks.borrow_and_update();
+            if let Some(mark) = mark {
+                engine.evaluate(mark);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{PositionEngine, PositionTrade, RingReceiver, Side, WalletEngine, mpsc_ring};
+
+    fn symbol() -> Symbol {
+        Symbol::new("BTC-PERP").unwrap()
+    }
+
+    //one bracket, maintenance margin 5% of the notional
+    fn liquidation(longs: &[(UserId, MarginMode, Decimal)]) -> (LiquidationEngine, RingReceiver<OrderBookMessage>, SharedWallet) {
+        let mut positions = PositionEngine::new();
+        let wallet: SharedWallet = Arc::new(Mutex::new(WalletEngine::new()));
+        for &(user_id, mode, balance) in longs {
+            let mut wallet = wallet.lock().unwrap();
+            wallet.credit(user_id, balance);
+            wallet.restore_margin_modes(&[(user_id, symbol(), mode)]);
+            //1 @ 100 at 10x, 10 of position margin
+            let trade = PositionTrade { user_id, symbol: symbol(), side: Side::Buy, quantity: dec!(1), price: dec!(100), leverage: dec!(10), timestamp: 0 };
+            wallet.apply_position_change(&positions.apply(trade), dec!(100), dec!(10));
+        }
+        let config = LiquidationConfig::new(symbol(), BracketTable::from_limits(&[(Decimal::MAX, dec!(10), dec!(0.05))]));
+        let (tx, rx) = mpsc_ring(16);
+        (LiquidationEngine::new(config, Arc::new(RwLock::new(positions)), wallet.clone(), tx), rx, wallet)
+    }
+
+    fn liquidated(rx: &RingReceiver<OrderBookMessage>) -> Vec<(UserId, Price)> {
+        let mut out = Vec::new();
+        rx.try_drain(&mut out, 16);
+        out.into_iter()
+            .filter_map(|msg| match msg {
+                OrderBookMessage::Liquidate { user_id, bankruptcy_price, .. } => Some((user_id, bankruptcy_price)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    //at 95 the long is 5 down and the maintenance margin is 4.75
+    #[test]
+    fn an_account_is_liquidated_once_equity_reaches_the_maintenance_margin() {
+        let (at, above) = (Uuid::from_u128(10), Uuid::from_u128(20));
+        let (mut engine, rx, _wallet) = liquidation(&[(at, MarginMode::Cross, dec!(9.75)), (above, MarginMode::Cross, dec!(9.76))]);
+
+        let mut health: Vec<_> = engine.health(dec!(95)).iter().map(|h| (h.user_id, h.equity, h.maintenance_margin)).collect();
+        health.sort();
+        assert_eq!(health, [(at, dec!(4.75), dec!(4.75)), (above, dec!(4.76), dec!(4.75))]);
+        assert_eq!(engine.evaluate(dec!(95)), 1);
+        assert_eq!(liquidated(&rx), [(at, dec!(90.25))]);
+
+        //in flight: not sent again until retry_after
+        assert_eq!(engine.evaluate(dec!(95)), 0);
+        assert!(liquidated(&rx).is_empty());
+    }
+
+    #[test]
+    fn an_isolated_position_only_counts_its_own_margin() {
+        let user = Uuid::from_u128(10);
+        let (mut engine, rx, wallet) = liquidation(&[(user, MarginMode::Isolated, dec!(1000))]);
+        wallet.lock().unwrap().restore_isolated_margin(user, symbol(), dec!(-1));
+
+        assert_eq!(engine.evaluate(dec!(96)), 0);
+        assert_eq!(engine.evaluate(dec!(95)), 1);
+        assert_eq!(liquidated(&rx), [(user, dec!(91))]);
+    }
+}
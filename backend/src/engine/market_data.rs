@@ -0,0 +1,230 @@
+// Market data over WebSocket: connections subscribe to channels per symbol, the consumer turns
+// engine events into messages and fans them out to the subscribed connections.
+// Every connection has a bounded send queue; the pipeline never waits on a client, a connection
+// whose queue is full is dropped as a slow consumer. Depth subscribers get the published
+// snapshot first and every diff after it, stitched by update id.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use chrono::DateTime;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    CandleInterval, CandleKind, Event, EventConsumer, MarkPriceUpdate, PublicTrade, SharedCandles, SharedDepth, Subscription, Symbol,
+    TickerService, WsChannel, WsMessage, WsMessageType, WsResponse,
+};
+
+pub const DEFAULT_WS_SEND_QUEUE: usize = 1024;
+
+pub type ConnectionId = u64;
+
+struct Connection {
+    tx: mpsc::Sender<Arc<str>>,
+    subscriptions: HashSet<Subscription>,
+}
+
+#[derive(Default)]
+struct Connections {
+    next_id: ConnectionId,
+    by_id: HashMap<ConnectionId, Connection>,
+}
+
+pub struct MarketDataHub {
+    symbol: Symbol,
+    queue_len: usize,
+    connections: RwLock<Connections>,
+    depth: SharedDepth,
+    tickers: Arc<TickerService>,
+    candles: SharedCandles,
+}
+
+fn encode<T: Serialize>(value: &T) -> Arc<str> {
+    serde_json::to_string(value).expect("market data serializes").into()
+}
+
+fn snapshot<T: Serialize>(subscription: Subscription, data: &T) -> Arc<str> {
+    encode(&WsMessage { subscription, type_: WsMessageType::Snapshot, data })
+}
+
+impl MarketDataHub {
+    pub fn new(symbol: Symbol, queue_len: usize, depth: SharedDepth, tickers: Arc<TickerService>, candles: SharedCandles) -> Self {
+        Self { symbol, queue_len, connections: RwLock::new(Connections::default()), depth, tickers, candles }
+    }
+
+    pub fn connect(&self) -> (ConnectionId, mpsc::Receiver<Arc<str>>) {
+        let (tx, rx) = mpsc::channel(self.queue_len);
+        let mut connections = self.connections.write().unwrap_or_else(|e| e.into_inner());
+        connections.next_id += 1;
+        let id = connections.next_id;
+        connections.by_id.insert(id, Connection { tx, subscriptions: HashSet::new() });
+        (id, rx)
+    }
+
+    pub fn disconnect(&self, id: ConnectionId) {
+        self.connections.write().unwrap_or_else(|e| e.into_inner()).by_id.remove(&id);
+    }
+
+    //dropping the sender ends the connection's queue, its task closes the socket once drained
+    fn drop_slow(&self, slow: &[ConnectionId]) {
+        if slow.is_empty() {
+            return;
+        }
+        let mut connections = self.connections.write().unwrap_or_else(|e| e.into_inner());
+        for id in slow {
+            if connections.by_id.remove(id).is_some() {
+                println!(" [WS] connection {id} dropped: send queue full");
+            }
+        }
+    }
+
+    pub fn reply(&self, id: ConnectionId, response: &WsResponse) {
+        let full = {
+            let connections = self.connections.read().unwrap_or_else(|e| e.into_inner());
+            connections.by_id.get(&id).is_some_and(|c| c.tx.try_send(encode(response)).is_err())
+        };
+        if full {
+            self.drop_slow(&[id]);
+        }
+    }
+
+    //the ack and the current state are queued under the same lock the consumer publishes under,
+    //so nothing published after the snapshot is missed
+    pub fn subscribe(&self, id: ConnectionId, subscription: Subscription) {
+        let subscription = match subscription.validate() {
+            Ok(s) if s.symbol == self.symbol => s,
+            Ok(s) => return self.reply(id, &WsResponse::Error { error: format!("unknown symbol {}", s.symbol) }),
+            Err(error) => return self.reply(id, &WsResponse::Error { error }),
+        };
+        let mut messages = vec![encode(&WsResponse::Subscribed { subscription })];
+        let mut connections = self.connections.write().unwrap_or_else(|e| e.into_inner());
+        let Some(connection) = connections.by_id.get_mut(&id) else { return };
+        if connection.subscriptions.insert(subscription) {
+            match subscription.channel {
+                WsChannel::Depth => messages.push(snapshot(subscription, &**self.depth.load())),
+                WsChannel::Ticker => messages.extend(self.tickers.ticker(subscription.symbol).map(|t| snapshot(subscription, &t))),
+                WsChannel::Candles => {
+                    let (kind, interval) = (subscription.kind.unwrap_or_default(), subscription.interval.expect("validated"));
+                    let candles = self.candles.read().unwrap_or_else(|e| e.into_inner());
+                    messages.extend(candles.open_candle(subscription.symbol, kind, interval).map(|c| snapshot(subscription, c)));
+                }
+                WsChannel::Trades | WsChannel::MarkPrice => {}
+            }
+        }
+        if messages.into_iter().any(|m| connection.tx.try_send(m).is_err()) {
+            connections.by_id.remove(&id);
+            println!(" [WS] connection {id} dropped: send queue full");
+        }
+    }
+
+    pub fn unsubscribe(&self, id: ConnectionId, subscription: Subscription) {
+        let subscription = match subscription.validate() {
+            Ok(s) => s,
+            Err(error) => return self.reply(id, &WsResponse::Error { error }),
+        };
+        let removed = {
+            let mut connections = self.connections.write().unwrap_or_else(|e| e.into_inner());
+            connections.by_id.get_mut(&id).is_some_and(|c| c.subscriptions.remove(&subscription))
+        };
+        let response = match removed {
+            true => WsResponse::Unsubscribed { subscription },
+            false => WsResponse::Error { error: "not subscribed".to_string() },
+        };
+        self.reply(id, &response);
+    }
+
+    pub fn is_subscribed(&self, subscription: &Subscription) -> bool {
+        let connections = self.connections.read().unwrap_or_else(|e| e.into_inner());
+        connections.by_id.values().any(|c| c.subscriptions.contains(subscription))
+    }
+
+    //serialized once, only when someone is subscribed
+    pub fn publish<T: Serialize>(&self, subscription: Subscription, data: &T) {
+        let mut slow = Vec::new();
+        {
+            let connections = self.connections.read().unwrap_or_else(|e| e.into_inner());
+            let mut message = None;
+            for (id, connection) in connections.by_id.iter() {
+                if !connection.subscriptions.contains(&subscription) {
+                    continue;
+                }
+                let message = message.get_or_insert_with(|| encode(&WsMessage { subscription, type_: WsMessageType::Update, data }));
+                if connection.tx.try_send(message.clone()).is_err() {
+                    slow.push(*id);
+                }
+            }
+        }
+        self.drop_slow(&slow);
+    }
+}
+
+pub struct MarketDataConsumer {
+    hub: Arc<MarketDataHub>,
+    next_seq: i64,
+    tickers: HashSet<Symbol>,
+    candles: HashSet<(Symbol, CandleKind)>,
+}
+
+impl MarketDataConsumer {
+    //numbers events like the persistence consumer, so trade ids match the stored ones
+    pub fn new(hub: Arc<MarketDataHub>, last_seq: i64) -> Self {
+        Self { hub, next_seq: last_seq + 1, tickers: HashSet::new(), candles: HashSet::new() }
+    }
+}
+
+impl EventConsumer for MarketDataConsumer {
+    fn name(&self) -> &'static str {
+        "market_data"
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        match event {
+            Event::Fill(fill) => {
+                let trade = PublicTrade {
+                    trade_id: seq,
+                    price: fill.price.normalize(),
+                    quantity: fill.quantity.normalize(),
+                    side: fill.taker_side.as_str().to_string(),
+                    time: DateTime::from_timestamp_nanos(fill.timestamp_ as i64),
+                };
+                self.hub.publish(Subscription::new(WsChannel::Trades, fill.symbol), &trade);
+                self.tickers.insert(fill.symbol);
+                self.candles.insert((fill.symbol, CandleKind::Trade));
+            }
+            Event::MarkPrice { symbol, price, timestamp } => {
+                let update = MarkPriceUpdate { price: price.normalize(), timestamp: *timestamp };
+                self.hub.publish(Subscription::new(WsChannel::MarkPrice, *symbol), &update);
+                self.tickers.insert(*symbol);
+                self.candles.insert((*symbol, CandleKind::Mark));
+            }
+            Event::DepthUpdate(update) => self.hub.publish(Subscription::new(WsChannel::Depth, update.symbol), update),
+            _ => {}
+        }
+    }
+
+    //ticker and candles move once per batch, after their own consumers saw it
+    fn end_batch(&mut self) {
+        for symbol in self.tickers.drain() {
+            let subscription = Subscription::new(WsChannel::Ticker, symbol);
+            if self.hub.is_subscribed(&subscription)
+                && let Some(ticker) = self.hub.tickers.ticker(symbol) {
+                self.hub.publish(subscription, &ticker);
+            }
+        }
+        for (symbol, kind) in self.candles.drain() {
+            for interval in CandleInterval::ALL {
+                let subscription = Subscription::candles(symbol, kind, interval);
+                if !self.hub.is_subscribed(&subscription) {
+                    continue;
+                }
+                let candle = self.hub.candles.read().unwrap_or_else(|e| e.into_inner()).open_candle(symbol, kind, interval).cloned();
+                if let Some(candle) = candle {
+                    self.hub.publish(subscription, &candle);
+                }
+            }
+        }
+    }
+}
@@ -1,75 +1,249 @@
-use std::sync::{Arc, mpsc}; 
+use std::{sync::Arc, time::Duration};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use tokio::sync::{oneshot};
 
-use crate::{Order, OrderBook, OrderId, Price, Quantity, RingBuffer, UserId, now_nanos, types::{Event, OrderBookMessage, OrderResponse, OrderStatus, OrderType}};
+use uuid::Uuid;
+
+use crate::{Adl, BackpressureConfig, FeeEngine, FeeSchedule, FEE_ACCOUNT, BookPrices, CHECKSUM_LEVELS, DepthSnapshot, DepthUpdate, Fill, Funding, funding_payment, InsuranceFundEntry, LIQUIDATION_ACCOUNT, LimitOrder, Liquidation, OpenOrder, PositionEngine, adl_queue, EngineMonitor, EngineSnapshot, EventBufferStats, EventPublisher, LaneConfig, depth_checksum, Order, OrderBook, OrderId, Price, PriorityLanes, Quantity, RingBuffer, SharedBookPrices, SharedDepth, RingReceiver, RiskConfig, RiskEngine, SharedWallet, Side, Symbol, UserId, WaitStrategy, initial_margin, now_nanos, types::{Event, OrderBookMessage, OrderResponse, OrderStatus, OrderType}};
+
+pub const MAX_BATCH: usize = 256;
 
 pub struct MatchingEngine{
-   event_buffer : Arc<RingBuffer<Event>>,
-   order_book :OrderBook
+   symbol : Symbol,  //one engine (and one book) per instrument
+   events : EventPublisher,
+   order_book :OrderBook,
+   idle_wait : WaitStrategy,  //how the engine waits on an empty command ring
+   lanes : PriorityLanes,
+   risk : RiskEngine,
+   fees : FeeEngine,
+   positions : PositionEngine,  //the engine's own copy, ADL ranks on it so the outcome is deterministic
+   wallet : Option<SharedWallet>,  //margin is only enforced when a wallet is attached
+   book_prices : Option<(SharedBookPrices, Decimal)>,  //published after every batch, with the impact notional
+   depth : Option<(SharedDepth, usize)>,  //published after every batch, with the number of levels per side
+   mark_price : Option<Price>,
+   monitor : Arc<EngineMonitor>
 }
 
 impl MatchingEngine{
    pub fn new(
+      symbol: Symbol,
       event: Arc<RingBuffer<Event>>
    )->Self{
+      Self::with_backpressure(symbol, event, BackpressureConfig::default())
+   }
+
+   pub fn with_backpressure(
+      symbol: Symbol,
+      event: Arc<RingBuffer<Event>>,
+      config: BackpressureConfig
+   )->Self{
+      let events = EventPublisher::new(event, config);
+      let lanes = PriorityLanes::new(LaneConfig::default());
+      let monitor = Arc::new(EngineMonitor::new(events.stats(), lanes.stats()));
       Self {
-         event_buffer: event ,
-         order_book:OrderBook::new()
+         symbol,
+         events,
+         order_book:OrderBook::new(),
+         idle_wait: WaitStrategy::Park(Duration::from_micros(50)),
+         lanes,
+         risk: RiskEngine::new(RiskConfig::default()),
+         fees: FeeEngine::new(FeeSchedule::default()),
+         positions: PositionEngine::new(),
+         wallet: None,
+         book_prices: None,
+         depth: None,
+         mark_price: None,
+         monitor
+      }
+   }
+
+   pub fn with_wallet(mut self, wallet: SharedWallet)->Self{
+      self.wallet = Some(wallet);
+      self
+   }
+
+   pub fn with_risk_config(mut self, config: RiskConfig)->Self{
+      self.risk = RiskEngine::new(config);
+      self
+   }
+
+   pub fn with_fee_schedule(mut self, schedule: FeeSchedule)->Self{
+      self.fees = FeeEngine::new(schedule);
+      self
+   }
+
+   //positions rebuilt from the stored events, see replay_positions; after the risk config,
+   //which starts the risk engine over
+   pub fn with_positions(mut self, positions: PositionEngine)->Self{
+      for position in positions.open_positions() {
+         if let Some(side) = position.side() {
+            self.risk.on_trade(position.user_id, position.symbol, side, position.size.abs());
+         }
+      }
+      self.positions = positions;
+      self
+   }
+
+   //30 day volumes traded before this run, see FeeEngine::restore
+   pub fn with_fee_volumes(mut self, volumes: Vec<(UserId, u64, Decimal)>)->Self{
+      self.fees.restore(volumes);
+      self
+   }
+
+   //books the orders of the last run's shutdown snapshot again, as they rested: margin is reserved
+   //again (the wallet and positions have to be attached first) and an OrderPlaced goes out for each,
+   //so the L3 book and the depth start from them. An order the wallet no longer covers is expired
+   pub fn with_snapshot(mut self, snapshot: EngineSnapshot)->Self{
+      self.order_book.fill_seq = snapshot.fill_seq;
+      for order in snapshot.orders {
+         let remaining = order.remaining();
+         if order.symbol != self.symbol || order.order_type != OrderType::Limit || remaining <= Decimal::ZERO {
+            continue;
+         }
+         if let Err(e) = self.reserve_margin(&Order { quantity: remaining, filled: Decimal::ZERO, ..order }) {
+            println!(" [ENGINE] order {} from the snapshot expired: {e}", order.order_id);
+            self.expire_order(&order, remaining);
+            continue;
+         }
+         let price = order.price.unwrap_or_default();
+         self.risk.on_rest(order.order_id, &order, price, remaining);
+         self.order_book.insert_order(order);
+         self.emit_event(Event::OrderPlaced {
+            order_id: order.order_id,
+            user_id: order.user_id,
+            symbol: order.symbol,
+            side: order.side,
+            price,
+            quantity: remaining,
+            timestamp: now_nanos(),
+         });
       }
+      self.publish_book_prices();
+      self.publish_depth();
+      self
+   }
+
+   //resting orders in price-time order, so the book can be rebuilt level by level
+   pub fn snapshot(&self)->EngineSnapshot{
+      let book = &self.order_book;
+      let orders = book.bids.values().rev()
+         .chain(book.asks.values())
+         .flat_map(|level| level.orders.iter())
+         .filter_map(|id| book.orders.get(id))
+         .copied()
+         .collect();
+      EngineSnapshot {
+         taken_at: now_nanos(),
+         fill_seq: book.fill_seq,
+         best_bid: book.best_bid,
+         best_ask: book.best_ask,
+         orders
+      }
+   }
+
+   pub fn with_book_prices(mut self, prices: SharedBookPrices, impact_notional: Decimal)->Self{
+      self.book_prices = Some((prices, impact_notional));
+      self
+   }
+
+   pub fn with_depth(mut self, depth: SharedDepth, levels: usize)->Self{
+      self.depth = Some((depth, levels));
+      self
+   }
+
+   pub fn with_idle_wait(mut self, wait: WaitStrategy)->Self{
+      self.idle_wait = wait;
+      self
+   }
+
+   pub fn with_lane_config(mut self, config: LaneConfig)->Self{
+      self.lanes.set_config(config);
+      self
+   }
+
+   pub fn event_stats(&self)->Arc<EventBufferStats>{
+      self.events.stats()
+   }
+
+   pub fn monitor(&self)->Arc<EngineMonitor>{
+      self.monitor.clone()
+   }
+
+   //blocks until nothing is left in the overflow queue, used on shutdown
+   pub fn flush_events(&mut self){
+      self.events.flush_blocking();
    }
 
    pub fn run(
       &mut self,
-      cmd_rx : mpsc::Receiver<OrderBookMessage>
+      cmd_rx : RingReceiver<OrderBookMessage>
    ){
-      let mut batch:Vec<OrderBookMessage> = Vec::with_capacity(256);
+      let mut batch:Vec<OrderBookMessage> = Vec::with_capacity(MAX_BATCH);
       loop{
-         match cmd_rx.recv() {
-            Ok(cmd) => batch.push(cmd),
-            Err(_) => {
+         //lanes still hold work: only take what is already queued, otherwise wait for the first command.
+         //once intake is closed this keeps going until the lanes are empty too
+         if self.lanes.is_empty() {
+            if cmd_rx.recv_batch(&mut batch, MAX_BATCH, self.idle_wait).is_err() {
                println!(" [ENGINE] Command channel closed");
                break;
             }
+         } else {
+            cmd_rx.try_drain(&mut batch, MAX_BATCH);
          }
-         while batch.len()<256{
-            match cmd_rx.try_recv(){ 
-               Ok(cmd)=>batch.push(cmd),
-               Err(_) =>break
-            }
+         for cmd in batch.drain(..) {
+            self.lanes.push(cmd);
          }
-         batch.sort_by_key(|cmd| cmd.priority());
 
-         self.process_batch(&mut batch);
-         
-         batch.clear();
+         //give spilled events a chance to reach consumers before producing new ones
+         self.events.flush_spill();
 
+         let mut processed = 0;
+         while processed < MAX_BATCH {
+            let Some(cmd) = self.lanes.pop() else { break };
+            self.dispatch(cmd);
+            processed += 1;
+         }
+         if processed > 0 {
+            self.publish_book_prices();
+            self.publish_depth();
+         }
+         self.monitor.record_batch(processed);
       }
    }
 
-   fn process_batch(&mut self, batch: &mut Vec<OrderBookMessage>) {
-      for cmd in batch.drain(..) {
-         match cmd {
-               OrderBookMessage::PlaceOrder {
-                  order,
-                  mut priority,
-                  mut responder,
-               } => {
-                  self.handle_place_order(order, &mut responder);
-               }
+   fn dispatch(&mut self, cmd: OrderBookMessage) {
+      match cmd {
+         OrderBookMessage::PlaceOrder {
+            order,
+            priority: _,
+            mut responder,
+         } => {
+            self.handle_place_order(order, &mut responder);
+         }
 
-               OrderBookMessage::CancelOrder {
-                  order_id,
-                  user_id,
-                  responder,
-               } => {
-                  self.handle_cancel_order(order_id, user_id, responder);
-               }
+         OrderBookMessage::CancelOrder {
+            order_id,
+            user_id,
+            responder,
+         } => {
+            self.handle_cancel_order(order_id, user_id, responder);
+         }
+
+         OrderBookMessage::UpdateMarkPrice { price } => {
+            self.handle_update_mark_price(price);
+         }
+
+         OrderBookMessage::OpenOrders { user_id, symbol, responder } => {
+            let _ = responder.send(self.open_orders(user_id, symbol));
+         }
 
-               OrderBookMessage::UpdateMarkPrice { price } => {
-                  self.handle_update_mark_price(price);
-               }
+         OrderBookMessage::Liquidate { user_id, symbol, mark_price, bankruptcy_price } => {
+            self.handle_liquidate(user_id, symbol, mark_price, bankruptcy_price);
+         }
+
+         OrderBookMessage::SettleFunding { symbol, funding_time, rate, mark_price } => {
+            self.handle_settle_funding(symbol, funding_time, rate, mark_price);
          }
       }
    }
@@ -79,40 +253,85 @@ impl MatchingEngine{
       order:  Order,
       responder: &mut Option<oneshot::Sender<Result<OrderResponse, String>>,>
    ) {
-      if let Err(e) = self.validate_order(&order) {
-          if let Some(tx) = responder.take() {
-            let _ = tx.send(Err(e));
+      if self.events.is_halted() {
+         if let Some(tx) = responder.take() {
+            let _ = tx.send(Err("engine halted: event stream overflow".to_string()));
          }
-         self.emit_event(Event::OrderRejected { 
-            order_id:order.order_id,
-            user_id :order.user_id,
-            reason : ("problem while validatin".to_string()),
-            timestamp : now_nanos()
-        });
-        return;
+         return;
+      }
+      if let Err(e) = self.validate_order(&order) {
+         self.reject_order(&order, e, responder);
+         return;
+      }
+      if let Err(e) = self.risk.check(&order, self.reference_price(&order)) {
+         self.reject_order(&order, e.to_string(), responder);
+         return;
+      }
+      if let Err(e) = self.reserve_margin(&order) {
+         self.reject_order(&order, e, responder);
+         return;
+      }
+      let (order_id, status, filled, remaining) = self.execute_order(order);
+
+      if let Some(tx) = responder.take(){
+         let _ = tx.send(Ok(OrderResponse::PlacedOrder {
+               order_id,
+               status,
+               filled,
+               remaining
+         }));
+      }
+   }
+
+   fn match_and_emit(&mut self, order: Order)->(Vec<Fill>, Option<Order>){
+      self.emit_event(Event::OrderAccepted { order, timestamp: now_nanos() });
+      let (mut fills, remaining) = self.order_book.match_order(order);
+      for fill in fills.iter_mut() {
+         self.fees.charge(fill);
+         self.risk.on_fill(fill);
+         self.positions.apply_fill(fill);
+         self.emit_event(Event::Fill(*fill));
       }
+      //market orders never come back from the book, whatever did not fill is gone
+      let filled: Quantity = fills.iter().map(|f| f.quantity).sum();
+      if order.order_type == OrderType::Market && filled < order.quantity {
+         self.expire_order(&order, order.quantity - filled);
+      }
+      (fills, remaining)
+   }
+
+   fn expire_order(&mut self, order: &Order, remaining: Quantity){
+      self.emit_event(Event::OrderExpired {
+         order_id: order.order_id,
+         user_id: order.user_id,
+         remaining,
+         timestamp: now_nanos()
+      });
+   }
+
+   //matches an accepted order and books what is left of a limit order
+   fn execute_order(&mut self, order: Order)->(OrderId, OrderStatus, Quantity, Quantity){
       let order_quantity = order.quantity;
       let order_id = order.order_id;
       let order_type = order.order_type;
-      let (fills,remaining_order) = self.order_book.match_order(order);
-
-      for fill in fills.iter() {
-         self.emit_event(Event::Fill(fill.clone()));
-      }
+      let (fills,remaining_order) = self.match_and_emit(order);
 
       if let Some(rem_order) = remaining_order {
          let order_id = rem_order.order_id;
          let user_id  = rem_order.user_id;
+         let symbol   = rem_order.symbol;
          let side     = rem_order.side;
          let price    = rem_order.price.unwrap();
          let quantity = rem_order.quantity;
          let filled = rem_order.filled;
 
+         self.risk.on_rest(order_id, &rem_order, price, quantity.checked_sub(filled).unwrap());
          self.order_book.insert_order(rem_order);
 
          self.emit_event(Event::OrderPlaced {
             order_id,
             user_id,
+            symbol,
             side,
             price,
             quantity:quantity.checked_sub(filled).unwrap(),
@@ -123,6 +342,11 @@ impl MatchingEngine{
       let total_filled:Quantity = fills.iter().map(|f|f.quantity).sum();
       let remaining = original_qty.checked_sub(total_filled).ok_or("err").unwrap();
 
+      //market orders never rest: margin held for the unfilled part goes back now
+      if order_type == OrderType::Market && remaining > dec!(0) && let Some(wallet) = &self.wallet {
+         wallet.lock().unwrap_or_else(|e| e.into_inner()).release_quantity(&order_id, remaining);
+      }
+
       let status = match order_type {
          OrderType::Market => {
             if total_filled == dec!(0) {
@@ -143,15 +367,17 @@ impl MatchingEngine{
             }
          }
       };
-      
-      if let Some(tx) = responder.take(){
-         let _ = tx.send(Ok(OrderResponse::PlacedOrder {
-               order_id,
-               status,
-               filled: total_filled, 
-               remaining 
-         }));
-      }
+      (order_id, status, total_filled, remaining)
+   }
+
+   //in time priority per user: user_orders keeps insertion order
+   fn open_orders(&self, user_id: UserId, symbol: Option<Symbol>)->Vec<OpenOrder>{
+      let Some(ids) = self.order_book.user_orders.get(&user_id) else { return Vec::new() };
+      ids.iter()
+         .filter_map(|id| self.order_book.orders.get(id))
+         .filter(|o| symbol.is_none_or(|s| o.symbol == s))
+         .map(OpenOrder::from)
+         .collect()
    }
 
    fn handle_cancel_order(
@@ -160,14 +386,15 @@ impl MatchingEngine{
       user_id: UserId ,
       mut responder:Option<oneshot::Sender<Result<OrderResponse, String>>,>
    ){
+      if self.events.is_halted() {
+         if let Some(tx) = responder.take() {
+            let _ = tx.send(Err("engine halted: event stream overflow".to_string()));
+         }
+         return;
+      }
 
-      match self.order_book.cancel_order(&order_id, &user_id){
-         Ok(order)=>{
-            self.emit_event(Event::OrderCancelled { 
-               order_id,
-               user_id, 
-               timestamp: now_nanos() 
-            });
+      match self.cancel_resting(order_id, user_id){
+         Ok(())=>{
             if let Some(tx) = responder.take(){
                 let _ = tx.send(Ok(OrderResponse::CanceledOrder { 
                   order_id,
@@ -187,16 +414,302 @@ impl MatchingEngine{
       };
    }
  
-   fn handle_update_mark_price(&mut self , price: Price){
+   fn cancel_resting(&mut self, order_id: OrderId, user_id: UserId)->Result<(),String>{
+      self.order_book.cancel_order(&order_id, &user_id)?;
+      self.risk.on_cancel(&order_id);
+      if let Some(wallet) = &self.wallet {
+         wallet.lock().unwrap_or_else(|e| e.into_inner()).release_order(&order_id);
+      }
+      self.emit_event(Event::OrderCancelled {
+         order_id,
+         user_id,
+         timestamp: now_nanos()
+      });
+      Ok(())
+   }
 
+   //takeover first, then the liquidation account closes the position with a market order.
+   //the size comes from the engine's own view, so a stale or repeated request does nothing
+   fn handle_liquidate(&mut self, user_id: UserId, symbol: Symbol, mark_price: Price, bankruptcy_price: Price){
+      if self.events.is_halted() || symbol != self.symbol || user_id == LIQUIDATION_ACCOUNT {
+         return;
+      }
+      let position = self.risk.position(&user_id, &symbol);
+      if position.is_zero() {
+         println!(" [ENGINE] liquidation of {user_id} ignored: no open position");
+         return;
+      }
 
+      //resting orders would reopen exposure the moment the position is gone
+      let open_orders = self.order_book.user_orders.get(&user_id).cloned().unwrap_or_default();
+      for order_id in open_orders {
+         let _ = self.cancel_resting(order_id, user_id);
+      }
+
+      let side = if position > Decimal::ZERO { Side::Buy } else { Side::Sell };
+      let quantity = position.abs();
+      let liquidation_id = Uuid::new_v4();
+      self.emit_event(Event::Liquidation(Liquidation {
+         liquidation_id,
+         user_id,
+         symbol,
+         side,
+         quantity,
+         mark_price,
+         bankruptcy_price,
+         fee: (quantity * (mark_price - bankruptcy_price).abs()).round_dp(8),
+         timestamp: now_nanos()
+      }));
+      self.transfer_position(user_id, symbol, side, quantity, bankruptcy_price);
+
+      let unresolved = self.close_liquidation(liquidation_id, symbol, side, quantity, mark_price, bankruptcy_price);
+      println!(" [ENGINE] liquidated {quantity} {symbol} of {user_id} at bankruptcy {bankruptcy_price}");
+      if unresolved > Decimal::ZERO {
+         println!(" [ENGINE] liquidation {liquidation_id}: {unresolved} {symbol} left with the liquidation account, nothing to deleverage against");
+      }
    }
-   fn emit_event(&self,event:Event){
-      self.event_buffer.push(event);
+
+   //one Funding event per open position, in user order so the stream does not depend on map order
+   fn handle_settle_funding(&mut self, symbol: Symbol, funding_time: u64, rate: Decimal, mark_price: Price){
+      if symbol != self.symbol {
+         return;
+      }
+      let mut open: Vec<_> = self.positions.open_positions()
+         .filter(|p| p.symbol == symbol)
+         .map(|p| (p.user_id, p.size))
+         .collect();
+      open.sort_by_key(|(user_id, _)| *user_id);
+      let timestamp = now_nanos();
+      for (user_id, size) in open {
+         self.emit_event(Event::Funding(Funding {
+            user_id,
+            symbol,
+            funding_time,
+            position_size: size,
+            rate,
+            amount: funding_payment(size, mark_price, rate),
+            timestamp
+         }));
+      }
+   }
+
+   //`user_id` hands `quantity` of its `side` position to the liquidation account at `price`.
+   //the event that goes with it (Liquidation / Adl) lets the pipeline do the same
+   fn transfer_position(&mut self, user_id: UserId, symbol: Symbol, side: Side, quantity: Quantity, price: Price){
+      self.positions.transfer(user_id, symbol, side, quantity, price, now_nanos());
+      self.risk.on_trade(user_id, symbol, side.opposite(), quantity);
+      self.risk.on_trade(LIQUIDATION_ACCOUNT, symbol, side, quantity);
+   }
+
+   //the liquidation account holds `quantity` on `side`. Returns what could not be closed at all
+   fn close_liquidation(&mut self, liquidation_id: Uuid, symbol: Symbol, side: Side, quantity: Quantity, mark_price: Price, bankruptcy_price: Price)->Quantity{
+      //IOC: matched right away, the unfilled rest is expired instead of booked
+      let close = |quantity: Quantity, price: Price| {
+         Order::limit_order(LimitOrder { user_id: LIQUIDATION_ACCOUNT, symbol, side: side.opposite(), price, quantity, leverage: dec!(1) })
+      };
+      //what closing at `fill.price` instead of bankruptcy earns (> 0) or costs (< 0) the fund
+      let versus_bankruptcy = |fills: &[Fill]| -> Decimal {
+         fills.iter().map(|f| match side {
+            Side::Buy => f.quantity * (f.price - bankruptcy_price),
+            Side::Sell => f.quantity * (bankruptcy_price - f.price),
+         }).sum()
+      };
+
+      //1. never worse than bankruptcy
+      let (fills, unbooked) = self.match_and_emit(close(quantity, bankruptcy_price));
+      if let Some(order) = unbooked {
+         self.expire_order(&order, order.remaining());
+      }
+      let mut remaining = quantity - fills.iter().map(|f| f.quantity).sum::<Quantity>();
+      self.book_insurance(liquidation_id, symbol, versus_bankruptcy(&fills));
+
+      //2. through bankruptcy for as much as the book takes and the fund can pay the difference of;
+      //what step 1 earned is not applied to the wallet yet, it counts towards the fund here
+      let fund = self.insurance_fund() + versus_bankruptcy(&fills);
+      if remaining > Decimal::ZERO
+         && let Some((coverable, worst)) = self.order_book.coverable_quantity(side.opposite(), remaining, bankruptcy_price, fund) {
+         let (fills, unbooked) = self.match_and_emit(close(coverable, worst));
+         if let Some(order) = unbooked {
+            self.expire_order(&order, order.remaining());
+         }
+         remaining -= fills.iter().map(|f| f.quantity).sum::<Quantity>();
+         self.book_insurance(liquidation_id, symbol, versus_bankruptcy(&fills));
+      }
+
+      //3. auto-deleveraging: the most profitable opposite positions close against it at bankruptcy
+      if remaining > Decimal::ZERO {
+         let queue = adl_queue(self.positions.open_positions(), symbol, side.opposite(), mark_price);
+         for candidate in queue {
+            if remaining <= Decimal::ZERO {
+               break;
+            }
+            let quantity = remaining.min(candidate.size.abs());
+            self.emit_event(Event::Adl(Adl {
+               liquidation_id,
+               user_id: candidate.user_id,
+               symbol,
+               side: side.opposite(),
+               quantity,
+               price: bankruptcy_price,
+               score: candidate.score,
+               timestamp: now_nanos()
+            }));
+            self.transfer_position(candidate.user_id, symbol, side.opposite(), quantity, bankruptcy_price);
+            remaining -= quantity;
+            println!(" [ENGINE] ADL: {quantity} {symbol} of {} closed at {bankruptcy_price}", candidate.user_id);
+         }
+      }
+      remaining
+   }
+
+   //the fund is the liquidation account's wallet balance: the closes above settle into it as realized
+   //PnL on the pipeline, so it lags the engine by the events not consumed yet
+   fn insurance_fund(&self)->Decimal{
+      let Some(wallet) = &self.wallet else { return Decimal::ZERO };
+      wallet.lock().unwrap_or_else(|e| e.into_inner()).account(&LIQUIDATION_ACCOUNT).map(|a| a.balance).unwrap_or_default()
+   }
+
+   //only the record of a surplus or deficit, the money moves with the fills
+   fn book_insurance(&mut self, liquidation_id: Uuid, symbol: Symbol, amount: Decimal){
+      if amount.is_zero() {
+         return;
+      }
+      self.emit_event(Event::InsuranceFund(InsuranceFundEntry {
+         liquidation_id,
+         symbol,
+         amount,
+         timestamp: now_nanos()
+      }));
+   }
+
+   fn reject_order(
+      &mut self,
+      order: &Order,
+      reason: String,
+      responder: &mut Option<oneshot::Sender<Result<OrderResponse, String>>,>
+   ){
+      if let Some(tx) = responder.take() {
+         let _ = tx.send(Err(reason.clone()));
+      }
+      self.emit_event(Event::OrderRejected {
+         order: *order,
+         reason,
+         timestamp: now_nanos()
+      });
+   }
+
+   //limit price, or for market orders the worst price the sweep reaches, so no fill is priced above it
+   //(0 on an empty side, nothing can fill then)
+   fn reference_price(&self, order: &Order)->Price{
+      match order.order_type {
+         OrderType::Limit => order.price.unwrap_or_default(),
+         OrderType::Market => self.order_book.worst_fill_price(order.side, order.quantity).unwrap_or_default(),
+      }
+   }
+
+   //the part of the order that closes the user's position; what the user's resting orders on the
+   //same side would close already is not counted twice
+   fn reducing_quantity(&self, order: &Order)->Quantity{
+      let position = self.risk.position(&order.user_id, &order.symbol);
+      let closes = match order.side {
+         Side::Buy => position < Decimal::ZERO,
+         Side::Sell => position > Decimal::ZERO,
+      };
+      if !closes {
+         return Decimal::ZERO;
+      }
+      let pending = self.risk.resting_quantity(&order.user_id, &order.symbol, order.side);
+      (position.abs() - pending).max(Decimal::ZERO).min(order.quantity)
+   }
+
+   //no fill of the order is priced above this: the limit or the worst ask for a buy, for a sell the
+   //best bid as well, a crossing sell fills there first
+   fn margin_price(&self, order: &Order)->Price{
+      let reference = self.reference_price(order);
+      match order.side {
+         Side::Buy => reference,
+         Side::Sell => self.order_book.best_bid.map_or(reference, |bid| bid.max(reference)),
+      }
+   }
+
+   //initial margin = notional / leverage for the part that opens or grows a position, plus the fee on
+   //the whole order at the higher of the user's rates, both at the margin price. Every fill is covered
+   //by its share of the reservation, so the wallet never has to book more than it released
+   fn reserve_margin(&mut self, order: &Order)->Result<(),String>{
+      if self.wallet.is_none() {
+         return Ok(());
+      }
+      let price = self.margin_price(order);
+      let opening = order.quantity - self.reducing_quantity(order);
+      let (maker_rate, taker_rate) = self.fees.rates(&order.user_id, now_nanos());
+      let fee = order.quantity * price * maker_rate.max(taker_rate).max(Decimal::ZERO);
+      let margin = initial_margin(opening, price, order.leverage) + fee;
+      let Some(wallet) = &self.wallet else { return Ok(()) };
+      wallet.lock().unwrap_or_else(|e| e.into_inner())
+         .reserve(order.order_id, order.user_id, order.symbol, order.quantity, margin)
+   }
+
+   fn handle_update_mark_price(&mut self , price: Price){
+      self.mark_price = Some(price);
+      self.emit_event(Event::MarkPrice { symbol: self.symbol, price, timestamp: now_nanos() });
+   }
+
+   fn publish_book_prices(&self){
+      let Some((prices, impact_notional)) = &self.book_prices else { return };
+      let book = &self.order_book;
+      let snapshot = BookPrices {
+         best_bid: book.best_bid,
+         best_ask: book.best_ask,
+         impact_bid: book.impact_price(Side::Sell, *impact_notional),
+         impact_ask: book.impact_price(Side::Buy, *impact_notional),
+         updated_at: now_nanos()
+      };
+      *prices.write().unwrap_or_else(|e| e.into_inner()) = snapshot;
+   }
+   //nothing is rebuilt for a batch that left every level as it was. The snapshot is stored before
+   //the diff goes out: whoever loads a snapshot and then follows the events sees every diff after it
+   fn publish_depth(&mut self){
+      let Some((first_update_id, last_update_id, bids, asks)) = self.order_book.take_level_changes() else { return };
+      //one walk of the book serves both the snapshot and the checksum
+      let levels = self.depth.as_ref().map_or(CHECKSUM_LEVELS, |(_, levels)| (*levels).max(CHECKSUM_LEVELS));
+      let (mut top_bids, mut top_asks) = self.order_book.depth(levels);
+      let checksum = depth_checksum(&top_bids, &top_asks);
+      let timestamp = now_nanos();
+      if let Some((depth, levels)) = &self.depth {
+         top_bids.truncate(*levels);
+         top_asks.truncate(*levels);
+         depth.store(Arc::new(DepthSnapshot {
+            symbol: self.symbol,
+            bids: top_bids,
+            asks: top_asks,
+            last_update_id: self.order_book.update_id,
+            checksum,
+            updated_at: timestamp
+         }));
+      }
+      self.emit_event(Event::DepthUpdate(DepthUpdate {
+         symbol: self.symbol,
+         first_update_id,
+         last_update_id,
+         bids,
+         asks,
+         checksum,
+         timestamp
+      }));
+   }
+
+   fn emit_event(&mut self,event:Event){
+      self.events.publish(event);
    }
  
    
    fn validate_order(&self,order:&Order)->Result<(),String>{
+      if order.user_id == LIQUIDATION_ACCOUNT || order.user_id == FEE_ACCOUNT {
+         return Err("reserved account".to_string());
+      }
+      if order.symbol != self.symbol {
+         return Err(format!("unknown symbol {}", order.symbol));
+      }
 
       if order.order_type != OrderType::Limit && order.order_type != OrderType::Market{
          return Err("invalid order_type".to_string());
@@ -211,3 +724,216 @@ impl MatchingEngine{
    }
 
 }
+
+#[cfg(test)]
+mod tests {
+   use crate::MarketOrder;
+
+   use super::*;
+
+   const CAPACITY: usize = 1024;
+
+   fn engine()->(MatchingEngine, Arc<RingBuffer<Event>>){
+      let ring = Arc::new(RingBuffer::new(CAPACITY));
+      (MatchingEngine::new(symbol(), ring.clone()), ring)
+   }
+
+   fn symbol()->Symbol{
+      Symbol::new("BTC-PERP").unwrap()
+   }
+
+   fn limit(engine: &mut MatchingEngine, user_id: UserId, side: Side, price: Price, quantity: Quantity){
+      let order = Order::limit_order(LimitOrder { user_id, symbol: symbol(), side, price, quantity, leverage: dec!(10) });
+      engine.handle_place_order(order, &mut None);
+   }
+
+   fn market(engine: &mut MatchingEngine, user_id: UserId, side: Side, quantity: Quantity){
+      let order = Order::market_order(MarketOrder { user_id, symbol: symbol(), side, quantity, leverage: dec!(10) });
+      engine.handle_place_order(order, &mut None);
+   }
+
+   fn events(ring: &RingBuffer<Event>)->Vec<Event>{
+      ring.drain_batch(CAPACITY)
+   }
+
+   #[test]
+   fn funding_is_paid_on_the_engine_positions_in_user_order(){
+      let (mut engine, ring) = engine();
+      let (long, short) = (Uuid::from_u128(20), Uuid::from_u128(10));
+      limit(&mut engine, short, Side::Sell, dec!(100), dec!(3));
+      market(&mut engine, long, Side::Buy, dec!(2));
+      events(&ring);
+
+      engine.dispatch(OrderBookMessage::SettleFunding { symbol: symbol(), funding_time: 1_000, rate: dec!(0.001), mark_price: dec!(100) });
+      let funding: Vec<Funding> = events(&ring).into_iter().filter_map(|e| match e {
+         Event::Funding(f) => Some(f),
+         _ => None,
+      }).collect();
+      assert_eq!(funding.len(), 2);
+      assert_eq!((funding[0].user_id, funding[0].position_size, funding[0].amount), (short, dec!(-2), dec!(0.2)));
+      assert_eq!((funding[1].user_id, funding[1].position_size, funding[1].amount), (long, dec!(2), dec!(-0.2)));
+      assert!(funding.iter().all(|f| f.funding_time == 1_000 && f.rate == dec!(0.001)));
+   }
+
+   fn with_wallet(engine: MatchingEngine, balances: &[(UserId, Decimal)])->(MatchingEngine, SharedWallet){
+      let wallet: SharedWallet = Arc::new(std::sync::Mutex::new(crate::WalletEngine::new()));
+      for (user_id, balance) in balances {
+         wallet.lock().unwrap().credit(*user_id, *balance);
+      }
+      (engine.with_wallet(wallet.clone()), wallet)
+   }
+
+   //what the pipeline does with the fills
+   fn settle(events: Vec<Event>, wallet: &SharedWallet){
+      let mut positions = PositionEngine::new();
+      for event in events {
+         if let Event::Fill(fill) = event {
+            let changes = positions.apply_fill(&fill);
+            wallet.lock().unwrap().apply_fill(&fill, &changes);
+         }
+      }
+   }
+
+   #[test]
+   fn the_reservation_includes_the_fee(){
+      let (short, enough) = (Uuid::from_u128(10), Uuid::from_u128(20));
+      let (engine, ring) = engine();
+      //margin 10 on 1 @ 100 at 10x, plus 0.05 taker fee
+      let (mut engine, wallet) = with_wallet(engine, &[(short, dec!(10.04)), (enough, dec!(10.05))]);
+      limit(&mut engine, short, Side::Buy, dec!(100), dec!(1));
+      limit(&mut engine, enough, Side::Buy, dec!(100), dec!(1));
+
+      let events = events(&ring);
+      assert!(matches!(events[0], Event::OrderRejected { order, .. } if order.user_id == short));
+      assert_eq!(wallet.lock().unwrap().balance_view(&enough).reserved, dec!(10.05));
+   }
+
+   #[test]
+   fn a_crossing_sell_is_reserved_at_the_best_bid(){
+      let (buyer, seller) = (Uuid::from_u128(10), Uuid::from_u128(20));
+      let (engine, ring) = engine();
+      let (mut engine, wallet) = with_wallet(engine, &[(buyer, dec!(1000)), (seller, dec!(1000))]);
+      limit(&mut engine, buyer, Side::Buy, dec!(110), dec!(1));
+      limit(&mut engine, seller, Side::Sell, dec!(100), dec!(1));
+      //11 margin and 0.055 fee at 110, not 10 and 0.05 at the limit
+      assert_eq!(wallet.lock().unwrap().balance_view(&seller).reserved, dec!(11.055));
+
+      settle(events(&ring), &wallet);
+      let view = wallet.lock().unwrap().balance_view(&seller);
+      assert_eq!((view.reserved, view.position_margin), (dec!(0), dec!(11)));
+      assert_eq!(view.available, dec!(988.945));
+   }
+
+   //long 3 @ 100 taken over at bankruptcy 98 against bids of 1 @ 99 and 2 @ 97, with 0.5 in the fund
+   #[test]
+   fn a_liquidation_closes_at_bankruptcy_then_through_it_then_deleverages(){
+      let (long, short, bid_99, bid_97) = (Uuid::from_u128(10), Uuid::from_u128(20), Uuid::from_u128(30), Uuid::from_u128(40));
+      let (engine, ring) = engine();
+      let funded = [(long, dec!(1000)), (short, dec!(1000)), (bid_99, dec!(1000)), (bid_97, dec!(1000)), (LIQUIDATION_ACCOUNT, dec!(0.5))];
+      let (mut engine, _wallet) = with_wallet(engine, &funded);
+      limit(&mut engine, short, Side::Sell, dec!(100), dec!(3));
+      market(&mut engine, long, Side::Buy, dec!(3));
+      limit(&mut engine, bid_99, Side::Buy, dec!(99), dec!(1));
+      limit(&mut engine, bid_97, Side::Buy, dec!(97), dec!(2));
+      events(&ring);
+
+      engine.dispatch(OrderBookMessage::Liquidate { user_id: long, symbol: symbol(), mark_price: dec!(98), bankruptcy_price: dec!(98) });
+      let events = events(&ring);
+      assert!(matches!(events[0], Event::Liquidation(l) if l.user_id == long && l.quantity == dec!(3) && l.bankruptcy_price == dec!(98)));
+
+      let closes: Vec<_> = events.iter().filter_map(|e| match e {
+         Event::Fill(f) if f.taker_user_id == LIQUIDATION_ACCOUNT => Some((f.price, f.quantity)),
+         _ => None,
+      }).collect();
+      //1 above bankruptcy, then as much at 97 as the 0.5 in the fund and the 1 just earned pay for
+      assert_eq!(closes, [(dec!(99), dec!(1)), (dec!(97), dec!(1.5))]);
+      let insurance: Vec<_> = events.iter().filter_map(|e| match e {
+         Event::InsuranceFund(entry) => Some(entry.amount),
+         _ => None,
+      }).collect();
+      assert_eq!(insurance, [dec!(1), dec!(-1.5)]);
+      let adl: Vec<_> = events.iter().filter_map(|e| match e {
+         Event::Adl(adl) => Some((adl.user_id, adl.quantity, adl.price)),
+         _ => None,
+      }).collect();
+      assert_eq!(adl, [(short, dec!(0.5), dec!(98))]);
+
+      assert_eq!(engine.risk.position(&long, &symbol()), dec!(0));
+      assert_eq!(engine.risk.position(&LIQUIDATION_ACCOUNT, &symbol()), dec!(0));
+      assert_eq!(engine.risk.position(&short, &symbol()), dec!(-2.5));
+      assert_eq!(engine.order_book.user_orders.get(&bid_97).map(|o| o.len()).unwrap_or_default(), 1);
+   }
+
+   //long 3 @ 100 taken over at bankruptcy 98 with only 97 bid: each 1 in the fund closes 1 in the book
+   #[test]
+   fn what_the_fund_cannot_cover_is_deleveraged_in_queue_order(){
+      let (long, short_2, short_1, bid) = (Uuid::from_u128(10), Uuid::from_u128(20), Uuid::from_u128(30), Uuid::from_u128(40));
+      for (fund, closed, deleveraged) in [
+         (dec!(0), vec![], vec![(short_2, dec!(2)), (short_1, dec!(1))]),
+         (dec!(1), vec![dec!(1)], vec![(short_2, dec!(2))]),
+      ] {
+         let (engine, ring) = engine();
+         let funded = [(long, dec!(1000)), (short_2, dec!(1000)), (short_1, dec!(1000)), (bid, dec!(1000)), (LIQUIDATION_ACCOUNT, fund)];
+         let (mut engine, _wallet) = with_wallet(engine, &funded);
+         limit(&mut engine, short_2, Side::Sell, dec!(100), dec!(2));
+         limit(&mut engine, short_1, Side::Sell, dec!(100), dec!(1));
+         market(&mut engine, long, Side::Buy, dec!(3));
+         limit(&mut engine, bid, Side::Buy, dec!(97), dec!(3));
+         events(&ring);
+
+         engine.dispatch(OrderBookMessage::Liquidate { user_id: long, symbol: symbol(), mark_price: dec!(98), bankruptcy_price: dec!(98) });
+         let events = events(&ring);
+         let closes: Vec<_> = events.iter().filter_map(|e| match e {
+            Event::Fill(f) if f.taker_user_id == LIQUIDATION_ACCOUNT => Some(f.quantity),
+            _ => None,
+         }).collect();
+         assert_eq!(closes, closed);
+         let adl: Vec<_> = events.iter().filter_map(|e| match e {
+            Event::Adl(adl) => Some((adl.user_id, adl.quantity)),
+            _ => None,
+         }).collect();
+         assert_eq!(adl, deleveraged);
+         assert_eq!(engine.risk.position(&LIQUIDATION_ACCOUNT, &symbol()), dec!(0));
+      }
+   }
+
+   #[test]
+   fn restored_positions_reach_the_risk_engine(){
+      let user = Uuid::from_u128(10);
+      let mut positions = PositionEngine::new();
+      positions.apply(crate::PositionTrade { user_id: user, symbol: symbol(), side: Side::Sell, quantity: dec!(3), price: dec!(100), leverage: dec!(10), timestamp: 0 });
+      let (engine, _ring) = engine();
+      let engine = engine.with_positions(positions);
+      assert_eq!(engine.risk.position(&user, &symbol()), dec!(-3));
+      assert_eq!(engine.positions.position(&user, &symbol()).map(|p| p.size), Some(dec!(-3)));
+   }
+
+   #[test]
+   fn a_snapshot_books_the_orders_again(){
+      let (maker, poor) = (Uuid::from_u128(10), Uuid::from_u128(20));
+      let (engine, ring) = engine();
+      let (mut engine, wallet) = with_wallet(engine, &[(maker, dec!(1000)), (poor, dec!(1000))]);
+      limit(&mut engine, maker, Side::Buy, dec!(99), dec!(1));
+      limit(&mut engine, maker, Side::Buy, dec!(100), dec!(2));
+      limit(&mut engine, poor, Side::Sell, dec!(101), dec!(1));
+      limit(&mut engine, maker, Side::Sell, dec!(101), dec!(1));
+      let reserved = wallet.lock().unwrap().balance_view(&maker).reserved;
+      let json = serde_json::to_string(&engine.snapshot()).unwrap();
+      events(&ring);
+
+      let (restarted, ring) = self::engine();
+      //the second user's balance is gone by the restart
+      let (restarted, wallet) = with_wallet(restarted, &[(maker, dec!(1000))]);
+      let restarted = restarted.with_snapshot(serde_json::from_str(&json).unwrap());
+
+      let book: Vec<_> = restarted.snapshot().orders.iter().map(|o| (o.user_id, o.side, o.price.unwrap())).collect();
+      assert_eq!(book, [(maker, Side::Buy, dec!(100)), (maker, Side::Buy, dec!(99)), (maker, Side::Sell, dec!(101))]);
+      assert_eq!(wallet.lock().unwrap().balance_view(&maker).reserved, reserved);
+
+      let events = events(&ring);
+      assert_eq!(events.iter().filter(|e| matches!(e, Event::OrderPlaced { .. })).count(), 3);
+      assert!(events.iter().any(|e| matches!(e, Event::OrderExpired { user_id, .. } if *user_id == poor)));
+      assert!(matches!(events.last(), Some(Event::DepthUpdate(_))));
+   }
+}
+
@@ -1,6 +1,54 @@
-pub mod order_book;
+mod order_book;
 pub use order_book::*;
-pub mod matching_engine;
+mod matching_engine;
 pub use matching_engine::*;
-pub mod ring_buffer;
+mod ring_buffer;
 pub use ring_buffer::*;
+mod command_ring;
+pub use command_ring::*;
+mod priority_lanes;
+pub use priority_lanes::*;
+mod event_publisher;
+pub use event_publisher::*;
+mod event_pipeline;
+pub use event_pipeline::*;
+mod position_engine;
+pub use position_engine::*;
+mod leverage_brackets;
+pub use leverage_brackets::*;
+mod risk_engine;
+pub use risk_engine::*;
+mod wallet_engine;
+pub use wallet_engine::*;
+mod price_source;
+pub use price_source::*;
+mod oracle;
+pub use oracle::*;
+mod funding_engine;
+pub use funding_engine::*;
+mod fee_engine;
+pub use fee_engine::*;
+mod liquidation_engine;
+pub use liquidation_engine::*;
+mod insurance_fund;
+pub use insurance_fund::*;
+mod adl;
+pub use adl::*;
+mod custody;
+pub use custody::*;
+mod candles;
+pub use candles::*;
+mod depth;
+pub use depth::*;
+mod ticker;
+pub use ticker::*;
+mod l3;
+pub use l3::*;
+mod market_data;
+pub use market_data::*;
+mod persistence;
+pub use persistence::*;
+mod ledger;
+pub use ledger::*;
+mod runtime;
+pub use runtime::*;
@@ -0,0 +1,351 @@
+// Oracle: turns external prices into the index and mark price of one instrument.
+//  index = median of the fresh sources, after dropping sources too far from the first median
+//  mark  = index + basis, basis = EMA of (impact mid of our own book - index), clamped
+// Runs on its own thread, the mark price reaches the engine as OrderBookMessage::UpdateMarkPrice.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use tokio::sync::watch;
+
+use crate::{
+    BookPrices, MarketPrices, OrderBookMessage, Price, PriceSource, RingSender, SourceState, SourceStatus, Symbol,
+    TrySendError, now_nanos, source_from_spec,
+};
+
+const PRICE_DP: u32 = 8;
+
+pub type SharedBookPrices = Arc<RwLock<BookPrices>>;
+pub type SharedMarketPrices = Arc<RwLock<MarketPrices>>;
+
+pub struct OracleConfig {
+    pub symbol: Symbol,
+    pub sources: Vec<String>,
+    pub interval: Duration,
+    pub staleness: Duration,      //quotes older than this do not count
+    pub max_deviation: Decimal,   //relative distance from the median before a source is an outlier
+    pub min_sources: usize,
+    pub basis_alpha: Decimal,     //EMA weight of the newest basis sample
+    pub max_basis: Decimal,       //|basis| is capped at this fraction of the index
+    pub request_timeout: Duration,
+}
+
+impl OracleConfig {
+    pub fn from_env(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            sources: std::env::var("ORACLE_SOURCES")
+                .map(|v| v.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect())
+                .unwrap_or_default(),
+            interval: Duration::from_millis(env_or("ORACLE_INTERVAL_MS", 1000)),
+            staleness: Duration::from_millis(env_or("ORACLE_STALENESS_MS", 5000)),
+            max_deviation: env_or("ORACLE_MAX_DEVIATION", dec!(0.02)),
+            min_sources: env_or("ORACLE_MIN_SOURCES", 1),
+            basis_alpha: env_or("ORACLE_BASIS_ALPHA", dec!(0.1)),
+            max_basis: env_or("ORACLE_MAX_BASIS", dec!(0.005)),
+            request_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+struct Quote {
+    price: Price,
+    received_at: u128,
+}
+
+struct Feed {
+    source: Box<dyn PriceSource>,
+    last: Option<Quote>,
+    error: Option<String>,
+}
+
+fn median(sorted: &[Price]) -> Price {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        ((sorted[mid - 1] + sorted[mid]) / Decimal::TWO).normalize()
+    } else {
+        sorted[mid]
+    }
+}
+
+pub struct Oracle {
+    config: OracleConfig,
+    feeds: Vec<Feed>,
+    basis: Option<Decimal>,
+    book: SharedBookPrices,
+    prices: SharedMarketPrices,
+    marks: watch::Sender<Option<Price>>,
+}
+
+impl Oracle {
+    pub fn new(config: OracleConfig, book: SharedBookPrices, prices: SharedMarketPrices) -> Result<Self, String> {
+        let feeds = config
+            .sources
+            .iter()
+            .map(|spec| source_from_spec(spec, config.request_timeout))
+            .map(|source| source.map(|source| Feed { source, last: None, error: None }))
+            .collect::<Result<Vec<_>, _>>()?;
+        if feeds.is_empty() {
+            return Err("no price sources configured (ORACLE_SOURCES)".to_string());
+        }
+        let (marks, _) = watch::channel(None);
+        Ok(Self { config, feeds, basis: None, book, prices, marks })
+    }
+
+    //every new mark price, for whoever has to react to it (liquidations)
+    pub fn subscribe(&self) -> watch::Receiver<Option<Price>> {
+        self.marks.subscribe()
+    }
+
+    pub fn add_source(&mut self, source: Box<dyn PriceSource>) {
+        self.feeds.push(Feed { source, last: None, error: None });
+    }
+
+    //one round: poll, index, mark. Returns the mark price if there is one
+    pub fn tick(&mut self) -> Option<Price> {
+        for feed in self.feeds.iter_mut() {
+            match feed.source.fetch() {
+                Ok(price) if price > Decimal::ZERO => {
+                    feed.last = Some(Quote { price, received_at: now_nanos() });
+                    feed.error = None;
+                }
+                Ok(price) => feed.error = Some(format!("non-positive price {price}")),
+                Err(e) => feed.error = Some(e),
+            }
+        }
+
+        let now = now_nanos();
+        let (index, statuses) = self.compute_index(now);
+        let book = *self.book.read().unwrap_or_else(|e| e.into_inner());
+
+        let mark = index.map(|index| {
+            if let Some(impact_mid) = book.impact_mid() {
+                let cap = index * self.config.max_basis;
+                let sample = (impact_mid - index).clamp(-cap, cap);
+                let alpha = self.config.basis_alpha;
+                self.basis = Some(match self.basis {
+                    Some(basis) => (basis + alpha * (sample - basis)).round_dp(PRICE_DP),
+                    None => sample.round_dp(PRICE_DP),
+                });
+            }
+            (index + self.basis.unwrap_or_default()).normalize()
+        });
+
+        let mut prices = self.prices.write().unwrap_or_else(|e| e.into_inner());
+        prices.index_price = index;
+        //without an index the last mark stays, liquidations should not run on a guessed price
+        if mark.is_some() {
+            prices.mark_price = mark;
+        }
+        prices.basis = self.basis.unwrap_or_default();
+        prices.book = book;
+        prices.sources = statuses;
+        prices.updated_at = now;
+        drop(prices);
+        if mark.is_some() {
+            self.marks.send_replace(mark);
+        }
+        mark
+    }
+
+    fn compute_index(&self, now: u128) -> (Option<Price>, Vec<SourceStatus>) {
+        let staleness = self.config.staleness.as_nanos();
+        let mut statuses: Vec<SourceStatus> = self
+            .feeds
+            .iter()
+            .map(|feed| {
+                let age = feed.last.as_ref().map(|q| now.saturating_sub(q.received_at));
+                //a failed poll keeps the last quote usable until it goes stale
+                let state = match age {
+                    None => SourceState::Error,
+                    Some(age) if age > staleness => SourceState::Stale,
+                    Some(_) => SourceState::Fresh,
+                };
+                SourceStatus {
+                    name: feed.source.name().to_string(),
+                    price: feed.last.as_ref().map(|q| q.price),
+                    age_ms: age.map(|a| (a / 1_000_000) as u64),
+                    state,
+                    error: feed.error.clone(),
+                }
+            })
+            .collect();
+
+        let mut fresh: Vec<Price> = statuses
+            .iter()
+            .filter(|s| s.state == SourceState::Fresh)
+            .filter_map(|s| s.price)
+            .collect();
+        if fresh.len() < self.config.min_sources.max(1) {
+            return (None, statuses);
+        }
+        fresh.sort();
+        let first = median(&fresh);
+
+        let max_deviation = self.config.max_deviation;
+        let is_outlier = |price: Price| ((price - first) / first).abs() > max_deviation;
+        for status in statuses.iter_mut().filter(|s| s.state == SourceState::Fresh) {
+            if status.price.is_some_and(is_outlier) {
+                status.state = SourceState::Outlier;
+            }
+        }
+        fresh.retain(|&price| !is_outlier(price));
+        if fresh.len() < self.config.min_sources.max(1) {
+            return (None, statuses);
+        }
+        (Some(median(&fresh)), statuses)
+    }
+}
+
+pub struct OracleHandle {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl OracleHandle {
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Release);
+        self.thread.thread().unpark();
+        if self.thread.join().is_err() {
+            println!(" [ORACLE] thread panicked");
+        }
+    }
+}
+
+pub fn start_oracle(mut oracle: Oracle, book_tx: RingSender<OrderBookMessage>) -> std::io::Result<OracleHandle> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        thread::Builder::new().name("oracle".to_string()).spawn(move || {
+            let symbol = oracle.config.symbol;
+            println!(" [ORACLE] {symbol}: {} price source(s)", oracle.feeds.len());
+            while !stop.load(Ordering::Acquire) {
+                if let Some(price) = oracle.tick() {
+                    match book_tx.try_send(OrderBookMessage::UpdateMarkPrice { price }) {
+                        Ok(()) => {}
+                        //next tick brings a newer price anyway
+                        Err(TrySendError::Full(_)) => println!(" [ORACLE] command ring full, mark price {price} skipped"),
+                        Err(TrySendError::Closed(_)) => break,
+                    }
+                }
+                thread::park_timeout(oracle.config.interval);
+            }
+        })?
+    };
+    Ok(OracleHandle { stop, thread })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(Price);
+
+    impl PriceSource for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn fetch(&mut self) -> Result<Price, String> {
+            Ok(self.0)
+        }
+    }
+
+    const SECOND: u128 = 1_000_000_000;
+
+    fn oracle(prices: &[Price]) -> Oracle {
+        let symbol = Symbol::new("BTC-PERP").unwrap();
+        let config = OracleConfig {
+            symbol,
+            sources: Vec::new(),
+            interval: Duration::from_secs(1),
+            staleness: Duration::from_secs(5),
+            max_deviation: dec!(0.02),
+            min_sources: 1,
+            basis_alpha: dec!(0.1),
+            max_basis: dec!(0.005),
+            request_timeout: Duration::from_millis(500),
+        };
+        let feeds = prices.iter().map(|&price| Feed { source: Box::new(Fixed(price)), last: None, error: None }).collect();
+        let book = Arc::new(RwLock::new(BookPrices::default()));
+        let prices = Arc::new(RwLock::new(MarketPrices::new(symbol)));
+        let (marks, _) = watch::channel(None);
+        Oracle { config, feeds, basis: None, book, prices, marks }
+    }
+
+    //the quote of each feed as received at `received_at`, None for a feed that never answered
+    fn quotes(oracle: &mut Oracle, received_at: &[Option<u128>]) {
+        for (feed, received_at) in oracle.feeds.iter_mut().zip(received_at) {
+            let price = feed.source.fetch().unwrap();
+            feed.last = received_at.map(|received_at| Quote { price, received_at });
+        }
+    }
+
+    fn states(statuses: &[SourceStatus]) -> Vec<SourceState> {
+        statuses.iter().map(|s| s.state).collect()
+    }
+
+    #[test]
+    fn the_median_of_an_even_count_is_the_mean_of_the_middle_two() {
+        assert_eq!(median(&[dec!(1), dec!(2), dec!(7)]), dec!(2));
+        assert_eq!(median(&[dec!(1), dec!(2), dec!(3), dec!(7)]), dec!(2.5));
+        assert_eq!(median(&[dec!(4)]), dec!(4));
+    }
+
+    #[test]
+    fn stale_and_silent_sources_do_not_count() {
+        let now = 100 * SECOND;
+        let mut oracle = oracle(&[dec!(100), dec!(102), dec!(90), dec!(95)]);
+        quotes(&mut oracle, &[Some(now - SECOND), Some(now - 5 * SECOND), Some(now - 6 * SECOND), None]);
+
+        let (index, statuses) = oracle.compute_index(now);
+        assert_eq!(index, Some(dec!(101)));
+        assert_eq!(states(&statuses), [SourceState::Fresh, SourceState::Fresh, SourceState::Stale, SourceState::Error]);
+
+        oracle.config.min_sources = 3;
+        assert_eq!(oracle.compute_index(now).0, None);
+    }
+
+    //first median 101.5, 150 is more than 2% away from it
+    #[test]
+    fn an_outlier_is_dropped_before_the_final_median() {
+        let now = 100 * SECOND;
+        let mut oracle = oracle(&[dec!(100), dec!(150), dec!(101), dec!(102)]);
+        quotes(&mut oracle, &[Some(now); 4]);
+
+        let (index, statuses) = oracle.compute_index(now);
+        assert_eq!(index, Some(dec!(101)));
+        assert_eq!(states(&statuses), [SourceState::Fresh, SourceState::Outlier, SourceState::Fresh, SourceState::Fresh]);
+
+        oracle.config.min_sources = 4;
+        assert_eq!(oracle.compute_index(now).0, None);
+    }
+
+    //index 100: the basis sample is capped at 0.5, then smoothed with alpha 0.1
+    #[test]
+    fn the_basis_is_clamped_then_smoothed() {
+        let mut oracle = oracle(&[dec!(100)]);
+        let marks = oracle.subscribe();
+        assert_eq!(oracle.tick(), Some(dec!(100)));
+
+        *oracle.book.write().unwrap() = BookPrices { impact_bid: Some(dec!(110)), impact_ask: Some(dec!(112)), ..Default::default() };
+        assert_eq!(oracle.tick(), Some(dec!(100.5)));
+        assert_eq!(*marks.borrow(), Some(dec!(100.5)));
+
+        *oracle.book.write().unwrap() = BookPrices { impact_bid: Some(dec!(100.1)), impact_ask: Some(dec!(100.3)), ..Default::default() };
+        assert_eq!(oracle.tick(), Some(dec!(100.47)));
+        *oracle.book.write().unwrap() = BookPrices { impact_bid: Some(dec!(80)), impact_ask: Some(dec!(80)), ..Default::default() };
+        assert_eq!(oracle.tick(), Some(dec!(100.373)));
+
+        let prices = oracle.prices.read().unwrap();
+        assert_eq!((prices.index_price, prices.mark_price, prices.basis), (Some(dec!(100)), Some(dec!(100.373)), dec!(0.373)));
+    }
+}
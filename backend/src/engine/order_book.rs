@@ -1,10 +1,11 @@
-use std::{collections::{BTreeMap, HashMap, VecDeque}, time::{SystemTime, UNIX_EPOCH}};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, VecDeque}, mem, time::{SystemTime, UNIX_EPOCH}};
 
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::types::{ OrderType, Side};
+use crate::types::{ DepthLevel, LevelChange, OrderType, Side, Symbol};
 pub type Price = Decimal;
 pub type OrderId = Uuid;
 pub type UserId = Uuid;
@@ -13,6 +14,7 @@ pub type Quantity = Decimal;
 
 pub struct LimitOrder{
     pub user_id :Uuid,
+    pub symbol : Symbol,
     pub side : Side,
     pub price : Price,
     pub quantity : Quantity,
@@ -20,6 +22,7 @@ pub struct LimitOrder{
 }
 pub struct MarketOrder{
     pub user_id : Uuid,
+    pub symbol : Symbol,
     pub side : Side,
     pub quantity : Quantity,
     pub leverage : Decimal,
@@ -31,9 +34,11 @@ pub struct PriceLevel{
     pub total_qty : Quantity
 }
 
+#[derive(Clone,Copy,Serialize,Deserialize)]
 pub struct Order {
     pub order_id : Uuid,
     pub user_id : Uuid,
+    pub symbol : Symbol,
     pub price : Option<Price>,
     pub leverage : Decimal,
     pub side : Side,
@@ -47,6 +52,7 @@ impl Order {
         Self{
             order_id : Uuid::new_v4(),
             user_id : limit_order.user_id,
+            symbol : limit_order.symbol,
             side : limit_order.side,
             price : Some(limit_order.price),
             quantity : limit_order.quantity,
@@ -59,6 +65,7 @@ impl Order {
         Self{
             order_id : Uuid::new_v4(),
             user_id : market_order.user_id,
+            symbol : market_order.symbol,
             price : None,
             leverage : market_order.leverage,
             side : market_order.side,
@@ -79,11 +86,16 @@ pub struct OrderBook {
    pub user_orders : HashMap<UserId,Vec<OrderId>>,
    pub best_bid : Option<Price>,
    pub best_ask :Option<Price>,
-   pub fill_seq:u64  //sequence numners for fills
+   pub fill_seq:u64,  //sequence numners for fills
+   pub update_id:u64,  //bumped on every price level change
+   published_update_id:u64,  //last update id handed out by take_level_changes
+   changed_bids:BTreeSet<Price>,
+   changed_asks:BTreeSet<Price>
 }
 #[derive(Clone,Copy)]
 pub struct Fill{
     pub seq_no : u64,
+    pub symbol : Symbol,
     pub maker_order_id:OrderId,
     pub taker_order_id:OrderId,
     pub maker_user_id:OrderId,
@@ -94,9 +106,18 @@ pub struct Fill{
     pub maker_leverage : Decimal,
     pub maker_side : Side,
     pub taker_side : Side,
+    pub maker_fee : Decimal,  //signed, < 0 is a rebate
+    pub taker_fee : Decimal,
+    pub fee_asset : Option<&'static str>,  //set by the fee engine, None when nothing was charged
     pub timestamp_: u128
 
 }
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OrderBook{
     pub fn new()->Self{
         Self{
@@ -106,7 +127,11 @@ impl OrderBook{
             user_orders : HashMap::new(),
             best_bid : None,
             best_ask : None,
-            fill_seq : 0
+            fill_seq : 0,
+            update_id : 0,
+            published_update_id : 0,
+            changed_bids : BTreeSet::new(),
+            changed_asks : BTreeSet::new()
         }
     }
     
@@ -131,6 +156,111 @@ impl OrderBook{
     }
    
 
+    fn touch_level(&mut self, side: Side, price: Price){
+        self.update_id += 1;
+        match side {
+            Side::Buy => self.changed_bids.insert(price),
+            Side::Sell => self.changed_asks.insert(price),
+        };
+    }
+
+    //levels changed since the last call as (first update id, last update id, bids, asks) with
+    //the new total per level, 0 when the level is gone. None when nothing changed
+    pub fn take_level_changes(&mut self) -> Option<(u64, u64, Vec<LevelChange>, Vec<LevelChange>)> {
+        if self.update_id == self.published_update_id {
+            return None;
+        }
+        let first = self.published_update_id + 1;
+        self.published_update_id = self.update_id;
+        let change = |book: &BTreeMap<Price,PriceLevel>, price: Price| LevelChange {
+            price: price.normalize(),
+            quantity: book.get(&price).map(|l| l.total_qty.normalize()).unwrap_or_default(),
+        };
+        let bids = mem::take(&mut self.changed_bids).into_iter().rev().map(|p| change(&self.bids, p)).collect();
+        let asks = mem::take(&mut self.changed_asks).into_iter().map(|p| change(&self.asks, p)).collect();
+        Some((first, self.update_id, bids, asks))
+    }
+
+    //best `levels` price levels of each side, bids highest first, asks lowest first
+    pub fn depth(&self, levels: usize) -> (Vec<DepthLevel>, Vec<DepthLevel>) {
+        let level = |l: &PriceLevel| DepthLevel { price: l.price.normalize(), quantity: l.total_qty.normalize(), orders: l.orders.len() };
+        (
+            self.bids.values().rev().take(levels).map(level).collect(),
+            self.asks.values().take(levels).map(level).collect(),
+        )
+    }
+
+    //average price of taking `notional` from the side a `side` order would hit, None if the book is too thin
+    pub fn impact_price(&self, side: Side, notional: Decimal) -> Option<Price> {
+        let levels: Box<dyn Iterator<Item = &PriceLevel>> = match side {
+            Side::Buy => Box::new(self.asks.values()),
+            Side::Sell => Box::new(self.bids.values().rev()),
+        };
+        let mut filled_notional = dec!(0);
+        let mut filled_qty = dec!(0);
+        for level in levels {
+            let level_notional = level.total_qty * level.price;
+            if filled_notional + level_notional >= notional {
+                filled_qty += (notional - filled_notional) / level.price;
+                return (filled_qty > dec!(0)).then(|| (notional / filled_qty).round_dp(8).normalize());
+            }
+            filled_notional += level_notional;
+            filled_qty += level.total_qty;
+        }
+        None
+    }
+
+    //price of the deepest level a `side` market order of `quantity` reaches, the last level when
+    //the side cannot fill all of it, None when it is empty
+    pub fn worst_fill_price(&self, side: Side, quantity: Quantity) -> Option<Price> {
+        let levels: Box<dyn Iterator<Item = &PriceLevel>> = match side {
+            Side::Buy => Box::new(self.asks.values()),
+            Side::Sell => Box::new(self.bids.values().rev()),
+        };
+        let mut left = quantity;
+        let mut worst = None;
+        for level in levels {
+            worst = Some(level.price);
+            left -= level.total_qty;
+            if left <= dec!(0) {
+                break;
+            }
+        }
+        worst
+    }
+
+    //how much of `quantity` a `side` market order can take before paying more than `budget` beyond
+    //`price` in total, and the worst level price it reaches; None when nothing can be taken
+    pub fn coverable_quantity(&self, side: Side, quantity: Quantity, price: Price, budget: Decimal) -> Option<(Quantity, Price)> {
+        let levels: Box<dyn Iterator<Item = &PriceLevel>> = match side {
+            Side::Buy => Box::new(self.asks.values()),
+            Side::Sell => Box::new(self.bids.values().rev()),
+        };
+        let mut left = quantity;
+        let mut budget = budget.max(dec!(0));
+        let mut reached = None;
+        for level in levels {
+            if left <= dec!(0) {
+                break;
+            }
+            let cost_per_unit = match side {
+                Side::Buy => level.price - price,
+                Side::Sell => price - level.price,
+            };
+            let mut take = left.min(level.total_qty);
+            if cost_per_unit > dec!(0) {
+                take = take.min((budget / cost_per_unit).round_dp_with_strategy(8, RoundingStrategy::ToZero));
+                budget -= take * cost_per_unit;
+            }
+            if take <= dec!(0) {
+                break;
+            }
+            left -= take;
+            reached = Some(level.price);
+        }
+        reached.map(|worst| (quantity - left, worst))
+    }
+
     pub fn insert_order (&mut self,order: Order){
         if order.order_type != OrderType::Limit{
             return;
@@ -154,16 +284,17 @@ impl OrderBook{
 
         self.user_orders
             .entry(user_id)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(order_id);
 
         self.orders.insert(order_id,order);
+        self.touch_level(side, price);
         self.update_best_prices();
 
     }
     
     pub fn cancel_order(&mut self, order_id : &OrderId, user_id :&UserId)->Result<Order,String>{
-        let order = self.orders.get(order_id).ok_or("order is not found").unwrap();
+        let order = self.orders.get(order_id).ok_or_else(|| "order is not found".to_string())?;
 
         if &order.user_id != user_id{
             return Err("unauthorized : not owner order".into());
@@ -179,7 +310,7 @@ impl OrderBook{
          //if let is syntactic sugar for a match, not a normal if.
         if let Some(level) =  book.get_mut(&price){
             level.orders.retain(|id|id!=order_id); //retain keep the element where clouser return true
-            level.total_qty -= order.quantity;
+            level.total_qty -= order.remaining();
 
             if level.orders.is_empty() {
                 book.remove(&price);
@@ -188,6 +319,7 @@ impl OrderBook{
         if let Some(user_orders) = self.user_orders.get_mut(user_id){
             user_orders.retain(|id|id!=order_id);
         }
+        self.touch_level(side, price);
         self.update_best_prices();
 
         Ok(order) 
@@ -253,6 +385,7 @@ impl OrderBook{
                 self.fill_seq += 1;
                 fills.push(Fill {
                     seq_no: self.fill_seq,
+                    symbol: taker.symbol,
                     maker_order_id: maker.order_id,
                     taker_order_id: taker.order_id,
                     maker_user_id: maker.user_id,
@@ -263,6 +396,9 @@ impl OrderBook{
                     taker_leverage: taker.leverage,
                     maker_side: maker.side,
                     taker_side: taker.side,
+                    maker_fee: Decimal::ZERO,
+                    taker_fee: Decimal::ZERO,
+                    fee_asset: None,
                     timestamp_: now_nanos(),
                 });
 
@@ -280,14 +416,18 @@ impl OrderBook{
                 if let Some(level) = side.get_mut(&best_price) {
                     level.total_qty -= total_qty_decrease;
                     level.orders.retain(|id| !orders_to_remove.contains(id));
+                    //an emptied level must go, otherwise the next loop picks the same price forever
+                    if level.orders.is_empty() {
+                        side.remove(&best_price);
+                    }
                 }
             }
+            self.touch_level(taker.side.opposite(), best_price);
 
             for id in orders_to_remove {
-                if let Some(order) = self.orders.remove(&id) {
-                    if let Some(list) = self.user_orders.get_mut(&order.user_id) {
-                        list.retain(|x| x != &id);
-                    }
+                if let Some(order) = self.orders.remove(&id)
+                    && let Some(list) = self.user_orders.get_mut(&order.user_id) {
+                    list.retain(|x| x != &id);
                 }
             }
         }
@@ -317,4 +457,6 @@ pub fn now_nanos() -> u128 {
             .as_nanos()
 }
 
-
+pub fn now_millis() -> u64 {
+    (now_nanos() / 1_000_000) as u64
+}
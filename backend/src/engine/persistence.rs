@@ -0,0 +1,190 @@
+// Persistence: orders, their state changes, trades, both sides of every fill, liquidations, ADL
+// and funding payments go to Postgres. The consumer numbers events in pipeline order and hands one batch per
+// drained pipeline batch to a writer task. The writer stores a batch and the consumer's offset in
+// one transaction and retries until it succeeds, so a batch it was handed is stored once; a full
+// channel backs up into the event ring. Events still in the ring or the channel when the process
+// dies are lost, and so is the book: at startup orders left open are expired and numbering
+// continues after the stored offset.
+
+use std::mem;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use db::{AdlRecord, Db, EventBatch, FillRecord, FundingPayment, LiquidationRecord, OrderChange, OrderRecord, OrderTransition, TradeRecord};
+use tokio::sync::mpsc;
+
+use crate::{Adl, Event, EventConsumer, Fill, Funding, Liquidation, Order, OrderId};
+
+pub const PERSISTENCE_CONSUMER: &str = "persistence";
+
+pub struct PersistenceConsumer {
+    next_seq: i64,
+    batch: EventBatch,
+    tx: mpsc::Sender<EventBatch>,
+}
+
+impl PersistenceConsumer {
+    //`last_seq` is the offset the writer stored last
+    pub fn new(last_seq: i64, tx: mpsc::Sender<EventBatch>) -> Self {
+        Self { next_seq: last_seq + 1, batch: EventBatch::default(), tx }
+    }
+
+    fn send(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = mem::take(&mut self.batch);
+        //blocks the pipeline while the writer is behind
+        if self.tx.blocking_send(batch).is_err() {
+            println!(" [PERSIST] writer is gone, events are no longer stored");
+        }
+    }
+
+    fn order(&mut self, seq: i64, order: &Order, status: &str, reason: Option<&str>, timestamp: u128) {
+        self.batch.orders.push(OrderRecord {
+            event_seq: seq,
+            order_id: order.order_id,
+            user_id: order.user_id,
+            symbol: order.symbol.to_string(),
+            side: order.side.as_str().to_string(),
+            order_type: order.order_type.as_str().to_string(),
+            price: order.price,
+            quantity: order.quantity,
+            leverage: order.leverage,
+            status: status.to_string(),
+            reason: reason.map(str::to_string),
+            created_at: at(timestamp),
+        });
+    }
+
+    fn fill(&mut self, seq: i64, fill: &Fill) {
+        let filled_at = at(fill.timestamp_);
+        let fee_asset = fill.fee_asset.map(str::to_string);
+        self.batch.trades.push(TradeRecord {
+            trade_id: seq,
+            symbol: fill.symbol.to_string(),
+            price: fill.price,
+            quantity: fill.quantity,
+            taker_side: fill.taker_side.as_str().to_string(),
+            maker_order_id: fill.maker_order_id,
+            taker_order_id: fill.taker_order_id,
+            traded_at: filled_at,
+        });
+        let sides = [
+            ("maker", fill.maker_user_id, fill.maker_order_id, fill.maker_side, fill.maker_fee),
+            ("taker", fill.taker_user_id, fill.taker_order_id, fill.taker_side, fill.taker_fee),
+        ];
+        for (liquidity, user_id, order_id, side, fee) in sides {
+            self.batch.fills.push(FillRecord {
+                trade_id: seq,
+                user_id,
+                order_id,
+                symbol: fill.symbol.to_string(),
+                side: side.as_str().to_string(),
+                liquidity: liquidity.to_string(),
+                price: fill.price,
+                quantity: fill.quantity,
+                fee,
+                fee_asset: fee_asset.clone(),
+                filled_at,
+            });
+            self.transition(seq, order_id, OrderChange::Fill { quantity: fill.quantity, price: fill.price }, fill.timestamp_);
+        }
+    }
+
+    fn liquidation(&mut self, seq: i64, l: &Liquidation) {
+        self.batch.liquidations.push(LiquidationRecord {
+            event_seq: seq,
+            liquidation_id: l.liquidation_id,
+            user_id: l.user_id,
+            symbol: l.symbol.to_string(),
+            side: l.side.as_str().to_string(),
+            quantity: l.quantity,
+            mark_price: l.mark_price,
+            bankruptcy_price: l.bankruptcy_price,
+            fee: l.fee,
+            liquidated_at: at(l.timestamp),
+        });
+    }
+
+    fn adl(&mut self, seq: i64, adl: &Adl) {
+        self.batch.adl.push(AdlRecord {
+            event_seq: seq,
+            liquidation_id: adl.liquidation_id,
+            user_id: adl.user_id,
+            symbol: adl.symbol.to_string(),
+            side: adl.side.as_str().to_string(),
+            quantity: adl.quantity,
+            price: adl.price,
+            score: adl.score,
+            deleveraged_at: at(adl.timestamp),
+        });
+    }
+
+    fn funding(&mut self, seq: i64, f: &Funding) {
+        self.batch.funding.push(FundingPayment {
+            event_seq: seq,
+            user_id: f.user_id,
+            symbol: f.symbol.to_string(),
+            funding_time: DateTime::from_timestamp_millis(f.funding_time as i64).unwrap_or_default(),
+            position_size: f.position_size,
+            rate: f.rate,
+            amount: f.amount,
+        });
+    }
+
+    fn transition(&mut self, seq: i64, order_id: OrderId, change: OrderChange, timestamp: u128) {
+        self.batch.transitions.push(OrderTransition { event_seq: seq, order_id, change, at: at(timestamp) });
+    }
+}
+
+impl EventConsumer for PersistenceConsumer {
+    fn name(&self) -> &'static str {
+        PERSISTENCE_CONSUMER
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.batch.last_seq = seq;
+        match event {
+            Event::OrderAccepted { order, timestamp } => self.order(seq, order, "new", None, *timestamp),
+            Event::OrderRejected { order, reason, timestamp } => self.order(seq, order, "rejected", Some(reason), *timestamp),
+            Event::Fill(fill) => self.fill(seq, fill),
+            Event::OrderCancelled { order_id, timestamp, .. } => self.transition(seq, *order_id, OrderChange::Cancelled, *timestamp),
+            Event::OrderExpired { order_id, timestamp, .. } => self.transition(seq, *order_id, OrderChange::Expired, *timestamp),
+            Event::Liquidation(l) => self.liquidation(seq, l),
+            Event::Adl(adl) => self.adl(seq, adl),
+            Event::Funding(f) => self.funding(seq, f),
+            _ => {}
+        }
+    }
+
+    fn end_batch(&mut self) {
+        self.send();
+    }
+
+    fn flush(&mut self) {
+        self.send();
+    }
+}
+
+fn at(timestamp: u128) -> DateTime<Utc> {
+    DateTime::from_timestamp_nanos(timestamp as i64)
+}
+
+//stores batches in order; a failed batch is retried, never dropped, later ones wait behind it.
+//ends once the consumer is dropped and everything it sent is stored
+pub fn spawn_persistence(db: Db, mut rx: mpsc::Receiver<EventBatch>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(batch) = rx.recv().await {
+            let mut backoff = Duration::from_millis(100);
+            while let Err(e) = db.persist_events(PERSISTENCE_CONSUMER, &batch).await {
+                println!(" [PERSIST] batch up to event {} failed, retrying in {backoff:?}: {e}", batch.last_seq);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(5));
+            }
+        }
+        println!(" [PERSIST] writer stopped");
+    })
+}
@@ -0,0 +1,381 @@
+// Position engine: folds Fill events into per user/symbol positions.
+// Runs on the event pipeline thread, HTTP readers share the state through an RwLock.
+// At startup positions and their margin are replayed from the stored events, see replay_positions.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, RwLock};
+
+use db::{JournalEntry, funding_entry};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{Event, EventConsumer, Fill, LIQUIDATION_ACCOUNT, Price, Quantity, SETTLEMENT_ASSET, SharedWallet, Side, Symbol, UserId, WalletEngine, fill_entries, pnl_entry};
+
+#[derive(Clone, Copy, Serialize)]
+pub struct Position {
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub size: Quantity,          //signed: > 0 long, < 0 short
+    pub entry_price: Price,      //volume-weighted over the open size, 0 when flat
+    pub realized_pnl: Decimal,   //lifetime, for this user/symbol
+    pub leverage: Decimal,       //leverage of the last fill that touched the position
+    pub updated_at: u128,
+}
+
+impl Position {
+    fn flat(user_id: UserId, symbol: Symbol) -> Self {
+        Self {
+            user_id,
+            symbol,
+            size: Decimal::ZERO,
+            entry_price: Decimal::ZERO,
+            realized_pnl: Decimal::ZERO,
+            leverage: Decimal::ONE,
+            updated_at: 0,
+        }
+    }
+
+    pub fn side(&self) -> Option<Side> {
+        if self.size > Decimal::ZERO {
+            Some(Side::Buy)
+        } else if self.size < Decimal::ZERO {
+            Some(Side::Sell)
+        } else {
+            None
+        }
+    }
+
+    pub fn notional(&self, price: Price) -> Decimal {
+        self.size.abs() * price
+    }
+
+    pub fn unrealized_pnl(&self, mark: Price) -> Decimal {
+        self.size * (mark - self.entry_price)
+    }
+}
+
+//one side of a fill (or any other trade that moves a position)
+#[derive(Clone, Copy)]
+pub struct PositionTrade {
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub quantity: Quantity,
+    pub price: Price,
+    pub leverage: Decimal,
+    pub timestamp: u128,
+}
+
+impl PositionTrade {
+    pub fn maker(fill: &Fill) -> Self {
+        Self {
+            user_id: fill.maker_user_id,
+            symbol: fill.symbol,
+            side: fill.maker_side,
+            quantity: fill.quantity,
+            price: fill.price,
+            leverage: fill.maker_leverage,
+            timestamp: fill.timestamp_,
+        }
+    }
+
+    pub fn taker(fill: &Fill) -> Self {
+        Self {
+            user_id: fill.taker_user_id,
+            symbol: fill.symbol,
+            side: fill.taker_side,
+            quantity: fill.quantity,
+            price: fill.price,
+            leverage: fill.taker_leverage,
+            timestamp: fill.timestamp_,
+        }
+    }
+}
+
+//what a single trade did to one side's position
+#[derive(Clone, Copy)]
+pub struct PositionChange {
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub closed_qty: Quantity,
+    pub opened_qty: Quantity,
+    pub realized_pnl: Decimal,
+    pub size_before: Quantity,
+    pub size_after: Quantity,
+}
+
+#[derive(Default, Clone)]
+pub struct PositionEngine {
+    positions: HashMap<(UserId, Symbol), Position>,
+}
+
+impl PositionEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //returns the change for the maker and for the taker, in that order
+    pub fn apply_fill(&mut self, fill: &Fill) -> [PositionChange; 2] {
+        let maker = self.apply(PositionTrade::maker(fill));
+        let taker = self.apply(PositionTrade::taker(fill));
+        [maker, taker]
+    }
+
+    pub fn apply(&mut self, trade: PositionTrade) -> PositionChange {
+        let PositionTrade { user_id, symbol, side, quantity: qty, price, leverage, timestamp } = trade;
+        let position = self
+            .positions
+            .entry((user_id, symbol))
+            .or_insert_with(|| Position::flat(user_id, symbol));
+
+        let signed_qty = match side {
+            Side::Buy => qty,
+            Side::Sell => -qty,
+        };
+        let size_before = position.size;
+        let mut closed_qty = Decimal::ZERO;
+        let mut realized = Decimal::ZERO;
+
+        if size_before.is_zero() || size_before.is_sign_positive() == signed_qty.is_sign_positive() {
+            //opening or adding: weighted entry over the combined size
+            let new_size = size_before + signed_qty;
+            position.entry_price = (size_before.abs() * position.entry_price + qty * price) / new_size.abs();
+            position.size = new_size;
+        } else {
+            //reducing, closing or flipping
+            closed_qty = qty.min(size_before.abs());
+            realized = if size_before.is_sign_positive() {
+                closed_qty * (price - position.entry_price)
+            } else {
+                closed_qty * (position.entry_price - price)
+            };
+            let new_size = size_before + signed_qty;
+
+            if new_size.is_zero() {
+                position.entry_price = Decimal::ZERO;
+            } else if new_size.is_sign_positive() != size_before.is_sign_positive() {
+                //flipped: what is left was opened at this fill's price
+                position.entry_price = price;
+            }
+            position.size = new_size;
+            position.realized_pnl += realized;
+        }
+
+        position.leverage = leverage;
+        position.updated_at = timestamp;
+
+        PositionChange {
+            user_id,
+            symbol,
+            closed_qty,
+            opened_qty: qty - closed_qty,
+            realized_pnl: realized,
+            size_before,
+            size_after: position.size,
+        }
+    }
+
+    //`quantity` of the user's `side` position moves to the liquidation account at `price`, at the
+    //user's leverage: a takeover on liquidation, or the liquidation account closing against an
+    //ADL'd position. Returns the user's and the liquidation account's change, and the leverage
+    pub fn transfer(&mut self, user_id: UserId, symbol: Symbol, side: Side, quantity: Quantity, price: Price, timestamp: u128) -> ([PositionChange; 2], Decimal) {
+        let leverage = self.position(&user_id, &symbol).map(|p| p.leverage).unwrap_or(Decimal::ONE);
+        let closed = self.apply(PositionTrade { user_id, symbol, side: side.opposite(), quantity, price, leverage, timestamp });
+        let taken = self.apply(PositionTrade { user_id: LIQUIDATION_ACCOUNT, symbol, side, quantity, price, leverage, timestamp });
+        ([closed, taken], leverage)
+    }
+
+    pub fn position(&self, user_id: &UserId, symbol: &Symbol) -> Option<&Position> {
+        self.positions.get(&(*user_id, *symbol))
+    }
+
+    //open positions only
+    pub fn user_positions(&self, user_id: &UserId) -> Vec<Position> {
+        self.positions
+            .values()
+            .filter(|p| &p.user_id == user_id && !p.size.is_zero())
+            .copied()
+            .collect()
+    }
+
+    pub fn open_positions(&self) -> impl Iterator<Item = &Position> {
+        self.positions.values().filter(|p| !p.size.is_zero())
+    }
+}
+
+pub type SharedPositions = Arc<RwLock<PositionEngine>>;
+
+//a stored event that moved a position or its margin, see replay_positions
+pub enum PositionReplay {
+    Trade(PositionTrade),
+    Transfer { user_id: UserId, symbol: Symbol, side: Side, quantity: Quantity, price: Price, timestamp: u128 },
+    Funding { user_id: UserId, symbol: Symbol, amount: Decimal },
+    MarginAdjustment { user_id: UserId, symbol: Symbol, amount: Decimal },
+}
+
+//startup: does what the position consumer did for every stored event, in event order, on a fresh
+//position engine. `wallet` gets the margin side (it needs the margin modes first); its balances
+//mean nothing, the real ones come from the ledger
+pub fn replay_positions(events: impl IntoIterator<Item = PositionReplay>, wallet: &mut WalletEngine) -> PositionEngine {
+    let mut positions = PositionEngine::new();
+    for event in events {
+        match event {
+            PositionReplay::Trade(trade) => {
+                let change = positions.apply(trade);
+                wallet.apply_position_change(&change, trade.price, trade.leverage);
+            }
+            PositionReplay::Transfer { user_id, symbol, side, quantity, price, timestamp } => {
+                let (changes, leverage) = positions.transfer(user_id, symbol, side, quantity, price, timestamp);
+                for change in &changes {
+                    wallet.apply_position_change(change, price, leverage);
+                }
+            }
+            PositionReplay::Funding { user_id, symbol, amount } => wallet.apply_funding(user_id, symbol, amount),
+            PositionReplay::MarginAdjustment { user_id, symbol, amount } => wallet.restore_isolated_margin(user_id, symbol, amount),
+        }
+    }
+    positions
+}
+
+//positions first, then the wallet settles margin and PnL from the resulting change;
+//the fees, PnL and funding it books are posted to the ledger once per batch
+pub struct PositionConsumer {
+    positions: SharedPositions,
+    wallet: SharedWallet,
+    entries: Vec<JournalEntry>,
+    ledger_tx: mpsc::Sender<Vec<JournalEntry>>,
+}
+
+impl PositionConsumer {
+    pub fn new(positions: SharedPositions, wallet: SharedWallet, ledger_tx: mpsc::Sender<Vec<JournalEntry>>) -> Self {
+        Self { positions, wallet, entries: Vec::new(), ledger_tx }
+    }
+
+    fn send(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+        //blocks the pipeline while the writer is behind
+        if self.ledger_tx.blocking_send(mem::take(&mut self.entries)).is_err() {
+            println!(" [LEDGER] writer is gone, fees and PnL are no longer posted");
+        }
+    }
+}
+
+impl PositionConsumer {
+    //see PositionEngine::transfer, the wallet moves the margin of both sides
+    fn transfer(&mut self, user_id: UserId, symbol: Symbol, side: Side, quantity: Quantity, price: Price, timestamp: u128) -> [PositionChange; 2] {
+        let (changes, leverage) = self.positions.write().unwrap_or_else(|e| e.into_inner()).transfer(user_id, symbol, side, quantity, price, timestamp);
+        let mut wallet = self.wallet.lock().unwrap_or_else(|e| e.into_inner());
+        for change in &changes {
+            wallet.apply_position_change(change, price, leverage);
+        }
+        changes
+    }
+
+    fn post_pnl(&mut self, reference: &str, changes: &[PositionChange; 2]) {
+        for change in changes {
+            pnl_entry(&format!("{reference}:{}", change.user_id), change, &mut self.entries);
+        }
+    }
+}
+
+impl EventConsumer for PositionConsumer {
+    fn name(&self) -> &'static str {
+        "position-engine"
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        match event {
+            Event::Fill(fill) => {
+                //a poisoned lock means a reader panicked, the data itself is still consistent
+                let changes = self.positions.write().unwrap_or_else(|e| e.into_inner()).apply_fill(fill);
+                self.wallet.lock().unwrap_or_else(|e| e.into_inner()).apply_fill(fill, &changes);
+                fill_entries(fill, &changes, &mut self.entries);
+            }
+            Event::Liquidation(l) => {
+                let changes = self.transfer(l.user_id, l.symbol, l.side, l.quantity, l.bankruptcy_price, l.timestamp);
+                self.post_pnl(&format!("liquidation:{}", l.liquidation_id), &changes);
+            }
+            Event::Adl(adl) => {
+                let changes = self.transfer(adl.user_id, adl.symbol, adl.side, adl.quantity, adl.price, adl.timestamp);
+                self.post_pnl(&format!("adl:{}:{}", adl.liquidation_id, adl.user_id), &changes);
+            }
+            Event::Funding(f) => {
+                self.wallet.lock().unwrap_or_else(|e| e.into_inner()).apply_funding(f.user_id, f.symbol, f.amount);
+                let reference = format!("funding:{}:{}:{}", f.symbol, f.funding_time, f.user_id);
+                self.entries.push(funding_entry(&reference, f.user_id, SETTLEMENT_ASSET, f.amount));
+            }
+            _ => {}
+        }
+    }
+
+    fn end_batch(&mut self) {
+        self.send();
+    }
+
+    fn flush(&mut self) {
+        self.send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use crate::MarginMode;
+
+    use super::*;
+
+    fn trade(user_id: UserId, side: Side, quantity: Quantity, price: Price, leverage: Decimal) -> PositionReplay {
+        let symbol = Symbol::new("BTC-PERP").unwrap();
+        PositionReplay::Trade(PositionTrade { user_id, symbol, side, quantity, price, leverage, timestamp: 0 })
+    }
+
+    #[test]
+    fn replay_rebuilds_positions_and_margin() {
+        let symbol = Symbol::new("BTC-PERP").unwrap();
+        let (isolated, cross) = (Uuid::from_u128(10), Uuid::from_u128(20));
+        let mut wallet = WalletEngine::new();
+        wallet.restore_margin_modes(&[(isolated, symbol, MarginMode::Isolated)]);
+
+        let events = [
+            trade(cross, Side::Sell, dec!(2), dec!(100), dec!(5)),
+            trade(isolated, Side::Buy, dec!(2), dec!(100), dec!(10)),
+            PositionReplay::MarginAdjustment { user_id: isolated, symbol, amount: dec!(5) },
+            PositionReplay::Funding { user_id: isolated, symbol, amount: dec!(-1) },
+            PositionReplay::Funding { user_id: cross, symbol, amount: dec!(1) },
+            //the short is taken over at its bankruptcy price, at its own leverage
+            PositionReplay::Transfer { user_id: cross, symbol, side: Side::Sell, quantity: dec!(2), price: dec!(120), timestamp: 0 },
+        ];
+        let positions = replay_positions(events, &mut wallet);
+
+        let long = positions.position(&isolated, &symbol).unwrap();
+        assert_eq!((long.size, long.entry_price), (dec!(2), dec!(100)));
+        assert!(positions.user_positions(&cross).is_empty());
+        let taken = positions.position(&LIQUIDATION_ACCOUNT, &symbol).unwrap();
+        assert_eq!((taken.size, taken.entry_price, taken.leverage), (dec!(-2), dec!(120), dec!(5)));
+
+        //20 initial + 5 added - 1 funding; the cross margin is gone with the position
+        assert_eq!(wallet.account(&isolated).unwrap().position_margin(&symbol), dec!(24));
+        assert!(wallet.account(&cross).unwrap().position_margin.is_empty());
+        assert_eq!(wallet.account(&LIQUIDATION_ACCOUNT).unwrap().position_margin(&symbol), dec!(48));
+    }
+
+    #[test]
+    fn restored_margin_leaves_balances_alone() {
+        let user = Uuid::from_u128(10);
+        let mut replayed = WalletEngine::new();
+        replay_positions([trade(user, Side::Buy, dec!(1), dec!(100), dec!(10))], &mut replayed);
+
+        let mut wallet = WalletEngine::new();
+        wallet.credit(user, dec!(50));
+        wallet.restore_position_margin(&replayed);
+        let view = wallet.balance_view(&user);
+        assert_eq!((view.balance, view.position_margin, view.available), (dec!(50), dec!(10), dec!(40)));
+    }
+}
+
@@ -0,0 +1,106 @@
+// Where the oracle gets external prices from. Real exchange connectors are not wired in yet,
+// the two implementations stand in for them: a CSV replay and a plain HTTP poller.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use crate::Price;
+
+pub trait PriceSource: Send {
+    fn name(&self) -> &str;
+    //latest price, called once per oracle tick
+    fn fetch(&mut self) -> Result<Price, String>;
+}
+
+//replays a recorded feed, one row per tick, from the start again at the end.
+//rows are `price` or `timestamp,price`: the last column is used, lines that do not parse are skipped
+pub struct FileReplaySource {
+    name: String,
+    prices: Vec<Price>,
+    next: usize,
+}
+
+impl FileReplaySource {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let prices: Vec<Price> = content
+            .lines()
+            .filter_map(|line| line.rsplit(',').next()?.trim().parse().ok())
+            .collect();
+        if prices.is_empty() {
+            return Err(format!("{}: no prices found", path.display()));
+        }
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("replay");
+        Ok(Self { name: format!("file:{stem}"), prices, next: 0 })
+    }
+}
+
+impl PriceSource for FileReplaySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&mut self) -> Result<Price, String> {
+        let price = self.prices[self.next];
+        self.next = (self.next + 1) % self.prices.len();
+        Ok(price)
+    }
+}
+
+//polls a url returning either a bare number or json like {"price": "50000.5"}
+pub struct HttpSource {
+    name: String,
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpSource {
+    pub fn new(url: &str, timeout: Duration) -> Self {
+        let agent = ureq::Agent::config_builder().timeout_global(Some(timeout)).build().into();
+        Self { name: format!("http:{url}"), url: url.to_string(), agent }
+    }
+}
+
+impl PriceSource for HttpSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&mut self) -> Result<Price, String> {
+        let body = self
+            .agent
+            .get(&self.url)
+            .call()
+            .map_err(|e| e.to_string())?
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| e.to_string())?;
+        parse_price(&body).ok_or_else(|| format!("unexpected response: {}", body.trim()))
+    }
+}
+
+fn parse_price(body: &str) -> Option<Price> {
+    if let Ok(price) = body.trim().parse::<Decimal>() {
+        return Some(price);
+    }
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    match json.get("price").unwrap_or(&json) {
+        serde_json::Value::String(s) => s.parse().ok(),
+        serde_json::Value::Number(n) => n.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+//ORACLE_SOURCES entries: `file:<path>` or an http(s) url
+pub fn source_from_spec(spec: &str, timeout: Duration) -> Result<Box<dyn PriceSource>, String> {
+    if let Some(path) = spec.strip_prefix("file:") {
+        return Ok(Box::new(FileReplaySource::open(Path::new(path))?));
+    }
+    if spec.starts_with("http://") || spec.starts_with("https://") {
+        return Ok(Box::new(HttpSource::new(spec, timeout)));
+    }
+    Err(format!("unknown price source {spec}"))
+}
@@ -0,0 +1,277 @@
+// Engine-side scheduling of commands by priority.
+// Every command drained from the ingress ring lands in the lane for its Priority and
+// keeps its arrival sequence. Lanes live across batches, so:
+//  - higher lanes go first, but a waiting lane that was passed over `aging_limit` times
+//    gets the next slot (Low traffic is never starved)
+//  - a user's commands always run in arrival order: before dispatching a command, any
+//    earlier command of the same user (in whatever lane) is dispatched first
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::{OrderBookMessage, Priority, UserId, now_nanos};
+
+pub const LANES: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LaneConfig {
+    //how many dispatches from higher lanes a non-empty lane tolerates before it is served
+    pub aging_limit: [u32; LANES],
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        Self {
+            aging_limit: [u32::MAX, 32, 64, 128],  //Critical is never passed over
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LaneCounters {
+    enqueued: AtomicU64,
+    dispatched: AtomicU64,
+    aged: AtomicU64,           //served because of aging rather than priority
+    user_promoted: AtomicU64,  //pulled forward so a later command of the same user could run
+    depth: AtomicU64,
+    max_wait_ns: AtomicU64,
+    total_wait_ns: AtomicU64,
+}
+
+#[derive(Default)]
+pub struct LaneStats {
+    lanes: [LaneCounters; LANES],
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LaneSnapshot {
+    pub priority: Priority,
+    pub enqueued: u64,
+    pub dispatched: u64,
+    pub aged: u64,
+    pub user_promoted: u64,
+    pub depth: u64,
+    pub max_wait_ns: u64,
+    pub avg_wait_ns: u64,
+}
+
+impl LaneStats {
+    pub fn snapshot(&self) -> Vec<LaneSnapshot> {
+        self.lanes
+            .iter()
+            .enumerate()
+            .map(|(lane, c)| {
+                let dispatched = c.dispatched.load(Ordering::Relaxed);
+                LaneSnapshot {
+                    priority: Priority::from_lane(lane),
+                    enqueued: c.enqueued.load(Ordering::Relaxed),
+                    dispatched,
+                    aged: c.aged.load(Ordering::Relaxed),
+                    user_promoted: c.user_promoted.load(Ordering::Relaxed),
+                    depth: c.depth.load(Ordering::Relaxed),
+                    max_wait_ns: c.max_wait_ns.load(Ordering::Relaxed),
+                    avg_wait_ns: c.total_wait_ns.load(Ordering::Relaxed).checked_div(dispatched).unwrap_or(0),
+                }
+            })
+            .collect()
+    }
+}
+
+struct Queued {
+    seq: u64,
+    user_id: Option<UserId>,
+    enqueued_at: u128,
+    cmd: OrderBookMessage,
+}
+
+pub struct PriorityLanes {
+    lanes: [VecDeque<Queued>; LANES],
+    skipped: [u32; LANES],
+    pending_by_user: HashMap<UserId, VecDeque<u64>>,  //queued seqs per user, oldest first
+    next_seq: u64,
+    config: LaneConfig,
+    stats: Arc<LaneStats>,
+}
+
+impl PriorityLanes {
+    pub fn new(config: LaneConfig) -> Self {
+        Self {
+            lanes: Default::default(),
+            skipped: [0; LANES],
+            pending_by_user: HashMap::new(),
+            next_seq: 0,
+            config,
+            stats: Arc::new(LaneStats::default()),
+        }
+    }
+
+    pub fn set_config(&mut self, config: LaneConfig) {
+        self.config = config;
+    }
+
+    pub fn stats(&self) -> Arc<LaneStats> {
+        self.stats.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(|lane| lane.is_empty())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(|lane| lane.len()).sum()
+    }
+
+    pub fn push(&mut self, cmd: OrderBookMessage) {
+        let lane = cmd.priority() as usize;
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let user_id = cmd.user_id();
+        if let Some(user_id) = user_id {
+            self.pending_by_user.entry(user_id).or_default().push_back(seq);
+        }
+        self.lanes[lane].push_back(Queued { seq, user_id, enqueued_at: now_nanos(), cmd });
+
+        let counters = &self.stats.lanes[lane];
+        counters.enqueued.fetch_add(1, Ordering::Relaxed);
+        counters.depth.store(self.lanes[lane].len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn pop(&mut self) -> Option<OrderBookMessage> {
+        let lane = self.pick_lane()?;
+        let mut queued = self.lanes[lane].pop_front()?;
+        let mut from_lane = lane;
+
+        //an older command of the same user is still queued somewhere: it has to run first
+        if let Some(user_id) = queued.user_id
+            && let Some(&oldest) = self.pending_by_user.get(&user_id).and_then(|seqs| seqs.front())
+            && oldest != queued.seq
+            && let Some((older_lane, pos)) = self.find(oldest)
+        {
+            self.lanes[lane].push_front(queued);
+            queued = self.lanes[older_lane].remove(pos)?;
+            from_lane = older_lane;
+            self.stats.lanes[older_lane].user_promoted.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(user_id) = queued.user_id
+            && let Some(seqs) = self.pending_by_user.get_mut(&user_id)
+        {
+            seqs.pop_front();
+            if seqs.is_empty() {
+                self.pending_by_user.remove(&user_id);
+            }
+        }
+
+        let counters = &self.stats.lanes[from_lane];
+        let waited = now_nanos().saturating_sub(queued.enqueued_at) as u64;
+        counters.dispatched.fetch_add(1, Ordering::Relaxed);
+        counters.total_wait_ns.fetch_add(waited, Ordering::Relaxed);
+        counters.max_wait_ns.fetch_max(waited, Ordering::Relaxed);
+        for (lane, queue) in self.lanes.iter().enumerate() {
+            self.stats.lanes[lane].depth.store(queue.len() as u64, Ordering::Relaxed);
+        }
+
+        Some(queued.cmd)
+    }
+
+    //highest non-empty lane, unless a lower one has waited past its aging limit
+    fn pick_lane(&mut self) -> Option<usize> {
+        let top = (0..LANES).find(|&lane| !self.lanes[lane].is_empty())?;
+        let aged = (top + 1..LANES)
+            .find(|&lane| !self.lanes[lane].is_empty() && self.skipped[lane] >= self.config.aging_limit[lane]);
+        let chosen = aged.unwrap_or(top);
+
+        if aged.is_some() {
+            self.stats.lanes[chosen].aged.fetch_add(1, Ordering::Relaxed);
+        }
+        self.skipped[chosen] = 0;
+        for lane in chosen + 1..LANES {
+            if !self.lanes[lane].is_empty() {
+                self.skipped[lane] = self.skipped[lane].saturating_add(1);
+            }
+        }
+        Some(chosen)
+    }
+
+    fn find(&self, seq: u64) -> Option<(usize, usize)> {
+        self.lanes.iter().enumerate().find_map(|(lane, queue)| {
+            //seqs are increasing inside a lane
+            queue.binary_search_by_key(&seq, |q| q.seq).ok().map(|pos| (lane, pos))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{Order, OrderType, Side, Symbol};
+
+    fn mark_price() -> OrderBookMessage {
+        OrderBookMessage::UpdateMarkPrice { price: Decimal::ONE_HUNDRED }
+    }
+
+    fn cancel(user_id: UserId) -> OrderBookMessage {
+        OrderBookMessage::CancelOrder { order_id: Uuid::new_v4(), user_id, responder: None }
+    }
+
+    fn place(user_id: UserId) -> OrderBookMessage {
+        let order = Order {
+            order_id: Uuid::new_v4(),
+            user_id,
+            symbol: Symbol::new("BTC-PERP").unwrap(),
+            price: Some(Decimal::ONE_HUNDRED),
+            leverage: Decimal::ONE,
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            quantity: Decimal::ONE,
+            filled: Decimal::ZERO,
+        };
+        OrderBookMessage::PlaceOrder { order, priority: Priority::Low, responder: None }
+    }
+
+    #[test]
+    fn higher_lane_goes_first() {
+        let mut lanes = PriorityLanes::new(LaneConfig::default());
+        lanes.push(place(Uuid::new_v4()));
+        lanes.push(mark_price());
+        assert_eq!(lanes.pop().unwrap().priority(), Priority::Critical);
+        assert_eq!(lanes.pop().unwrap().priority(), Priority::Low);
+        assert!(lanes.pop().is_none());
+    }
+
+    #[test]
+    fn aged_lane_is_promoted() {
+        let mut lanes = PriorityLanes::new(LaneConfig { aging_limit: [u32::MAX, 32, 64, 2] });
+        lanes.push(place(Uuid::new_v4()));
+        for _ in 0..5 {
+            lanes.push(mark_price());
+        }
+        let order: Vec<_> = std::iter::from_fn(|| lanes.pop()).map(|cmd| cmd.priority()).collect();
+        assert_eq!(order[..3], [Priority::Critical, Priority::Critical, Priority::Low]);
+        assert!(order[3..].iter().all(|&p| p == Priority::Critical));
+        assert_eq!(lanes.stats().snapshot()[Priority::Low as usize].aged, 1);
+    }
+
+    #[test]
+    fn user_commands_keep_arrival_order() {
+        let (user, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut lanes = PriorityLanes::new(LaneConfig::default());
+        lanes.push(place(user));
+        lanes.push(place(other));
+        lanes.push(cancel(user));
+
+        //the cancel is picked first, but the user's earlier order has to run before it
+        assert!(matches!(lanes.pop(), Some(OrderBookMessage::PlaceOrder { order, .. }) if order.user_id == user));
+        assert!(matches!(lanes.pop(), Some(OrderBookMessage::CancelOrder { user_id, .. }) if user_id == user));
+        assert!(matches!(lanes.pop(), Some(OrderBookMessage::PlaceOrder { order, .. }) if order.user_id == other));
+        assert!(lanes.is_empty());
+        assert_eq!(lanes.stats().snapshot()[Priority::Low as usize].user_promoted, 1);
+    }
+}
@@ -38,7 +38,23 @@ impl<T> RingBuffer<T> {
         }
     }
   
+    //number of unread items (one slot is always kept empty, so at most capacity - 1)
+    pub fn len(&self)->usize{
+        let read = self.read_idx.0.load(Ordering::Acquire);
+        let write = self.write_idx.0.load(Ordering::Acquire);
+        write.wrapping_sub(read) & self.mask
+    }
+
+    pub fn is_empty(&self)->bool{
+        self.len() == 0
+    }
+
     pub fn push(&self,item:T)->bool{
+        self.try_push(item).is_ok()
+    }
+
+    //same as push but hands the item back when the ring is full, so the caller can keep it
+    pub fn try_push(&self,item:T)->Result<(),T>{
         let write = self.write_idx.0.load(Ordering::Relaxed);
         let read = self.read_idx.0.load(Ordering::Acquire);  //why accquire here (Acquiew ensure Producer sees all consumer-side memory writes before the consumer updated read_idx.)
         //Consumer updates read_idx with Release
@@ -46,7 +62,7 @@ impl<T> RingBuffer<T> {
         let next_write = (write+1) & self.mask;
 
         if next_write == read {  //Cannot overwrite unread data.
-            return false;
+            return Err(item);
         }
 
         unsafe {  //happens before publishng write index
@@ -55,7 +71,7 @@ impl<T> RingBuffer<T> {
             ptr.write(item);
         }
         self.write_idx.0.store(next_write,Ordering::Release);  //Publish the new write index (Release)
-        true
+        Ok(())
     }
 
     pub fn push_spin(&mut self,item : T,max_spins: usize)->bool
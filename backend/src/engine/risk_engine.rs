@@ -0,0 +1,270 @@
+// Pre-trade risk checks, run on the matching thread between validation and matching.
+// Everything the checks read is kept here and updated from what the engine itself does
+// (rests, fills, cancels), so the outcome only depends on the command sequence.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::{AccountTier, Fill, LeverageBrackets, Order, OrderId, OrderType, Price, Quantity, RiskRejectCode, RiskRejection, Side, Symbol, UserId};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RiskLimits {
+    pub max_open_orders: usize,
+    pub max_position_size: Quantity,      //absolute net size per symbol, after the order fills completely
+    pub max_order_notional: Decimal,
+    pub max_resting_notional: Decimal,    //sum over the user's resting orders, incl. the new one if it rests
+}
+
+#[derive(Debug, Clone)]
+pub struct RiskConfig {
+    pub standard: RiskLimits,
+    pub pro: RiskLimits,
+    pub institutional: RiskLimits,
+    pub tiers: HashMap<UserId, AccountTier>,  //users not listed are Standard
+    pub brackets: LeverageBrackets,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            standard: RiskLimits {
+                max_open_orders: 200,
+                max_position_size: dec!(100),
+                max_order_notional: dec!(1_000_000),
+                max_resting_notional: dec!(5_000_000),
+            },
+            pro: RiskLimits {
+                max_open_orders: 1_000,
+                max_position_size: dec!(1_000),
+                max_order_notional: dec!(10_000_000),
+                max_resting_notional: dec!(50_000_000),
+            },
+            institutional: RiskLimits {
+                max_open_orders: 5_000,
+                max_position_size: dec!(10_000),
+                max_order_notional: dec!(100_000_000),
+                max_resting_notional: dec!(500_000_000),
+            },
+            tiers: HashMap::new(),
+            brackets: LeverageBrackets::default(),
+        }
+    }
+}
+
+impl RiskConfig {
+    pub fn limits(&self, tier: AccountTier) -> &RiskLimits {
+        match tier {
+            AccountTier::Standard => &self.standard,
+            AccountTier::Pro => &self.pro,
+            AccountTier::Institutional => &self.institutional,
+        }
+    }
+}
+
+struct RestingOrder {
+    user_id: UserId,
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    remaining: Quantity,
+}
+
+#[derive(Default)]
+struct UserExposure {
+    open_orders: usize,
+    resting_notional: Decimal,
+    resting_qty: HashMap<(Symbol, Side), Quantity>,
+    positions: HashMap<Symbol, Quantity>,  //signed net size
+}
+
+impl UserExposure {
+    fn add_resting(&mut self, order: &RestingOrder, quantity: Quantity) {
+        let resting = self.resting_qty.entry((order.symbol, order.side)).or_default();
+        *resting += quantity;
+        if *resting <= Decimal::ZERO {
+            self.resting_qty.remove(&(order.symbol, order.side));
+        }
+    }
+}
+
+pub struct RiskEngine {
+    config: RiskConfig,
+    resting: HashMap<OrderId, RestingOrder>,
+    users: HashMap<UserId, UserExposure>,
+}
+
+impl RiskEngine {
+    pub fn new(config: RiskConfig) -> Self {
+        Self {
+            config,
+            resting: HashMap::new(),
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn tier(&self, user_id: &UserId) -> AccountTier {
+        self.config.tiers.get(user_id).copied().unwrap_or_default()
+    }
+
+    pub fn limits(&self, user_id: &UserId) -> &RiskLimits {
+        self.config.limits(self.tier(user_id))
+    }
+
+    //`price` is the limit price, or for market orders the worst price their sweep reaches
+    pub fn check(&self, order: &Order, price: Price) -> Result<(), RiskRejection> {
+        let limits = self.limits(&order.user_id);
+        let exposure = self.users.get(&order.user_id);
+        let notional = order.quantity * price;
+
+        if notional > limits.max_order_notional {
+            return Err(RiskRejection {
+                code: RiskRejectCode::MaxOrderNotional,
+                detail: format!("order notional {notional} exceeds {}", limits.max_order_notional),
+            });
+        }
+
+        let position = exposure.and_then(|e| e.positions.get(&order.symbol)).copied().unwrap_or_default();
+        let after = match order.side {
+            Side::Buy => position + order.quantity,
+            Side::Sell => position - order.quantity,
+        };
+        //reducing is always allowed, only growth beyond the limit is refused
+        if after.abs() > limits.max_position_size && after.abs() > position.abs() {
+            return Err(RiskRejection {
+                code: RiskRejectCode::MaxPositionSize,
+                detail: format!("position {after} on {} exceeds {}", order.symbol, limits.max_position_size),
+            });
+        }
+        //leverage is capped by the bracket the grown position lands in, with the user's resting
+        //orders on the same side filled as well
+        if after.abs() > position.abs() {
+            let resting = exposure.and_then(|e| e.resting_qty.get(&(order.symbol, order.side))).copied().unwrap_or_default();
+            let exposed = match order.side {
+                Side::Buy => after + resting,
+                Side::Sell => after - resting,
+            };
+            let position_notional = exposed.abs() * price;
+            let bracket = self.config.brackets.table(&order.symbol).bracket(position_notional);
+            if order.leverage > bracket.max_leverage {
+                return Err(RiskRejection {
+                    code: RiskRejectCode::MaxLeverage,
+                    detail: format!(
+                        "position notional {position_notional} is in bracket {}, max leverage {}x",
+                        bracket.bracket, bracket.max_leverage
+                    ),
+                });
+            }
+        }
+
+        //market orders never rest
+        if order.order_type == OrderType::Limit {
+            let open_orders = exposure.map(|e| e.open_orders).unwrap_or(0);
+            if open_orders >= limits.max_open_orders {
+                return Err(RiskRejection {
+                    code: RiskRejectCode::MaxOpenOrders,
+                    detail: format!("{open_orders} open orders, limit {}", limits.max_open_orders),
+                });
+            }
+            let resting = exposure.map(|e| e.resting_notional).unwrap_or_default() + notional;
+            if resting > limits.max_resting_notional {
+                return Err(RiskRejection {
+                    code: RiskRejectCode::MaxRestingNotional,
+                    detail: format!("resting notional {resting} exceeds {}", limits.max_resting_notional),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    //the unfilled part of a limit order went into the book
+    pub fn on_rest(&mut self, order_id: OrderId, order: &Order, price: Price, remaining: Quantity) {
+        let resting = RestingOrder { user_id: order.user_id, symbol: order.symbol, side: order.side, price, remaining };
+        let exposure = self.users.entry(order.user_id).or_default();
+        exposure.open_orders += 1;
+        exposure.resting_notional += remaining * price;
+        exposure.add_resting(&resting, remaining);
+        self.resting.insert(order_id, resting);
+    }
+
+    //position side of any trade, fills as well as takeovers and deleveraging
+    pub fn on_trade(&mut self, user_id: UserId, symbol: Symbol, side: Side, quantity: Quantity) {
+        let positions = &mut self.users.entry(user_id).or_default().positions;
+        let position = positions.entry(symbol).or_default();
+        match side {
+            Side::Buy => *position += quantity,
+            Side::Sell => *position -= quantity,
+        }
+        if position.is_zero() {
+            positions.remove(&symbol);
+        }
+    }
+
+    pub fn on_fill(&mut self, fill: &Fill) {
+        self.on_trade(fill.maker_user_id, fill.symbol, fill.maker_side, fill.quantity);
+        self.on_trade(fill.taker_user_id, fill.symbol, fill.taker_side, fill.quantity);
+
+        let Some(maker) = self.resting.get_mut(&fill.maker_order_id) else { return };
+        let qty = fill.quantity.min(maker.remaining);
+        maker.remaining -= qty;
+        let done = maker.remaining.is_zero();
+        let exposure = self.users.entry(maker.user_id).or_default();
+        exposure.resting_notional -= qty * maker.price;
+        exposure.add_resting(maker, -qty);
+        if done {
+            exposure.open_orders = exposure.open_orders.saturating_sub(1);
+            self.resting.remove(&fill.maker_order_id);
+        }
+    }
+
+    pub fn position(&self, user_id: &UserId, symbol: &Symbol) -> Quantity {
+        self.users.get(user_id).and_then(|e| e.positions.get(symbol)).copied().unwrap_or_default()
+    }
+
+    //total of the user's resting orders on `side`
+    pub fn resting_quantity(&self, user_id: &UserId, symbol: &Symbol, side: Side) -> Quantity {
+        self.users.get(user_id).and_then(|e| e.resting_qty.get(&(*symbol, side))).copied().unwrap_or_default()
+    }
+
+    pub fn on_cancel(&mut self, order_id: &OrderId) {
+        let Some(order) = self.resting.remove(order_id) else { return };
+        let exposure = self.users.entry(order.user_id).or_default();
+        exposure.open_orders = exposure.open_orders.saturating_sub(1);
+        exposure.resting_notional -= order.remaining * order.price;
+        exposure.add_resting(&order, -order.remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{BracketTable, LimitOrder};
+
+    fn buy(user_id: UserId, symbol: Symbol, quantity: Quantity, leverage: Decimal) -> Order {
+        Order::limit_order(LimitOrder { user_id, symbol, side: Side::Buy, price: dec!(100), quantity, leverage })
+    }
+
+    #[test]
+    fn bracket_counts_resting_orders_on_the_same_side() {
+        let symbol = Symbol::new("BTC-PERP").unwrap();
+        let mut config = RiskConfig::default();
+        config.brackets.default = BracketTable::from_limits(&[(dec!(1_000), dec!(20), dec!(0.01)), (dec!(0), dec!(5), dec!(0.05))]);
+        let mut risk = RiskEngine::new(config);
+        let user = Uuid::new_v4();
+
+        //300 alone is in the first bracket
+        assert!(risk.check(&buy(user, symbol, dec!(3), dec!(10)), dec!(100)).is_ok());
+
+        let resting = buy(user, symbol, dec!(8), dec!(10));
+        risk.on_rest(resting.order_id, &resting, dec!(100), dec!(8));
+        let rejection = risk.check(&buy(user, symbol, dec!(3), dec!(10)), dec!(100)).unwrap_err();
+        assert_eq!(rejection.code, RiskRejectCode::MaxLeverage);
+        assert!(risk.check(&buy(user, symbol, dec!(3), dec!(5)), dec!(100)).is_ok());
+
+        risk.on_cancel(&resting.order_id);
+        assert!(risk.check(&buy(user, symbol, dec!(3), dec!(10)), dec!(100)).is_ok());
+    }
+}
@@ -0,0 +1,320 @@
+// Starts and stops the engine threads.
+// matching-engine: pinned, optionally SCHED_FIFO, owns the order book.
+// event-pipeline: drains the event ring into the registered consumers.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::{
+    BackpressureConfig, EngineHealth, EngineSnapshot, EventBufferStats, EventConsumer, EventPipeline, DEFAULT_DEPTH_LEVELS, FeeSchedule, LaneConfig, LaneStats, MatchingEngine, RiskConfig,
+    OrderBookMessage, PositionEngine, RingBuffer, RingSender, SharedBookPrices, SharedDepth, SharedWallet, Symbol, UserId, WaitStrategy, mpsc_ring, now_millis,
+};
+
+pub struct RuntimeConfig {
+    pub symbol: Symbol,
+    pub engine_core: Option<usize>,
+    pub pipeline_core: Option<usize>,
+    pub realtime_priority: Option<i32>,  //SCHED_FIFO priority for the matching thread, None keeps the default scheduler
+    pub command_capacity: usize,
+    pub event_capacity: usize,
+    pub backpressure: BackpressureConfig,
+    pub idle_wait: WaitStrategy,
+    pub lanes: LaneConfig,
+    pub risk: RiskConfig,
+    pub fees: FeeSchedule,
+    pub fee_volumes: Vec<(UserId, u64, Decimal)>,  //(user, day, notional) traded in the fee window before startup
+    pub positions: PositionEngine,  //rebuilt from the stored events before startup
+    pub impact_notional: Decimal,  //size used for impact bid/ask, in quote currency
+    pub depth_levels: usize,       //levels per side in the published depth snapshot
+    pub insurance_fund: Decimal,   //seeds the fund, deposited to the liquidation account once
+    pub snapshot_path: PathBuf,
+    pub snapshot: Option<EngineSnapshot>,  //the last run's book, see load_snapshot
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            symbol: Symbol::new("BTC-PERP").expect("valid symbol"),
+            engine_core: Some(0),
+            pipeline_core: Some(2),
+            realtime_priority: None,
+            command_capacity: 1024,
+            event_capacity: 1 << 20,
+            backpressure: BackpressureConfig::default(),
+            idle_wait: WaitStrategy::Park(Duration::from_micros(50)),
+            lanes: LaneConfig::default(),
+            risk: RiskConfig::default(),
+            fees: FeeSchedule::default(),
+            fee_volumes: Vec::new(),
+            positions: PositionEngine::new(),
+            impact_notional: dec!(10_000),
+            depth_levels: DEFAULT_DEPTH_LEVELS,
+            insurance_fund: Decimal::ZERO,
+            snapshot_path: PathBuf::from("engine_snapshot.json"),
+            snapshot: None,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    //ENGINE_CORE / PIPELINE_CORE accept a core id or "none"
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            symbol: env_or("ENGINE_SYMBOL", Some(default.symbol)).unwrap_or(default.symbol),
+            engine_core: env_or("ENGINE_CORE", default.engine_core),
+            pipeline_core: env_or("PIPELINE_CORE", default.pipeline_core),
+            realtime_priority: env_or("ENGINE_RT_PRIORITY", default.realtime_priority),
+            command_capacity: env_or("ENGINE_COMMAND_CAPACITY", Some(default.command_capacity)).unwrap_or(default.command_capacity),
+            event_capacity: env_or("ENGINE_EVENT_CAPACITY", Some(default.event_capacity)).unwrap_or(default.event_capacity),
+            impact_notional: env_or("ENGINE_IMPACT_NOTIONAL", Some(default.impact_notional)).unwrap_or(default.impact_notional),
+            depth_levels: env_or("ENGINE_DEPTH_LEVELS", Some(default.depth_levels)).unwrap_or(default.depth_levels),
+            insurance_fund: env_or("INSURANCE_FUND_BALANCE", Some(default.insurance_fund)).unwrap_or(default.insurance_fund),
+            snapshot_path: std::env::var("ENGINE_SNAPSHOT_PATH").map(PathBuf::from).unwrap_or(default.snapshot_path),
+            ..default
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: Option<T>) -> Option<T> {
+    match std::env::var(key) {
+        Ok(value) => value.parse().ok(),
+        Err(_) => default,
+    }
+}
+
+//shared with the HTTP layer for health checks
+pub struct EngineMonitor {
+    pub events: Arc<EventBufferStats>,
+    pub lanes: Arc<LaneStats>,
+    pub engine_alive: AtomicBool,
+    pub pipeline_alive: AtomicBool,
+    pub batches: AtomicU64,
+    pub commands: AtomicU64,
+    pub last_batch_at: AtomicU64,  //unix millis, 0 = never
+}
+
+impl EngineMonitor {
+    pub fn new(events: Arc<EventBufferStats>, lanes: Arc<LaneStats>) -> Self {
+        Self {
+            events,
+            lanes,
+            engine_alive: AtomicBool::new(false),
+            pipeline_alive: AtomicBool::new(false),
+            batches: AtomicU64::new(0),
+            commands: AtomicU64::new(0),
+            last_batch_at: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_batch(&self, commands: usize) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.commands.fetch_add(commands as u64, Ordering::Relaxed);
+        self.last_batch_at.store(now_millis(), Ordering::Relaxed);
+    }
+
+    pub fn health(&self, queued_commands: usize) -> EngineHealth {
+        let last = self.last_batch_at.load(Ordering::Relaxed);
+        EngineHealth {
+            state: self.events.state(),
+            engine_alive: self.engine_alive.load(Ordering::Acquire),
+            pipeline_alive: self.pipeline_alive.load(Ordering::Acquire),
+            batches: self.batches.load(Ordering::Relaxed),
+            commands: self.commands.load(Ordering::Relaxed),
+            last_batch_age_ms: (last != 0).then(|| now_millis().saturating_sub(last)),
+            queued_commands,
+            events: self.events.snapshot(),
+            lanes: self.lanes.snapshot(),
+        }
+    }
+}
+
+//clears the alive flag even if the thread panics
+struct AliveGuard<'a>(&'a AtomicBool);
+
+impl<'a> AliveGuard<'a> {
+    fn new(flag: &'a AtomicBool) -> Self {
+        flag.store(true, Ordering::Release);
+        Self(flag)
+    }
+}
+
+impl Drop for AliveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+pub struct EngineHandle {
+    intake: RingSender<OrderBookMessage>,
+    monitor: Arc<EngineMonitor>,
+    stop_pipeline: Arc<AtomicBool>,
+    engine_thread: JoinHandle<()>,
+    pipeline_thread: JoinHandle<()>,
+}
+
+//state the matching thread shares with the rest of the process
+pub struct EngineServices {
+    pub wallet: SharedWallet,
+    pub book_prices: SharedBookPrices,
+    pub depth: SharedDepth,
+}
+
+//spawns both threads, returns the sender the gateway pushes commands into
+pub fn start_engine(
+    config: RuntimeConfig,
+    services: EngineServices,
+    consumers: Vec<Box<dyn EventConsumer>>,
+) -> std::io::Result<(RingSender<OrderBookMessage>, EngineHandle)> {
+    let event_ring = Arc::new(RingBuffer::new(config.event_capacity));
+    let (book_tx, book_rx) = mpsc_ring::<OrderBookMessage>(config.command_capacity);
+
+    let mut engine = MatchingEngine::with_backpressure(config.symbol, event_ring.clone(), config.backpressure)
+        .with_idle_wait(config.idle_wait)
+        .with_lane_config(config.lanes)
+        .with_risk_config(config.risk)
+        .with_positions(config.positions)
+        .with_fee_schedule(config.fees)
+        .with_fee_volumes(config.fee_volumes)
+        .with_wallet(services.wallet)
+        .with_book_prices(services.book_prices, config.impact_notional)
+        .with_depth(services.depth, config.depth_levels);
+    if let Some(snapshot) = config.snapshot {
+        engine = engine.with_snapshot(snapshot);
+    }
+    let monitor = engine.monitor();
+
+    let mut pipeline = EventPipeline::new(event_ring, config.idle_wait);
+    for consumer in consumers {
+        pipeline.add_consumer(consumer);
+    }
+
+    let engine_thread = {
+        let monitor = monitor.clone();
+        let snapshot_path = config.snapshot_path.clone();
+        let (core, priority) = (config.engine_core, config.realtime_priority);
+        thread::Builder::new()
+            .name("matching-engine".to_string())
+            .spawn(move || {
+                let _alive = AliveGuard::new(&monitor.engine_alive);
+                pin_to_core("matching-engine", core);
+                set_realtime_priority("matching-engine", priority);
+
+                engine.run(book_rx);
+                //intake is closed and the last batch is done: nothing may be left in the spill queue
+                engine.flush_events();
+                if let Err(e) = write_snapshot(&engine, &snapshot_path) {
+                    println!(" [RUNTIME] snapshot failed: {e}");
+                }
+            })?
+    };
+
+    let stop_pipeline = Arc::new(AtomicBool::new(false));
+    let pipeline_thread = {
+        let monitor = monitor.clone();
+        let stop = stop_pipeline.clone();
+        let core = config.pipeline_core;
+        thread::Builder::new()
+            .name("event-pipeline".to_string())
+            .spawn(move || {
+                let _alive = AliveGuard::new(&monitor.pipeline_alive);
+                pin_to_core("event-pipeline", core);
+                pipeline.run(&stop);
+            })?
+    };
+
+    Ok((
+        book_tx.clone(),
+        EngineHandle {
+            intake: book_tx,
+            monitor,
+            stop_pipeline,
+            engine_thread,
+            pipeline_thread,
+        },
+    ))
+}
+
+impl EngineHandle {
+    pub fn monitor(&self) -> Arc<EngineMonitor> {
+        self.monitor.clone()
+    }
+
+    //stop intake -> engine drains queued commands, flushes events, snapshots -> pipeline drains and flushes consumers
+    pub fn shutdown(self) {
+        println!(" [RUNTIME] shutting down: closing command intake");
+        self.intake.close();
+
+        if self.engine_thread.join().is_err() {
+            println!(" [RUNTIME] matching-engine thread panicked");
+        }
+
+        self.stop_pipeline.store(true, Ordering::Release);
+        if self.pipeline_thread.join().is_err() {
+            println!(" [RUNTIME] event-pipeline thread panicked");
+        }
+        println!(" [RUNTIME] shutdown complete");
+    }
+}
+
+fn write_snapshot(engine: &MatchingEngine, path: &Path) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(file, &engine.snapshot())?;
+    println!(" [RUNTIME] engine snapshot written to {}", path.display());
+    Ok(())
+}
+
+//the snapshot is taken off disk as it is read: after a crash the book it describes is stale,
+//a later start must not book it again. None when there is none or it cannot be read
+pub fn load_snapshot(path: &Path) -> Option<EngineSnapshot> {
+    let file = File::open(path).ok()?;
+    let snapshot = serde_json::from_reader(BufReader::new(file));
+    if let Err(e) = std::fs::remove_file(path) {
+        println!(" [RUNTIME] could not remove {}: {e}", path.display());
+    }
+    match snapshot {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            println!(" [RUNTIME] engine snapshot {} unreadable: {e}", path.display());
+            None
+        }
+    }
+}
+
+fn pin_to_core(thread: &str, core: Option<usize>) {
+    let Some(core) = core else { return };
+    if core_affinity::set_for_current(core_affinity::CoreId { id: core }) {
+        println!(" [RUNTIME] {thread} pinned to core {core}");
+    } else {
+        println!(" [RUNTIME] could not pin {thread} to core {core}, running unpinned");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_realtime_priority(thread: &str, priority: Option<i32>) {
+    let Some(priority) = priority else { return };
+    let param = libc::sched_param { sched_priority: priority };
+    let rc = unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+    if rc == 0 {
+        println!(" [RUNTIME] {thread} running SCHED_FIFO priority {priority}");
+    } else {
+        //usually missing CAP_SYS_NICE, see README
+        println!(" [RUNTIME] could not set SCHED_FIFO for {thread} (error {rc}), using default scheduler");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_realtime_priority(thread: &str, priority: Option<i32>) {
+    if priority.is_some() {
+        println!(" [RUNTIME] real-time priority for {thread} is only supported on linux");
+    }
+}
@@ -0,0 +1,226 @@
+// 24h ticker: a consumer folds fills into one-minute buckets per symbol, the window is the
+// current minute and the 1439 before it. Book, mark/index and open interest are read from the
+// shared state when a ticker is asked for, so every part is as fresh as its source.
+// On startup the window is refilled from the persisted trades of the last 24h.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use chrono::DateTime;
+use db::Db;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::{
+    Event, EventConsumer, Price, Quantity, SharedBookPrices, SharedMarketPrices, SharedPositions, Symbol, Ticker, now_millis,
+};
+
+const MINUTE_MS: i64 = 60_000;
+const WINDOW_MINUTES: i64 = 1440;
+
+struct MinuteBucket {
+    minute: i64,  //unix minutes
+    open: Price,
+    high: Price,
+    low: Price,
+    volume: Quantity,
+    quote_volume: Decimal,
+    trades: u64,
+}
+
+#[derive(Default)]
+pub struct RollingStats {
+    buckets: VecDeque<MinuteBucket>,  //oldest first
+    last: Option<Price>,
+}
+
+impl RollingStats {
+    pub fn record(&mut self, price: Price, quantity: Quantity, time: i64) {
+        let minute = time.div_euclid(MINUTE_MS);
+        while self.buckets.front().is_some_and(|b| b.minute <= minute - WINDOW_MINUTES) {
+            self.buckets.pop_front();
+        }
+        match self.buckets.back_mut() {
+            //a fill stamped slightly out of order stays in the newest bucket
+            Some(b) if b.minute >= minute => {
+                b.high = b.high.max(price);
+                b.low = b.low.min(price);
+                b.volume += quantity;
+                b.quote_volume += quantity * price;
+                b.trades += 1;
+            }
+            _ => self.buckets.push_back(MinuteBucket {
+                minute,
+                open: price,
+                high: price,
+                low: price,
+                volume: quantity,
+                quote_volume: quantity * price,
+                trades: 1,
+            }),
+        }
+        self.last = Some(price);
+    }
+}
+
+pub type SharedTickerStats = Arc<RwLock<HashMap<Symbol, RollingStats>>>;
+
+pub struct TickerService {
+    stats: SharedTickerStats,
+    book_prices: SharedBookPrices,
+    prices: SharedMarketPrices,
+    positions: SharedPositions,
+}
+
+impl TickerService {
+    pub fn new(book_prices: SharedBookPrices, prices: SharedMarketPrices, positions: SharedPositions) -> Self {
+        Self { stats: Arc::new(RwLock::new(HashMap::new())), book_prices, prices, positions }
+    }
+
+    pub fn stats(&self) -> SharedTickerStats {
+        self.stats.clone()
+    }
+
+    pub async fn backfill(&self, db: &Db, symbol: Symbol) -> anyhow::Result<usize> {
+        let since = DateTime::from_timestamp_millis(now_millis() as i64 - WINDOW_MINUTES * MINUTE_MS);
+        let trades = db.get_trades_since(symbol.as_str(), since).await?;
+        let mut stats = self.stats.write().unwrap_or_else(|e| e.into_inner());
+        let rolling = stats.entry(symbol).or_default();
+        for t in &trades {
+            rolling.record(t.price, t.quantity, t.traded_at.timestamp_millis());
+        }
+        Ok(trades.len())
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = self.stats.read().unwrap_or_else(|e| e.into_inner()).keys().copied().collect();
+        let engine_symbol = self.prices.read().unwrap_or_else(|e| e.into_inner()).symbol;
+        if !symbols.contains(&engine_symbol) {
+            symbols.push(engine_symbol);
+        }
+        symbols.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        symbols
+    }
+
+    //None for a symbol nothing is known about
+    pub fn ticker(&self, symbol: Symbol) -> Option<Ticker> {
+        let now = now_millis() as i64;
+        let first_minute = now.div_euclid(MINUTE_MS) - WINDOW_MINUTES + 1;
+        let mut ticker = Ticker {
+            symbol,
+            open: None,
+            high: None,
+            low: None,
+            last: None,
+            volume: Quantity::ZERO,
+            quote_volume: Decimal::ZERO,
+            trades: 0,
+            price_change: None,
+            price_change_percent: None,
+            best_bid: None,
+            best_ask: None,
+            mark_price: None,
+            index_price: None,
+            open_interest: Quantity::ZERO,
+            window_start: first_minute * MINUTE_MS,
+            updated_at: now,
+        };
+
+        let known_stats = {
+            let stats = self.stats.read().unwrap_or_else(|e| e.into_inner());
+            if let Some(rolling) = stats.get(&symbol) {
+                for b in rolling.buckets.iter().filter(|b| b.minute >= first_minute) {
+                    ticker.open = ticker.open.or(Some(b.open));
+                    ticker.high = Some(ticker.high.map_or(b.high, |h| h.max(b.high)));
+                    ticker.low = Some(ticker.low.map_or(b.low, |l| l.min(b.low)));
+                    ticker.volume += b.volume;
+                    ticker.quote_volume += b.quote_volume;
+                    ticker.trades += b.trades;
+                }
+                ticker.last = rolling.last;
+            }
+            stats.contains_key(&symbol)
+        };
+        ticker.volume = ticker.volume.normalize();
+        ticker.quote_volume = ticker.quote_volume.normalize();
+        if let (Some(open), Some(last)) = (ticker.open, ticker.last) {
+            ticker.price_change = Some((last - open).normalize());
+            ticker.price_change_percent = (!open.is_zero()).then(|| ((last - open) / open * dec!(100)).round_dp(4).normalize());
+        }
+
+        let prices = self.prices.read().unwrap_or_else(|e| e.into_inner());
+        if prices.symbol == symbol {
+            let book = *self.book_prices.read().unwrap_or_else(|e| e.into_inner());
+            ticker.best_bid = book.best_bid;
+            ticker.best_ask = book.best_ask;
+            ticker.mark_price = prices.mark_price;
+            ticker.index_price = prices.index_price;
+        } else if !known_stats {
+            return None;
+        }
+        drop(prices);
+
+        let positions = self.positions.read().unwrap_or_else(|e| e.into_inner());
+        ticker.open_interest = positions
+            .open_positions()
+            .filter(|p| p.symbol == symbol && p.size > Quantity::ZERO)
+            .map(|p| p.size)
+            .sum::<Quantity>()
+            .normalize();
+        Some(ticker)
+    }
+}
+
+pub struct TickerConsumer {
+    stats: SharedTickerStats,
+}
+
+impl TickerConsumer {
+    pub fn new(stats: SharedTickerStats) -> Self {
+        Self { stats }
+    }
+}
+
+impl EventConsumer for TickerConsumer {
+    fn name(&self) -> &'static str {
+        "ticker"
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        if let Event::Fill(fill) = event {
+            let time = (fill.timestamp_ / 1_000_000) as i64;
+            let mut stats = self.stats.write().unwrap_or_else(|e| e.into_inner());
+            stats.entry(fill.symbol).or_default().record(fill.price, fill.quantity, time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(stats: &RollingStats) -> Vec<(i64, u64)> {
+        stats.buckets.iter().map(|b| (b.minute, b.trades)).collect()
+    }
+
+    #[test]
+    fn a_minute_leaves_the_window_after_24_hours() {
+        let mut stats = RollingStats::default();
+        stats.record(dec!(100), dec!(1), 30_000);
+        stats.record(dec!(102), dec!(2), MINUTE_MS + 10_000);
+        stats.record(dec!(101), dec!(1), MINUTE_MS + 20_000);
+        //out of order, kept in the newest bucket
+        stats.record(dec!(99), dec!(1), 50_000);
+        assert_eq!(minutes(&stats), [(0, 1), (1, 3)]);
+        let newest = stats.buckets.back().unwrap();
+        assert_eq!((newest.open, newest.high, newest.low, newest.volume, newest.quote_volume), (dec!(102), dec!(102), dec!(99), dec!(4), dec!(404)));
+
+        stats.record(dec!(105), dec!(1), (WINDOW_MINUTES - 1) * MINUTE_MS);
+        assert_eq!(minutes(&stats), [(0, 1), (1, 3), (WINDOW_MINUTES - 1, 1)]);
+        stats.record(dec!(106), dec!(1), WINDOW_MINUTES * MINUTE_MS);
+        assert_eq!(minutes(&stats), [(1, 3), (WINDOW_MINUTES - 1, 1), (WINDOW_MINUTES, 1)]);
+        stats.record(dec!(107), dec!(1), (WINDOW_MINUTES + 5) * MINUTE_MS);
+        assert_eq!(minutes(&stats), [(WINDOW_MINUTES - 1, 1), (WINDOW_MINUTES, 1), (WINDOW_MINUTES + 5, 1)]);
+        assert_eq!(stats.last, Some(dec!(107)));
+    }
+}
@@ -0,0 +1,462 @@
+// Wallet engine: per-user collateral and the margin locked against it.
+//  - matching thread: reserves initial margin (notional / leverage) when an order is accepted,
+//    releases it on cancel and for the unfilled part of market orders
+//  - event pipeline: on each Fill moves the filled part of the reservation into position margin,
+//    frees position margin on reductions and settles realized PnL into the balance,
+//    fees go from both users to the fee account (a maker rebate the other way)
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{FEE_ACCOUNT, Fill, LIQUIDATION_ACCOUNT, MarginMode, MarginView, OrderId, PositionChange, Price, Quantity, Symbol, UserId};
+
+//the one asset balances, margin, fees and funding are denominated in
+pub const SETTLEMENT_ASSET: &str = "USDT";
+
+#[derive(Clone, Default)]
+pub struct Account {
+    pub balance: Decimal,                          //collateral incl. realized PnL
+    pub reserved: Decimal,                         //initial margin of open orders
+    pub position_margin: HashMap<Symbol, Decimal>, //margin backing open positions
+    pub margin_modes: HashMap<Symbol, MarginMode>, //symbols not listed are cross
+    pub withdrawal_locked: Decimal,                //requested withdrawals not completed yet
+}
+
+impl Account {
+    pub fn total_position_margin(&self) -> Decimal {
+        self.position_margin.values().copied().sum()
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.balance - self.reserved - self.total_position_margin() - self.withdrawal_locked
+    }
+
+    pub fn margin_mode(&self, symbol: &Symbol) -> MarginMode {
+        self.margin_modes.get(symbol).copied().unwrap_or_default()
+    }
+
+    pub fn position_margin(&self, symbol: &Symbol) -> Decimal {
+        self.position_margin.get(symbol).copied().unwrap_or_default()
+    }
+
+    //the part of the balance cross positions can lose: everything not locked in an isolated position
+    pub fn cross_collateral(&self) -> Decimal {
+        let isolated: Decimal = self
+            .position_margin
+            .iter()
+            .filter(|(symbol, _)| self.margin_mode(symbol) == MarginMode::Isolated)
+            .map(|(_, margin)| *margin)
+            .sum();
+        self.balance - isolated - self.withdrawal_locked
+    }
+}
+
+struct Reservation {
+    user_id: UserId,
+    symbol: Symbol,
+    remaining_qty: Quantity,
+    margin: Decimal,
+}
+
+#[derive(Serialize)]
+pub struct BalanceView {
+    pub user_id: UserId,
+    pub balance: Decimal,
+    pub reserved: Decimal,
+    pub position_margin: Decimal,
+    pub withdrawal_locked: Decimal,
+    pub available: Decimal,
+}
+
+#[derive(Default)]
+pub struct WalletEngine {
+    accounts: HashMap<UserId, Account>,
+    reservations: HashMap<OrderId, Reservation>,
+    initial_balance: Decimal,  //credited to an account the first time it is seen, 0 in production
+}
+
+pub type SharedWallet = Arc<Mutex<WalletEngine>>;
+
+pub fn initial_margin(quantity: Quantity, price: Price, leverage: Decimal) -> Decimal {
+    quantity * price / leverage
+}
+
+impl WalletEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //WALLET_INITIAL_BALANCE, a test balance for every new account; real money comes in as deposits
+    pub fn from_env() -> Self {
+        let initial_balance = std::env::var("WALLET_INITIAL_BALANCE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        Self::new().with_initial_balance(initial_balance)
+    }
+
+    pub fn with_initial_balance(mut self, amount: Decimal) -> Self {
+        self.initial_balance = amount;
+        self
+    }
+
+    //system accounts never get the test balance, the liquidation account's balance is the insurance fund
+    fn initial_balance(&self, user_id: &UserId) -> Decimal {
+        match *user_id == LIQUIDATION_ACCOUNT || *user_id == FEE_ACCOUNT {
+            true => Decimal::ZERO,
+            false => self.initial_balance,
+        }
+    }
+
+    fn account_mut(&mut self, user_id: UserId) -> &mut Account {
+        let initial_balance = self.initial_balance(&user_id);
+        self.accounts.entry(user_id).or_insert_with(|| Account { balance: initial_balance, ..Default::default() })
+    }
+
+    //startup: balances from the ledger and the amounts of withdrawals still in flight;
+    //the test balance is not in the ledger, it comes on top as for any new account
+    pub fn restore(&mut self, balances: Vec<(UserId, Decimal)>, withdrawal_locked: Vec<(UserId, Decimal)>) {
+        for (user_id, balance) in balances {
+            self.account_mut(user_id).balance += balance;
+        }
+        for (user_id, amount) in withdrawal_locked {
+            self.account_mut(user_id).withdrawal_locked += amount;
+        }
+    }
+
+    pub fn restore_margin_modes(&mut self, modes: &[(UserId, Symbol, MarginMode)]) {
+        for (user_id, symbol, mode) in modes {
+            if *mode == MarginMode::Isolated {
+                self.account_mut(*user_id).margin_modes.insert(*symbol, *mode);
+            }
+        }
+    }
+
+    //an adjustment that was accepted when it was made, replayed as is
+    pub fn restore_isolated_margin(&mut self, user_id: UserId, symbol: Symbol, amount: Decimal) {
+        if let Some(margin) = self.account_mut(user_id).position_margin.get_mut(&symbol) {
+            *margin = (*margin + amount).max(Decimal::ZERO);
+        }
+    }
+
+    //startup: the position margin of `replayed` (see replay_positions), balances stay as they are
+    pub fn restore_position_margin(&mut self, replayed: &WalletEngine) {
+        for (user_id, account) in &replayed.accounts {
+            if !account.position_margin.is_empty() {
+                self.account_mut(*user_id).position_margin = account.position_margin.clone();
+            }
+        }
+    }
+
+    pub fn account(&self, user_id: &UserId) -> Option<&Account> {
+        self.accounts.get(user_id)
+    }
+
+    pub fn balance_view(&self, user_id: &UserId) -> BalanceView {
+        let account = self.accounts.get(user_id).cloned().unwrap_or_else(|| Account {
+            balance: self.initial_balance(user_id),
+            ..Default::default()
+        });
+        BalanceView {
+            user_id: *user_id,
+            balance: account.balance,
+            reserved: account.reserved,
+            position_margin: account.total_position_margin(),
+            withdrawal_locked: account.withdrawal_locked,
+            available: account.available(),
+        }
+    }
+
+    pub fn credit(&mut self, user_id: UserId, amount: Decimal) {
+        self.account_mut(user_id).balance += amount;
+    }
+
+    pub fn debit(&mut self, user_id: UserId, amount: Decimal) -> Result<(), String> {
+        let account = self.account_mut(user_id);
+        if account.available() < amount {
+            return Err("insufficient available balance".to_string());
+        }
+        account.balance -= amount;
+        Ok(())
+    }
+
+    //a withdrawal request takes the amount out of `available` until it completes or is rejected
+    pub fn lock_withdrawal(&mut self, user_id: UserId, amount: Decimal) -> Result<(), String> {
+        let account = self.account_mut(user_id);
+        if account.available() < amount {
+            return Err(format!("insufficient available balance: need {amount}, available {}", account.available()));
+        }
+        account.withdrawal_locked += amount;
+        Ok(())
+    }
+
+    pub fn unlock_withdrawal(&mut self, user_id: UserId, amount: Decimal) {
+        let account = self.account_mut(user_id);
+        account.withdrawal_locked = (account.withdrawal_locked - amount).max(Decimal::ZERO);
+    }
+
+    //the money has left through custody; refused (and left locked) when the balance no longer covers it
+    pub fn settle_withdrawal(&mut self, user_id: UserId, amount: Decimal) -> Result<(), String> {
+        let account = self.account_mut(user_id);
+        if account.balance < amount {
+            return Err(format!("balance {} does not cover {amount}", account.balance));
+        }
+        account.balance -= amount;
+        account.withdrawal_locked = (account.withdrawal_locked - amount).max(Decimal::ZERO);
+        Ok(())
+    }
+
+    //holds `margin` for an order of `quantity`, released pro rata as it fills
+    pub fn reserve(&mut self, order_id: OrderId, user_id: UserId, symbol: Symbol, quantity: Quantity, margin: Decimal) -> Result<(), String> {
+        let account = self.account_mut(user_id);
+        if account.available() < margin {
+            return Err(format!(
+                "insufficient available balance: need {margin}, available {}",
+                account.available()
+            ));
+        }
+        account.reserved += margin;
+        self.reservations.insert(order_id, Reservation { user_id, symbol, remaining_qty: quantity, margin });
+        Ok(())
+    }
+
+    //whole reservation back, e.g. on cancel
+    pub fn release_order(&mut self, order_id: &OrderId) -> Decimal {
+        let Some(reservation) = self.reservations.remove(order_id) else {
+            return Decimal::ZERO;
+        };
+        if let Some(account) = self.accounts.get_mut(&reservation.user_id) {
+            account.reserved -= reservation.margin;
+        }
+        reservation.margin
+    }
+
+    //releases the share of the reservation that belongs to `quantity` of the order
+    pub fn release_quantity(&mut self, order_id: &OrderId, quantity: Quantity) -> Decimal {
+        let Some(reservation) = self.reservations.get_mut(order_id) else {
+            return Decimal::ZERO;
+        };
+        let qty = quantity.min(reservation.remaining_qty);
+        let portion = if qty == reservation.remaining_qty {
+            reservation.margin
+        } else {
+            reservation.margin * qty / reservation.remaining_qty
+        };
+        reservation.remaining_qty -= qty;
+        reservation.margin -= portion;
+
+        let user_id = reservation.user_id;
+        if reservation.remaining_qty.is_zero() {
+            self.reservations.remove(order_id);
+        }
+        if let Some(account) = self.accounts.get_mut(&user_id) {
+            account.reserved -= portion;
+        }
+        portion
+    }
+
+    pub fn margin_view(&self, user_id: &UserId, symbol: &Symbol) -> MarginView {
+        let account = self.accounts.get(user_id);
+        MarginView {
+            user_id: *user_id,
+            symbol: *symbol,
+            mode: account.map(|a| a.margin_mode(symbol)).unwrap_or_default(),
+            margin: account.map(|a| a.position_margin(symbol)).unwrap_or_default(),
+        }
+    }
+
+    //only while the user has neither a position nor open orders in the symbol
+    pub fn set_margin_mode(&mut self, user_id: UserId, symbol: Symbol, mode: MarginMode) -> Result<(), String> {
+        let has_orders = self.reservations.values().any(|r| r.user_id == user_id && r.symbol == symbol);
+        let account = self.account_mut(user_id);
+        if account.margin_mode(&symbol) == mode {
+            return Ok(());
+        }
+        if account.position_margin.contains_key(&symbol) {
+            return Err(format!("close the {symbol} position before changing margin mode"));
+        }
+        if has_orders {
+            return Err(format!("cancel open {symbol} orders before changing margin mode"));
+        }
+        match mode {
+            MarginMode::Cross => account.margin_modes.remove(&symbol),
+            MarginMode::Isolated => account.margin_modes.insert(symbol, mode),
+        };
+        Ok(())
+    }
+
+    //moves `amount` between the balance and an isolated position, removal keeps at least `min_margin`
+    pub fn adjust_isolated_margin(&mut self, user_id: UserId, symbol: Symbol, amount: Decimal, min_margin: Decimal) -> Result<Decimal, String> {
+        let account = self.account_mut(user_id);
+        if account.margin_mode(&symbol) != MarginMode::Isolated {
+            return Err(format!("{symbol} is not in isolated margin mode"));
+        }
+        let available = account.available();
+        let Some(margin) = account.position_margin.get_mut(&symbol) else {
+            return Err(format!("no open {symbol} position"));
+        };
+        if amount > available {
+            return Err(format!("insufficient available balance: need {amount}, available {available}"));
+        }
+        if *margin + amount < min_margin {
+            return Err(format!("at most {} can be removed", (*margin - min_margin).max(Decimal::ZERO)));
+        }
+        *margin += amount;
+        Ok(*margin)
+    }
+
+    //funding always moves the balance. A cross position's margin is a requirement and stays put,
+    //an isolated position's margin is its collateral and moves with it, down to zero at most
+    pub fn apply_funding(&mut self, user_id: UserId, symbol: Symbol, amount: Decimal) {
+        let account = self.account_mut(user_id);
+        account.balance += amount;
+        if account.margin_mode(&symbol) == MarginMode::Isolated
+            && let Some(margin) = account.position_margin.get_mut(&symbol)
+        {
+            *margin = (*margin + amount).max(Decimal::ZERO);
+        }
+    }
+
+    //maker and taker changes come from PositionEngine::apply_fill, in that order
+    pub fn apply_fill(&mut self, fill: &Fill, changes: &[PositionChange; 2]) {
+        let sides = [
+            (fill.maker_order_id, fill.maker_leverage, fill.maker_fee, &changes[0]),
+            (fill.taker_order_id, fill.taker_leverage, fill.taker_fee, &changes[1]),
+        ];
+        for (order_id, leverage, fee, change) in sides {
+            self.release_quantity(&order_id, fill.quantity);
+            self.apply_position_change(change, fill.price, leverage);
+            self.account_mut(change.user_id).balance -= fee;
+            self.flag_shortfall(&change.user_id, &change.symbol);
+        }
+        self.account_mut(FEE_ACCOUNT).balance += fill.maker_fee + fill.taker_fee;
+    }
+
+    //margin and PnL side of any position change, `price` is what the trade was done at
+    pub fn apply_position_change(&mut self, change: &PositionChange, price: Price, leverage: Decimal) {
+        let account = self.account_mut(change.user_id);
+        if !change.closed_qty.is_zero()
+            && !change.size_before.is_zero()
+            && let Some(margin) = account.position_margin.get_mut(&change.symbol)
+        {
+            *margin -= *margin * change.closed_qty / change.size_before.abs();
+        }
+        account.balance += change.realized_pnl;
+        //a fill cannot be undone, its margin is always booked in full. The order's reservation
+        //(released just before) covers it, see MatchingEngine::reserve_margin
+        if !change.opened_qty.is_zero() {
+            *account.position_margin.entry(change.symbol).or_default() += initial_margin(change.opened_qty, price, leverage);
+        }
+        if change.size_after.is_zero() {
+            account.position_margin.remove(&change.symbol);
+        }
+    }
+
+    //a fill the reservation did not cover (the fee tier moved, or the position it was meant to reduce
+    //was closed first) leaves the account below zero available; reported, the liquidation engine takes
+    //it from there. The liquidation account's takeovers are backed by the fund, not by a reservation
+    fn flag_shortfall(&self, user_id: &UserId, symbol: &Symbol) {
+        if *user_id == LIQUIDATION_ACCOUNT {
+            return;
+        }
+        if let Some(account) = self.accounts.get(user_id)
+            && account.available() < Decimal::ZERO
+        {
+            println!(" [WALLET] {user_id} is {} short after a {symbol} fill", -account.available());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn funding_moves_isolated_margin_but_not_cross_margin() {
+        let user = Uuid::from_u128(7);
+        let (cross, isolated) = (Symbol::new("BTC-PERP").unwrap(), Symbol::new("ETH-PERP").unwrap());
+        let mut wallet = WalletEngine::default();
+        wallet.credit(user, dec!(1000));
+        let account = wallet.account_mut(user);
+        account.margin_modes.insert(isolated, MarginMode::Isolated);
+        account.position_margin.insert(cross, dec!(100));
+        account.position_margin.insert(isolated, dec!(50));
+
+        wallet.apply_funding(user, cross, dec!(-10));
+        wallet.apply_funding(user, isolated, dec!(-5));
+
+        let account = wallet.account(&user).unwrap();
+        assert_eq!(account.balance, dec!(985));
+        assert_eq!(account.position_margin(&cross), dec!(100));
+        assert_eq!(account.position_margin(&isolated), dec!(45));
+        assert_eq!(account.available(), dec!(840));
+    }
+
+    #[test]
+    fn a_withdrawal_is_locked_then_settled_or_unlocked() {
+        let user = Uuid::from_u128(7);
+        let mut wallet = WalletEngine::default();
+        wallet.credit(user, dec!(100));
+
+        assert!(wallet.lock_withdrawal(user, dec!(150)).is_err());
+        wallet.lock_withdrawal(user, dec!(60)).unwrap();
+        assert_eq!(wallet.balance_view(&user).available, dec!(40));
+        assert!(wallet.lock_withdrawal(user, dec!(50)).is_err());
+        assert!(wallet.debit(user, dec!(50)).is_err());
+
+        wallet.lock_withdrawal(user, dec!(30)).unwrap();
+        wallet.unlock_withdrawal(user, dec!(30));
+        let view = wallet.balance_view(&user);
+        assert_eq!((view.balance, view.withdrawal_locked, view.available), (dec!(100), dec!(60), dec!(40)));
+
+        wallet.settle_withdrawal(user, dec!(60)).unwrap();
+        let view = wallet.balance_view(&user);
+        assert_eq!((view.balance, view.withdrawal_locked, view.available), (dec!(40), dec!(0), dec!(40)));
+    }
+
+    #[test]
+    fn a_withdrawal_the_balance_no_longer_covers_stays_locked() {
+        let user = Uuid::from_u128(7);
+        let mut wallet = WalletEngine::default();
+        wallet.credit(user, dec!(100));
+        wallet.lock_withdrawal(user, dec!(80)).unwrap();
+        wallet.account_mut(user).balance -= dec!(30);  //a loss after the request
+
+        assert!(wallet.settle_withdrawal(user, dec!(80)).is_err());
+        let view = wallet.balance_view(&user);
+        assert_eq!((view.balance, view.withdrawal_locked), (dec!(70), dec!(80)));
+
+        wallet.unlock_withdrawal(user, dec!(80));
+        assert_eq!(wallet.balance_view(&user).withdrawal_locked, dec!(0));
+    }
+
+    #[test]
+    fn fill_margin_is_booked_in_full_beyond_available() {
+        let user = Uuid::from_u128(7);
+        let symbol = Symbol::new("BTC-PERP").unwrap();
+        let mut wallet = WalletEngine::default();
+        wallet.credit(user, dec!(5));
+        let change = PositionChange {
+            user_id: user,
+            symbol,
+            closed_qty: dec!(0),
+            opened_qty: dec!(1),
+            realized_pnl: dec!(0),
+            size_before: dec!(0),
+            size_after: dec!(1),
+        };
+
+        wallet.apply_position_change(&change, dec!(100), dec!(10));
+
+        let account = wallet.account(&user).unwrap();
+        assert_eq!(account.position_margin(&symbol), dec!(10));
+        assert_eq!(account.available(), dec!(-5));
+    }
+}
+
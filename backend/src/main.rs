@@ -9,21 +9,119 @@ pub mod auth;
 pub use auth::*;
 pub mod engine;
 pub use engine::*;
-use std::sync::mpsc;
 
-use axum::{Router, routing::post};
-use db::Db;
-use std::sync::Arc;
+use axum::{Router, routing::{get, post}};
+use db::{Db, LedgerAccount, PositionEventRecord};
+use chrono::Utc;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 #[tokio::main]
 async fn main(){
     dotenvy::dotenv().ok();
     let db = Db::new().await.expect("db init needed");
-    let (book_tx, book_rx) = mpsc::sync_channel::<OrderBookMessage>(1000);
 
+    let mut config = RuntimeConfig::from_env();
+    //the liquidation account trades on behalf of the insurance fund, its wallet balance is the fund
+    if config.insurance_fund > Decimal::ZERO {
+        db.post_deposit("insurance_fund:seed", LIQUIDATION_ACCOUNT, SETTLEMENT_ASSET, config.insurance_fund)
+            .await
+            .expect("failed to seed the insurance fund");
+    }
+    let mut wallet = load_wallet(&db).await;
+    config.positions = load_positions(&db, config.symbol, &mut wallet).await;
+    let positions: SharedPositions = Arc::new(RwLock::new(config.positions.clone()));
+    let wallet: SharedWallet = Arc::new(Mutex::new(wallet));
+    let insurance: SharedInsuranceFund = Arc::new(RwLock::new(InsuranceFund::default()));
+    config.snapshot = load_resting_orders(&db, &config).await;
+    let last_seq = db.get_event_offset(PERSISTENCE_CONSUMER).await.expect("failed to load event offset");
+    let (persist_tx, persist_rx) = tokio::sync::mpsc::channel(64);
+    let persistence = spawn_persistence(db.clone(), persist_rx);
+    let (ledger_tx, ledger_rx) = tokio::sync::mpsc::channel(64);
+    let ledger_writer = spawn_ledger_writer(db.clone(), ledger_rx);
+
+    let mut candle_book = CandleBook::new();
+    backfill_candles(&db, &mut candle_book, config.symbol).await.expect("failed to backfill candles");
+    let candles: SharedCandles = Arc::new(RwLock::new(candle_book));
+    let (candle_tx, candle_rx) = tokio::sync::mpsc::channel(64);
+    let candle_writer = spawn_candle_writer(db.clone(), candle_rx);
+
+    let symbol = config.symbol;
+    let book_prices: SharedBookPrices = Arc::new(RwLock::new(BookPrices::default()));
+    let prices: SharedMarketPrices = Arc::new(RwLock::new(MarketPrices::new(symbol)));
+    let tickers = Arc::new(TickerService::new(book_prices.clone(), prices.clone(), positions.clone()));
+    tickers.backfill(&db, symbol).await.expect("failed to backfill ticker");
+    let l3: SharedL3 = Arc::new(RwLock::new(L3Book::new(symbol)));
+    let depth = shared_depth(symbol);
+    let ws_queue = std::env::var("WS_SEND_QUEUE").ok().and_then(|v| v.parse().ok()).filter(|n| *n > 0).unwrap_or(DEFAULT_WS_SEND_QUEUE);
+    let market_data = Arc::new(MarketDataHub::new(symbol, ws_queue, depth.clone(), tickers.clone(), candles.clone()));
+
+    let consumers: Vec<Box<dyn EventConsumer>> = vec![
+        Box::new(PositionConsumer::new(positions.clone(), wallet.clone(), ledger_tx)),
+        Box::new(InsuranceFundConsumer::new(insurance.clone())),
+        Box::new(PersistenceConsumer::new(last_seq, persist_tx)),
+        Box::new(CandleConsumer::new(candles.clone(), candle_tx)),
+        Box::new(TickerConsumer::new(tickers.stats())),
+        Box::new(L3Consumer::new(l3.clone())),
+        //last, so ticker and candles already include the batch it publishes
+        Box::new(MarketDataConsumer::new(market_data.clone(), last_seq)),
+    ];
+
+    config.risk.tiers = load_account_tiers(&db).await;
+    config.fee_volumes = load_fee_volumes(&db).await;
+
+    let fees = config.fees.clone();
+    let brackets = config.risk.brackets.clone();
+    let services = EngineServices { wallet: wallet.clone(), book_prices: book_prices.clone(), depth: depth.clone() };
+    let (book_tx, engine) = start_engine(config, services, consumers)
+        .expect("failed to start engine threads");
+
+    //trading works without an oracle, only mark-price driven features stay idle
+    let oracle = match Oracle::new(OracleConfig::from_env(symbol), book_prices, prices.clone()) {
+        Ok(oracle) => {
+            let liquidations = LiquidationEngine::new(
+                LiquidationConfig::new(symbol, brackets.table(&symbol).clone()),
+                positions.clone(),
+                wallet.clone(),
+                book_tx.clone(),
+            );
+            spawn_liquidation(liquidations, oracle.subscribe());
+            Some(start_oracle(oracle, book_tx.clone()).expect("failed to start oracle thread"))
+        }
+        Err(e) => {
+            println!(" [ORACLE] not started: {e}");
+            None
+        }
+    };
+
+    let funding = FundingEngine::new(FundingConfig::from_env(symbol), prices.clone());
+    let funding_state = funding.state();
+    spawn_funding(funding, db.clone(), book_tx.clone());
+
+    let simulated_custody = Arc::new(SimulatedCustody::from_env());
+    let custody = Arc::new(CustodyService::new(simulated_custody.clone(), db.clone(), wallet.clone()));
+    spawn_custody(custody.clone(), Duration::from_millis(500));
 
     let app_state = Arc::new(AppState {
         book_tx,
+        engine: engine.monitor(),
+        positions,
+        wallet,
+        prices,
+        funding: funding_state,
+        insurance,
+        candles,
+        tickers,
+        depth,
+        l3,
+        market_data,
+        fees,
+        brackets,
+        custody,
+        simulated_custody,
+        admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
         db,
     });
     let app = Router::new()
@@ -31,6 +129,39 @@ async fn main(){
         .route("/signin", post(signin))  
         .route("/place_order", post(place_order))
         .route("/cancel", post(cancel_order))
+        .route("/orders/open", get(get_open_orders))
+        .route("/orders/history", get(get_order_history))
+        .route("/orders/{id}", get(get_order))
+        .route("/trades/{symbol}", get(get_trades))
+        .route("/fills", get(get_fills))
+        .route("/candles/{symbol}", get(get_candles))
+        .route("/depth/{symbol}", get(get_depth))
+        .route("/l3/{symbol}", get(get_l3_snapshot))
+        .route("/l3/{symbol}/messages", get(get_l3_messages))
+        .route("/ws", get(ws_market_data))
+        .route("/ticker", get(get_tickers))
+        .route("/ticker/{symbol}", get(get_ticker))
+        .route("/health", get(health))
+        .route("/positions", get(get_positions))
+        .route("/balance", get(get_balance))
+        .route("/ledger", get(get_ledger_balances))
+        .route("/deposits", get(get_deposits))
+        .route("/withdrawals", get(get_withdrawals).post(request_withdrawal))
+        .route("/admin/deposits", get(get_all_deposits))
+        .route("/admin/withdrawals", get(get_all_withdrawals))
+        .route("/admin/withdrawals/{id}/approve", post(approve_withdrawal))
+        .route("/admin/withdrawals/{id}/reject", post(reject_withdrawal))
+        .route("/admin/simulate_deposit", post(simulate_deposit))
+        .route("/prices/{symbol}", get(get_prices))
+        .route("/funding/{symbol}", get(get_funding))
+        .route("/insurance_fund", get(get_insurance_fund))
+        .route("/fees", get(get_fee_schedule))
+        .route("/leverage_brackets/{symbol}", get(get_leverage_brackets))
+        .route("/leverage_bracket", get(get_user_bracket))
+        .route("/margin", get(get_margin))
+        .route("/margin_mode", post(set_margin_mode))
+        .route("/isolated_margin", post(adjust_isolated_margin))
+        .route("/adl_rank", get(get_adl_rank))
         .with_state(app_state);  
 
     let listener = TcpListener::bind("0.0.0.0:3000")
@@ -38,6 +169,145 @@ async fn main(){
         .expect("failed to bind");
 
     axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .expect("server failed");
-}
\ No newline at end of file
+
+    //http is drained, stop everything that feeds the engine, then the engine itself
+    if let Some(oracle) = oracle {
+        oracle.shutdown();
+    }
+    engine.shutdown();
+    //the pipeline dropped its senders, the writers end after storing what is left
+    let _ = persistence.await;
+    let _ = ledger_writer.await;
+    let _ = candle_writer.await;
+}
+
+//the wallet is rebuilt before the engine starts: balances from the ledger, locks from open withdrawals
+async fn load_wallet(db: &Db) -> WalletEngine {
+    let balances = db.get_wallet_balances(SETTLEMENT_ASSET).await.expect("failed to load wallet balances");
+    let locked = db.get_open_withdrawal_totals(SETTLEMENT_ASSET).await.expect("failed to load open withdrawals");
+    println!(" [SERVER] restored {} wallet balances, {} users with open withdrawals", balances.len(), locked.len());
+    let fees = db.get_ledger_balance(LedgerAccount::FeeRevenue, SETTLEMENT_ASSET).await.expect("failed to load fee revenue");
+    let mut wallet = WalletEngine::from_env();
+    wallet.restore(balances, locked);
+    wallet.credit(FEE_ACCOUNT, fees);
+    wallet
+}
+
+//positions are replayed from the stored fills, liquidations, ADL and funding, with the margin
+//adjustments merged in by time; the wallet gets the margin modes and the replayed position margin
+async fn load_positions(db: &Db, symbol: Symbol, wallet: &mut WalletEngine) -> PositionEngine {
+    let modes: Vec<_> = db.get_margin_modes(symbol.as_str()).await.expect("failed to load margin modes")
+        .into_iter()
+        .filter_map(|(user_id, mode)| match mode.parse() {
+            Ok(mode) => Some((user_id, symbol, mode)),
+            Err(e) => {
+                println!(" [SERVER] {e} for user {user_id}, using cross");
+                None
+            }
+        })
+        .collect();
+    let events = db.get_position_events(symbol.as_str()).await.expect("failed to load position events");
+    let adjustments = db.get_margin_adjustments(symbol.as_str()).await.expect("failed to load margin adjustments");
+    let count = events.len();
+
+    let mut adjustments = adjustments.into_iter().peekable();
+    let mut replay = Vec::with_capacity(count + adjustments.len());
+    for event in events {
+        while let Some(adjustment) = adjustments.next_if(|a| a.adjusted_at <= event.at) {
+            replay.push(PositionReplay::MarginAdjustment { user_id: adjustment.user_id, symbol, amount: adjustment.amount });
+        }
+        match position_replay(symbol, &event) {
+            Some(item) => replay.push(item),
+            None => println!(" [SERVER] position event {} ({}) is incomplete, skipped", event.event_seq, event.kind),
+        }
+    }
+    replay.extend(adjustments.map(|a| PositionReplay::MarginAdjustment { user_id: a.user_id, symbol, amount: a.amount }));
+
+    let mut replayed = WalletEngine::new();
+    replayed.restore_margin_modes(&modes);
+    let positions = replay_positions(replay, &mut replayed);
+    wallet.restore_margin_modes(&modes);
+    wallet.restore_position_margin(&replayed);
+    println!(" [SERVER] replayed {count} position events, {} open {symbol} positions", positions.open_positions().count());
+    positions
+}
+
+fn position_replay(symbol: Symbol, event: &PositionEventRecord) -> Option<PositionReplay> {
+    let user_id = event.user_id;
+    let timestamp = event.at.timestamp_nanos_opt().unwrap_or_default() as u128;
+    if event.kind == "funding" {
+        return Some(PositionReplay::Funding { user_id, symbol, amount: event.amount? });
+    }
+    let side: Side = event.side.as_deref()?.parse().ok()?;
+    let (quantity, price) = (event.quantity?, event.price?);
+    match event.kind.as_str() {
+        "maker" | "taker" => Some(PositionReplay::Trade(PositionTrade { user_id, symbol, side, quantity, price, leverage: event.leverage?, timestamp })),
+        "liquidation" | "adl" => Some(PositionReplay::Transfer { user_id, symbol, side, quantity, price, timestamp }),
+        _ => None,
+    }
+}
+
+//the book comes back from the last shutdown's snapshot, with the orders the store still has open;
+//open orders it does not hold (the last run crashed, or there is no snapshot) are expired
+async fn load_resting_orders(db: &Db, config: &RuntimeConfig) -> Option<EngineSnapshot> {
+    let open: HashSet<_> = db.get_open_order_ids(config.symbol.as_str()).await.expect("failed to load open orders").into_iter().collect();
+    let mut snapshot = load_snapshot(&config.snapshot_path);
+    if let Some(snapshot) = snapshot.as_mut() {
+        snapshot.orders.retain(|o| o.symbol == config.symbol && open.contains(&o.order_id));
+        println!(" [SERVER] {} resting orders restored from {}", snapshot.orders.len(), config.snapshot_path.display());
+    }
+    let keep: Vec<_> = snapshot.iter().flat_map(|s| s.orders.iter().map(|o| o.order_id)).collect();
+    let expired = db.expire_open_orders(PERSISTENCE_CONSUMER, Utc::now(), &keep).await.expect("failed to expire open orders");
+    if expired > 0 {
+        println!(" [PERSIST] {expired} orders left open by the last run marked expired");
+    }
+    snapshot
+}
+
+//tiers only change through the users table, they are read once at startup
+async fn load_account_tiers(db: &Db) -> HashMap<UserId, AccountTier> {
+    let rows = db.get_account_tiers().await.expect("failed to load account tiers");
+    rows.into_iter()
+        .filter_map(|(user_id, tier)| match tier.parse() {
+            Ok(tier) => Some((user_id, tier)),
+            Err(e) => {
+                println!(" [SERVER] {e} for user {user_id}, using standard");
+                None
+            }
+        })
+        .collect()
+}
+
+//the fee window the last runs traded, so tiers survive a restart
+async fn load_fee_volumes(db: &Db) -> Vec<(UserId, u64, Decimal)> {
+    let today = (now_nanos() / NANOS_PER_DAY) as u64;
+    let since = today.saturating_sub(VOLUME_WINDOW_DAYS - 1);
+    let rows = db.get_daily_volumes(since as i64).await.expect("failed to load fee volumes");
+    println!(" [SERVER] restored {} daily fee volumes", rows.len());
+    rows.into_iter().map(|(user_id, day, notional)| (user_id, day as u64, notional)).collect()
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    println!(" [SERVER] shutdown signal received");
+}
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use db::Candle;
+
+use crate::{ApiError, AppState, CandleQuery, Symbol, api_error};
+
+//stored bars, then the newest closed bar if the writer has not stored it yet, then the open bar
+pub async fn get_candles(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+    Query(query): Query<CandleQuery>,
+) -> Result<Json<Vec<Candle>>, ApiError> {
+    let limit = query.limit.unwrap_or(500).clamp(1, 1500);
+    let mut candles = state
+        .db
+        .get_candles(symbol.as_str(), query.kind.as_str(), query.interval.as_str(), query.from, query.to, limit)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let in_range = |c: &Candle| query.from.is_none_or(|from| c.open_time >= from) && query.to.is_none_or(|to| c.open_time < to);
+    {
+        let book = state.candles.read().unwrap_or_else(|e| e.into_inner());
+        let live = [
+            book.last_closed(symbol, query.kind, query.interval),
+            book.open_candle(symbol, query.kind, query.interval),
+        ];
+        for bar in live.into_iter().flatten() {
+            let newer = candles.last().is_none_or(|last| bar.open_time > last.open_time);
+            if newer && in_range(bar) {
+                candles.push(bar.clone());
+            }
+        }
+    }
+    if candles.len() > limit as usize {
+        candles.drain(..candles.len() - limit as usize);
+    }
+    Ok(Json(candles))
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, DepthQuery, DepthSnapshot, Symbol, api_error};
+
+//reads the snapshot the engine published after its last batch, never the live book
+pub async fn get_depth(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+    Query(query): Query<DepthQuery>,
+) -> Result<Json<DepthSnapshot>, ApiError> {
+    let snapshot = state.depth.load();
+    if snapshot.symbol != symbol {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("unknown symbol {symbol}")));
+    }
+    let limit = query.limit.unwrap_or(20).max(1);
+    Ok(Json(DepthSnapshot {
+        symbol,
+        bids: snapshot.bids.iter().take(limit).copied().collect(),
+        asks: snapshot.asks.iter().take(limit).copied().collect(),
+        last_update_id: snapshot.last_update_id,
+        checksum: snapshot.checksum,
+        updated_at: snapshot.updated_at,
+    }))
+}
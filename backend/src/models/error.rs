@@ -0,0 +1,9 @@
+use axum::{http::StatusCode, Json};
+
+use crate::Response;
+
+pub type ApiError = (StatusCode, Json<Response>);
+
+pub fn api_error(status: StatusCode, error: impl ToString) -> ApiError {
+    (status, Json(Response { message: String::new(), error: error.to_string() }))
+}
@@ -0,0 +1,9 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+
+use crate::{AppState, FeeSchedule};
+
+pub async fn get_fee_schedule(State(state): State<Arc<AppState>>) -> Json<FeeSchedule> {
+    Json(state.fees.clone())
+}
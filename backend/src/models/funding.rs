@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, FundingQuery, FundingResponse, Symbol, api_error};
+
+pub async fn get_funding(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+    Query(query): Query<FundingQuery>,
+) -> Result<Json<FundingResponse>, ApiError> {
+    let current = state.funding.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if current.symbol != symbol {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("unknown symbol {symbol}")));
+    }
+
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    match state.db.get_funding_history(symbol.as_str(), limit).await {
+        Ok(history) => Ok(Json(FundingResponse { current, history })),
+        Err(e) => Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+
+use crate::{AppState, EngineHealth, EngineState};
+
+pub async fn health(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<EngineHealth>) {
+    let health = state.engine.health(state.book_tx.len());
+
+    let status = if health.engine_alive && health.pipeline_alive && health.state == EngineState::Running {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, L3Event, L3ReplayQuery, L3Snapshot, Symbol, api_error};
+
+fn check_symbol(state: &AppState, symbol: Symbol) -> Result<(), ApiError> {
+    let known = state.l3.read().unwrap_or_else(|e| e.into_inner()).symbol();
+    if known != symbol {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("unknown symbol {symbol}")));
+    }
+    Ok(())
+}
+
+//every resting order in queue order, as of message `seq`
+pub async fn get_l3_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+) -> Result<Json<L3Snapshot>, ApiError> {
+    check_symbol(&state, symbol)?;
+    Ok(Json(state.l3.read().unwrap_or_else(|e| e.into_inner()).snapshot()))
+}
+
+//messages after `after`, oldest first; 410 once they fell out of the journal, take a new snapshot then
+pub async fn get_l3_messages(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+    Query(query): Query<L3ReplayQuery>,
+) -> Result<Json<Vec<L3Event>>, ApiError> {
+    check_symbol(&state, symbol)?;
+    let limit = query.limit.unwrap_or(1000).clamp(1, 10_000);
+    let messages = state.l3.read().unwrap_or_else(|e| e.into_inner()).replay(query.after, limit);
+    messages
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::GONE, format!("messages after {} are no longer kept, reload the snapshot", query.after)))
+}
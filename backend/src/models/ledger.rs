@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use db::LedgerBalance;
+
+use crate::{ApiError, AppState, AuthUser, api_error};
+
+//balances as the ledger sees them, summed from postings
+pub async fn get_ledger_balances(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<Vec<LedgerBalance>>, ApiError> {
+    state
+        .db
+        .get_ledger_balances(user_id)
+        .await
+        .map(Json)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+
+use crate::{AppState, AuthUser, BracketQuery, BracketTable, Symbol, UserBracket};
+
+pub async fn get_leverage_brackets(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+) -> Json<BracketTable> {
+    Json(state.brackets.table(&symbol).clone())
+}
+
+pub async fn get_user_bracket(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<BracketQuery>,
+) -> Json<UserBracket> {
+    let mark = {
+        let prices = state.prices.read().unwrap_or_else(|e| e.into_inner());
+        prices.mark_price.or(prices.index_price)
+    };
+    let position = state
+        .positions
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .position(&user_id, &query.symbol)
+        .copied()
+        .filter(|p| !p.size.is_zero());
+    let notional = position
+        .map(|p| p.notional(mark.unwrap_or(p.entry_price)))
+        .unwrap_or_default();
+    let table = state.brackets.table(&query.symbol);
+    Json(UserBracket {
+        user_id,
+        symbol: query.symbol,
+        position_notional: notional,
+        leverage: position.map(|p| p.leverage),
+        maintenance_margin: table.maintenance_margin(notional),
+        bracket: table.bracket(notional).clone(),
+    })
+}
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+
+use crate::{AdlRank, AppState, AuthUser, InsuranceFund, LIQUIDATION_ACCOUNT, PositionsQuery, adl_queue, adl_rank};
+
+pub async fn get_insurance_fund(
+    State(state): State<Arc<AppState>>,
+) -> Json<InsuranceFund> {
+    let mut fund = state.insurance.read().unwrap_or_else(|e| e.into_inner()).clone();
+    fund.balance = state.wallet.lock().unwrap_or_else(|e| e.into_inner()).balance_view(&LIQUIDATION_ACCOUNT).balance;
+    Json(fund)
+}
+
+//where each open position of the user stands in the ADL queue at the current mark
+pub async fn get_adl_rank(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<PositionsQuery>,
+) -> Json<Vec<AdlRank>> {
+    let mark = {
+        let prices = state.prices.read().unwrap_or_else(|e| e.into_inner());
+        prices.mark_price.or(prices.index_price)
+    };
+    let positions = state.positions.read().unwrap_or_else(|e| e.into_inner());
+    let ranks = positions
+        .user_positions(&user_id)
+        .iter()
+        .filter(|p| query.symbol.is_none_or(|s| s == p.symbol))
+        .filter_map(|p| {
+            let side = p.side()?;
+            let queue = match mark {
+                Some(mark) => adl_queue(positions.open_positions(), p.symbol, side, mark),
+                None => Vec::new(),
+            };
+            Some(adl_rank(p, &queue))
+        })
+        .collect();
+    Json(ranks)
+}
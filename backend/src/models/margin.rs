@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, AuthUser, IsolatedMarginRequest, MarginModeRequest, MarginQuery, MarginView, api_error};
+
+pub async fn get_margin(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<MarginQuery>,
+) -> Json<MarginView> {
+    let wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+    Json(wallet.margin_view(&user_id, &query.symbol))
+}
+
+pub async fn set_margin_mode(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<MarginModeRequest>,
+) -> Result<Json<MarginView>, ApiError> {
+    let previous = {
+        let mut wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = wallet.margin_view(&user_id, &req.symbol).mode;
+        wallet.set_margin_mode(user_id, req.symbol, req.mode).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+        previous
+    };
+    //positions are rebuilt with the stored mode at startup, a change that is not stored is undone
+    if let Err(e) = state.db.set_margin_mode(user_id, req.symbol.as_str(), req.mode.as_str()).await {
+        let mut wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = wallet.set_margin_mode(user_id, req.symbol, previous);
+        return Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, e));
+    }
+    let wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(Json(wallet.margin_view(&user_id, &req.symbol)))
+}
+
+//removing margin keeps at least the initial margin of the position at the current mark
+pub async fn adjust_isolated_margin(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<IsolatedMarginRequest>,
+) -> Result<Json<MarginView>, ApiError> {
+    if req.amount.is_zero() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "amount must not be zero"));
+    }
+    let mark = {
+        let prices = state.prices.read().unwrap_or_else(|e| e.into_inner());
+        prices.mark_price.or(prices.index_price)
+    };
+    let position = state
+        .positions
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .position(&user_id, &req.symbol)
+        .copied();
+    let min_margin = match (position, mark) {
+        (Some(p), Some(mark)) => p.notional(mark) / p.leverage,
+        (Some(p), None) => p.notional(p.entry_price) / p.leverage,
+        (None, _) => Default::default(),
+    };
+
+    state
+        .wallet
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .adjust_isolated_margin(user_id, req.symbol, req.amount, min_margin)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    if let Err(e) = state.db.record_margin_adjustment(user_id, req.symbol.as_str(), req.amount).await {
+        let mut wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+        wallet.restore_isolated_margin(user_id, req.symbol, -req.amount);
+        return Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, e));
+    }
+    let wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(Json(wallet.margin_view(&user_id, &req.symbol)))
+}
@@ -1,4 +1,40 @@
-pub mod auth;
+mod error;
+pub use error::*;
+mod auth;
 pub use auth::*;
-pub mod order;
-pub use order::*;
\ No newline at end of file
+mod order;
+pub use order::*;
+mod health;
+pub use health::*;
+mod position;
+pub use position::*;
+mod oracle;
+pub use oracle::*;
+mod funding;
+pub use funding::*;
+mod liquidation;
+pub use liquidation::*;
+mod fee;
+pub use fee::*;
+mod margin;
+pub use margin::*;
+mod leverage;
+pub use leverage::*;
+mod ledger;
+pub use ledger::*;
+mod transfer;
+pub use transfer::*;
+mod wallet;
+pub use wallet::*;
+mod trade;
+pub use trade::*;
+mod candle;
+pub use candle::*;
+mod ticker;
+pub use ticker::*;
+mod depth;
+pub use depth::*;
+mod l3;
+pub use l3::*;
+mod ws;
+pub use ws::*;
@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, MarketPrices, Symbol, api_error};
+
+pub async fn get_prices(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+) -> Result<Json<MarketPrices>, ApiError> {
+    let prices = state.prices.read().unwrap_or_else(|e| e.into_inner());
+    if prices.symbol != symbol {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("unknown symbol {symbol}")));
+    }
+    Ok(Json(prices.clone()))
+}
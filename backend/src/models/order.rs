@@ -1,15 +1,35 @@
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
+use chrono::DateTime;
+use db::{OrderFilter, OrderRow};
 use std::sync::Arc;
 use tokio::sync::oneshot;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::FromPrimitive; 
 use rust_decimal_macros::dec;
+use uuid::Uuid;
 
-use crate::{AppState, CanceledOrderRequest, LimitOrder, MarketOrder, Order, OrderBookMessage, OrderRequest, OrderResponse, OrderType, Response};
+use crate::{
+    ApiError, AppState, AuthUser, CanceledOrderRequest, LimitOrder, MarketOrder, OpenOrder, OpenOrdersQuery, Order, OrderBookMessage, OrderHistoryPage,
+    OrderHistoryQuery, OrderRequest, OrderResponse, OrderType, Priority, Response, TrySendError, api_error,
+};
+
+//never blocks the async worker: a full ingress ring is answered right away so clients back off
+pub fn submit_to_engine(state: &AppState, msg: OrderBookMessage) -> Result<(), ApiError> {
+    //the last quarter of the ring is kept for Critical/High commands (cancels, liquidations)
+    let high_water = state.book_tx.capacity() / 4 * 3;
+    if msg.priority() >= Priority::Normal && state.book_tx.len() >= high_water {
+        return Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "Engine overloaded, retry later"));
+    }
+    match state.book_tx.try_send(msg) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "Engine overloaded, retry later")),
+        Err(TrySendError::Closed(_)) => Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "Engine unavailable")),
+    }
+}
 
 pub async fn place_order(
     State(state): State<Arc<AppState>>,
@@ -63,6 +83,7 @@ pub async fn place_order(
 
             Order::limit_order(LimitOrder {
                 user_id: req.user_id,
+                symbol: req.symbol,
                 side: req.side,
                 price,
                 quantity,
@@ -83,6 +104,7 @@ pub async fn place_order(
 
             Order::market_order(MarketOrder {
                 user_id: req.user_id,
+                symbol: req.symbol,
                 side: req.side,
                 quantity,
                 leverage,
@@ -90,19 +112,12 @@ pub async fn place_order(
         }
     };
 
-    if state.book_tx.send(OrderBookMessage::PlaceOrder {
+    if let Err(rejected) = submit_to_engine(&state, OrderBookMessage::PlaceOrder {
         order,
         priority: crate::types::Priority::Normal,
         responder: Some(tx),
-    }).is_err()
-    {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(Response {
-                message: String::new(),
-                error: "Engine unavailable".to_string(),
-            }),
-        );
+    }) {
+        return rejected;
     }
 
     match rx.await {
@@ -122,6 +137,13 @@ pub async fn place_order(
             }),
         ),
 
+        Ok(Err(err)) => (
+            StatusCode::BAD_REQUEST,
+            Json(Response {
+                message: String::new(),
+                error: err,
+            }),
+        ),
         _ => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(Response {
@@ -140,21 +162,12 @@ pub async fn cancel_order(
 
     let (tx, rx) = oneshot::channel::<Result<OrderResponse, String>>();
 
-    if state.book_tx
-        .send(OrderBookMessage::CancelOrder {
-            user_id: req.user_id,
-            order_id: req.order_id,
-            responder: Some(tx),
-        })
-        .is_err()
-    {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(Response {
-                message: String::new(),
-                error: "Engine unavailable".to_string(),
-            }),
-        );
+    if let Err(rejected) = submit_to_engine(&state, OrderBookMessage::CancelOrder {
+        user_id: req.user_id,
+        order_id: req.order_id,
+        responder: Some(tx),
+    }) {
+        return rejected;
     }
     match rx.await {
         Ok(Ok(OrderResponse::CanceledOrder {
@@ -188,4 +201,82 @@ pub async fn cancel_order(
             }),
         ),
     }
-}
\ No newline at end of file
+}
+
+//live from the engine, not from the database, so it is never behind the book
+pub async fn get_open_orders(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<OpenOrdersQuery>,
+) -> Result<Json<Vec<OpenOrder>>, ApiError> {
+    let (tx, rx) = oneshot::channel();
+    submit_to_engine(&state, OrderBookMessage::OpenOrders {
+        user_id,
+        symbol: query.symbol,
+        responder: tx,
+    })?;
+    rx.await
+        .map(Json)
+        .map_err(|_| api_error(StatusCode::INTERNAL_SERVER_ERROR, "Engine response dropped"))
+}
+
+//from the persisted history, a just-placed order shows up once the pipeline wrote it
+pub async fn get_order(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Path(order_id): Path<Uuid>,
+) -> Result<Json<OrderRow>, ApiError> {
+    match state.db.get_order(order_id).await {
+        Ok(Some(order)) if order.user_id == user_id => Ok(Json(order)),
+        Ok(_) => Err(api_error(StatusCode::NOT_FOUND, "order not found")),
+        Err(e) => Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+pub async fn get_order_history(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<OrderHistoryQuery>,
+) -> Result<Json<OrderHistoryPage>, ApiError> {
+    let millis = |ms: Option<i64>| match ms {
+        Some(ms) => DateTime::from_timestamp_millis(ms).map(Some).ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "invalid time")),
+        None => Ok(None),
+    };
+    let before = match query.cursor.as_deref() {
+        Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "invalid cursor"))?),
+        None => None,
+    };
+    let symbol = query.symbol.map(|s| s.to_string());
+    let filter = OrderFilter {
+        symbol: symbol.as_deref(),
+        status: query.status.map(|s| s.as_str()),
+        from: millis(query.from)?,
+        to: millis(query.to)?,
+        before,
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    //one extra row tells whether there is a next page
+    let mut orders = state
+        .db
+        .get_order_history(user_id, &filter, limit + 1)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let next_cursor = if orders.len() as i64 > limit {
+        orders.truncate(limit as usize);
+        orders.last().map(|o| encode_cursor(o.created_at.timestamp_micros(), o.order_id))
+    } else {
+        None
+    };
+    Ok(Json(OrderHistoryPage { orders, next_cursor }))
+}
+
+//"<created_at micros>_<order_id>", postgres keeps microseconds so the pair is exact
+fn encode_cursor(created_at: i64, order_id: Uuid) -> String {
+    format!("{created_at}_{order_id}")
+}
+
+fn decode_cursor(cursor: &str) -> Option<(DateTime<chrono::Utc>, Uuid)> {
+    let (micros, order_id) = cursor.split_once('_')?;
+    Some((DateTime::from_timestamp_micros(micros.parse().ok()?)?, order_id.parse().ok()?))
+}
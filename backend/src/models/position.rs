@@ -0,0 +1,21 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+
+use crate::{AppState, AuthUser, Position, PositionsQuery};
+
+pub async fn get_positions(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<PositionsQuery>,
+) -> Json<Vec<Position>> {
+    let positions = state.positions.read().unwrap_or_else(|e| e.into_inner());
+    let mut open = positions.user_positions(&user_id);
+    if let Some(symbol) = query.symbol {
+        open.retain(|p| p.symbol == symbol);
+    }
+    Json(open)
+}
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, Symbol, Ticker, api_error};
+
+pub async fn get_tickers(State(state): State<Arc<AppState>>) -> Json<Vec<Ticker>> {
+    let tickers = &state.tickers;
+    Json(tickers.symbols().into_iter().filter_map(|s| tickers.ticker(s)).collect())
+}
+
+pub async fn get_ticker(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+) -> Result<Json<Ticker>, ApiError> {
+    state
+        .tickers
+        .ticker(symbol)
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("unknown symbol {symbol}")))
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{ApiError, AppState, AuthUser, FillPage, FillsQuery, PublicTrade, Symbol, TradePage, TradesQuery, api_error};
+
+//recent prints, newest first; the cursor is the last trade id of the previous page
+pub async fn get_trades(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<Symbol>,
+    Query(query): Query<TradesQuery>,
+) -> Result<Json<TradePage>, ApiError> {
+    let before = match query.cursor.as_deref() {
+        Some(cursor) => Some(cursor.parse::<i64>().map_err(|_| api_error(StatusCode::BAD_REQUEST, "invalid cursor"))?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    //one extra row tells whether there is a next page
+    let mut trades = state
+        .db
+        .get_trades(symbol.as_str(), before, limit + 1)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let next_cursor = if trades.len() as i64 > limit {
+        trades.truncate(limit as usize);
+        trades.last().map(|t| t.trade_id.to_string())
+    } else {
+        None
+    };
+    Ok(Json(TradePage { trades: trades.into_iter().map(PublicTrade::from).collect(), next_cursor }))
+}
+
+//the user's executions, both sides of a self-trade included; cursor is "<trade id>_<maker|taker>"
+pub async fn get_fills(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<FillsQuery>,
+) -> Result<Json<FillPage>, ApiError> {
+    let before = match query.cursor.as_deref() {
+        Some(cursor) => {
+            let parsed = cursor
+                .split_once('_')
+                .and_then(|(id, liquidity)| Some((id.parse::<i64>().ok()?, liquidity)))
+                .filter(|(_, liquidity)| matches!(*liquidity, "maker" | "taker"));
+            Some(parsed.ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "invalid cursor"))?)
+        }
+        None => None,
+    };
+    let symbol = query.symbol.map(|s| s.to_string());
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let mut fills = state
+        .db
+        .get_fills(user_id, symbol.as_deref(), before, limit + 1)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let next_cursor = if fills.len() as i64 > limit {
+        fills.truncate(limit as usize);
+        fills.last().map(|f| format!("{}_{}", f.trade_id, f.liquidity))
+    } else {
+        None
+    };
+    Ok(Json(FillPage { fills, next_cursor }))
+}
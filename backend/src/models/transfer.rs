@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use db::{Deposit, Withdrawal};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::{
+    AdminDepositQuery, AdminWithdrawalQuery, ApiError, AppState, AuthUser, DepositQuery, RejectWithdrawalRequest, SETTLEMENT_ASSET,
+    SimulateDepositRequest, SimulateDepositResponse, UserId, WithdrawalQuery, WithdrawalRequest, WithdrawalStatus, api_error,
+};
+
+//admin routes need the x-admin-token header to match ADMIN_TOKEN, and are closed without one
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let given = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    match (&state.admin_token, given) {
+        (Some(token), Some(given)) if token == given => Ok(()),
+        _ => Err(api_error(StatusCode::FORBIDDEN, "admin token required")),
+    }
+}
+
+pub async fn request_withdrawal(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<WithdrawalRequest>,
+) -> Result<Json<Withdrawal>, ApiError> {
+    if req.amount <= Decimal::ZERO {
+        return Err(api_error(StatusCode::BAD_REQUEST, "amount must be positive"));
+    }
+    if req.address.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "address is required"));
+    }
+    state
+        .wallet
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .lock_withdrawal(user_id, req.amount)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+
+    match state.db.create_withdrawal(user_id, SETTLEMENT_ASSET, req.amount, req.address.trim()).await {
+        Ok(w) => Ok(Json(w)),
+        Err(e) => {
+            state.wallet.lock().unwrap_or_else(|e| e.into_inner()).unlock_withdrawal(user_id, req.amount);
+            Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+        }
+    }
+}
+
+pub async fn get_withdrawals(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<WithdrawalQuery>,
+) -> Result<Json<Vec<Withdrawal>>, ApiError> {
+    list_withdrawals(&state, Some(user_id), query.status, query.limit).await
+}
+
+pub async fn get_deposits(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<DepositQuery>,
+) -> Result<Json<Vec<Deposit>>, ApiError> {
+    list_deposits(&state, Some(user_id), query.limit).await
+}
+
+pub async fn get_all_withdrawals(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminWithdrawalQuery>,
+) -> Result<Json<Vec<Withdrawal>>, ApiError> {
+    require_admin(&state, &headers)?;
+    list_withdrawals(&state, query.user_id, query.status, query.limit).await
+}
+
+pub async fn get_all_deposits(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminDepositQuery>,
+) -> Result<Json<Vec<Deposit>>, ApiError> {
+    require_admin(&state, &headers)?;
+    list_deposits(&state, query.user_id, query.limit).await
+}
+
+async fn list_withdrawals(
+    state: &AppState,
+    user_id: Option<UserId>,
+    status: Option<WithdrawalStatus>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<Withdrawal>>, ApiError> {
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    state
+        .db
+        .get_withdrawals(user_id, status.map(|s| s.as_str()), limit)
+        .await
+        .map(Json)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+async fn list_deposits(state: &AppState, user_id: Option<UserId>, limit: Option<i64>) -> Result<Json<Vec<Deposit>>, ApiError> {
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    state
+        .db
+        .get_deposits(user_id, limit)
+        .await
+        .map(Json)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+pub async fn approve_withdrawal(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Withdrawal>, ApiError> {
+    require_admin(&state, &headers)?;
+    let (from, to) = (WithdrawalStatus::Pending.as_str(), WithdrawalStatus::Approved.as_str());
+    match state.db.transition_withdrawal(id, &[from], to, None, None).await {
+        Ok(Some(w)) => Ok(Json(w)),
+        Ok(None) => Err(api_error(StatusCode::CONFLICT, "withdrawal is not pending")),
+        Err(e) => Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+//only before custody has it
+pub async fn reject_withdrawal(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(req): Json<RejectWithdrawalRequest>,
+) -> Result<Json<Withdrawal>, ApiError> {
+    require_admin(&state, &headers)?;
+    let internal = |e: anyhow::Error| api_error(StatusCode::INTERNAL_SERVER_ERROR, e);
+    let Some(w) = state.db.get_withdrawal(id).await.map_err(internal)? else {
+        return Err(api_error(StatusCode::NOT_FOUND, "unknown withdrawal"));
+    };
+    let from = [WithdrawalStatus::Pending, WithdrawalStatus::Approved];
+    if !state.custody.reject(&w, &from, &req.reason).await.map_err(internal)? {
+        return Err(api_error(StatusCode::CONFLICT, "withdrawal is already with custody or finished"));
+    }
+    match state.db.get_withdrawal(id).await.map_err(internal)? {
+        Some(w) => Ok(Json(w)),
+        None => Err(api_error(StatusCode::NOT_FOUND, "unknown withdrawal")),
+    }
+}
+
+//stands in for money arriving at the custodian
+pub async fn simulate_deposit(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<SimulateDepositRequest>,
+) -> Result<Json<SimulateDepositResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+    if req.amount <= Decimal::ZERO {
+        return Err(api_error(StatusCode::BAD_REQUEST, "amount must be positive"));
+    }
+    let custody_ref = state.simulated_custody.simulate_deposit(req.user_id, SETTLEMENT_ASSET, req.amount);
+    Ok(Json(SimulateDepositResponse { custody_ref }))
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    Json,
+};
+
+use crate::{AppState, AuthUser, BalanceView};
+
+pub async fn get_balance(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> Json<BalanceView> {
+    let wallet = state.wallet.lock().unwrap_or_else(|e| e.into_inner());
+    Json(wallet.balance_view(&user_id))
+}
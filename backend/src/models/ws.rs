@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    response::Response,
+};
+use futures_util::{SinkExt, StreamExt};
+
+use crate::{AppState, ConnectionId, MarketDataHub, WsOp, WsRequest, WsResponse};
+
+//a client that takes longer than this to accept one frame is gone
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn ws_market_data(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
+    let hub = state.market_data.clone();
+    ws.on_upgrade(move |socket| serve(socket, hub))
+}
+
+async fn serve(socket: WebSocket, hub: Arc<MarketDataHub>) {
+    let (id, mut rx) = hub.connect();
+    let (mut sink, mut stream) = socket.split();
+
+    let writer = async {
+        while let Some(text) = rx.recv().await {
+            match tokio::time::timeout(SEND_TIMEOUT, sink.send(Message::Text(text.as_ref().into()))).await {
+                Ok(Ok(())) => {}
+                _ => return,
+            }
+        }
+        //the hub let go of the queue: it filled up
+        let close = CloseFrame { code: close_code::AGAIN, reason: "slow consumer".into() };
+        let _ = tokio::time::timeout(SEND_TIMEOUT, sink.send(Message::Close(Some(close)))).await;
+    };
+    let reader = async {
+        while let Some(Ok(message)) = stream.next().await {
+            match message {
+                Message::Text(text) => handle(&hub, id, text.as_str()),
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+    };
+    tokio::select! {
+        _ = writer => {}
+        _ = reader => {}
+    }
+    hub.disconnect(id);
+}
+
+fn handle(hub: &MarketDataHub, id: ConnectionId, text: &str) {
+    match serde_json::from_str::<WsRequest>(text) {
+        Ok(WsRequest { op: WsOp::Subscribe, subscription }) => hub.subscribe(id, subscription),
+        Ok(WsRequest { op: WsOp::Unsubscribe, subscription }) => hub.unsubscribe(id, subscription),
+        Err(e) => hub.reply(id, &WsResponse::Error { error: format!("invalid request: {e}") }),
+    }
+}
@@ -1,9 +1,25 @@
 use db::Db;
-use std::sync::mpsc;
+use std::sync::Arc;
 
-use crate::types::OrderBookMessage;
+use crate::{CustodyService, EngineMonitor, FeeSchedule, LeverageBrackets, MarketDataHub, SimulatedCustody, RingSender, SharedCandles, SharedDepth, SharedFunding, SharedL3, SharedInsuranceFund, SharedMarketPrices, SharedPositions, SharedWallet, TickerService, types::OrderBookMessage};
 
 pub struct AppState{
-    pub book_tx : mpsc::SyncSender<OrderBookMessage>,
+    pub book_tx : RingSender<OrderBookMessage>,
+    pub engine : Arc<EngineMonitor>,
+    pub positions : SharedPositions,
+    pub wallet : SharedWallet,
+    pub prices : SharedMarketPrices,
+    pub funding : SharedFunding,
+    pub insurance : SharedInsuranceFund,
+    pub candles : SharedCandles,
+    pub tickers : Arc<TickerService>,
+    pub depth : SharedDepth,
+    pub l3 : SharedL3,
+    pub market_data : Arc<MarketDataHub>,
+    pub fees : FeeSchedule,
+    pub brackets : LeverageBrackets,
+    pub custody : Arc<CustodyService>,
+    pub simulated_custody : Arc<SimulatedCustody>,
+    pub admin_token : Option<String>,
     pub db: Db
-}
\ No newline at end of file
+}
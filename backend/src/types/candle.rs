@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "15m")]
+    M15,
+    #[serde(rename = "1h")]
+    H1,
+    #[serde(rename = "4h")]
+    H4,
+    #[serde(rename = "1d")]
+    D1,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 6] = [
+        CandleInterval::M1,
+        CandleInterval::M5,
+        CandleInterval::M15,
+        CandleInterval::H1,
+        CandleInterval::H4,
+        CandleInterval::D1,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::M1 => "1m",
+            CandleInterval::M5 => "5m",
+            CandleInterval::M15 => "15m",
+            CandleInterval::H1 => "1h",
+            CandleInterval::H4 => "4h",
+            CandleInterval::D1 => "1d",
+        }
+    }
+
+    pub fn millis(&self) -> i64 {
+        const MINUTE: i64 = 60_000;
+        match self {
+            CandleInterval::M1 => MINUTE,
+            CandleInterval::M5 => 5 * MINUTE,
+            CandleInterval::M15 => 15 * MINUTE,
+            CandleInterval::H1 => 60 * MINUTE,
+            CandleInterval::H4 => 240 * MINUTE,
+            CandleInterval::D1 => 1440 * MINUTE,
+        }
+    }
+
+    //open time of the bar `time` (unix millis) falls into
+    pub fn open_time(&self, time: i64) -> i64 {
+        time - time.rem_euclid(self.millis())
+    }
+}
+
+//trade candles come from fills, mark candles from mark price updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandleKind {
+    #[default]
+    Trade,
+    Mark,
+}
+
+impl CandleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleKind::Trade => "trade",
+            CandleKind::Mark => "mark",
+        }
+    }
+}
+
+//from / to are unix millis on the bar open time
+#[derive(Deserialize)]
+pub struct CandleQuery {
+    pub interval: CandleInterval,
+    #[serde(default)]
+    pub kind: CandleKind,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: Option<i64>,
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Price, Quantity, Symbol};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub quantity: Quantity,  //total resting at the level
+    pub orders: usize,
+}
+
+//aggregated top of the book, bids best (highest) first, asks best (lowest) first
+#[derive(Debug, Clone, Serialize)]
+pub struct DepthSnapshot {
+    pub symbol: Symbol,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    pub last_update_id: u64,  //diffs up to this one are already applied
+    pub checksum: u32,        //over the top 25 levels of the whole book
+    pub updated_at: u128,
+}
+
+impl DepthSnapshot {
+    pub fn empty(symbol: Symbol) -> Self {
+        Self { symbol, bids: Vec::new(), asks: Vec::new(), last_update_id: 0, checksum: 0, updated_at: 0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LevelChange {
+    pub price: Price,
+    pub quantity: Quantity,  //new total at the level, 0 when it is gone
+}
+
+//levels changed by one engine batch. Update ids are contiguous across diffs: the first diff to
+//apply on a snapshot is the one with first_update_id <= last_update_id + 1 <= its last_update_id
+#[derive(Debug, Clone, Serialize)]
+pub struct DepthUpdate {
+    pub symbol: Symbol,
+    pub first_update_id: u64,
+    pub last_update_id: u64,
+    pub bids: Vec<LevelChange>,
+    pub asks: Vec<LevelChange>,
+    pub checksum: u32,  //of the book after the diff, see depth_checksum
+    pub timestamp: u128,
+}
+
+#[derive(Deserialize)]
+pub struct DepthQuery {
+    pub limit: Option<usize>,
+}
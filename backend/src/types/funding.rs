@@ -0,0 +1,28 @@
+use db::FundingRate;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::Symbol;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FundingState {
+    pub symbol: Symbol,
+    pub predicted_rate: Option<Decimal>,  //rate if funding happened now, from the samples so far
+    pub premium_index: Option<Decimal>,   //average premium of the current interval
+    pub samples: u32,
+    pub interval_secs: u64,
+    pub next_funding_time: u64,           //unix millis
+    pub last_rate: Option<Decimal>,
+    pub last_funding_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct FundingQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct FundingResponse {
+    pub current: FundingState,
+    pub history: Vec<FundingRate>,
+}
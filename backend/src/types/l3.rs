@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Price, Quantity, Side, Symbol};
+
+//order ids in the feed are per-process counters, never the engine's ids
+pub type AnonOrderId = u64;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum L3Message {
+    //a new resting order joins the back of its level
+    Add {
+        order: AnonOrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        queue_position: usize,  //orders ahead of it at the level
+    },
+    //a resting order traded; it leaves the queue once remaining is 0
+    Execute {
+        order: AnonOrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        remaining: Quantity,
+    },
+    //a resting order was cancelled
+    Delete {
+        order: AnonOrderId,
+        side: Side,
+        price: Price,
+    },
+}
+
+//seq is contiguous from 1 within a process
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct L3Event {
+    pub seq: u64,
+    pub timestamp: u128,
+    #[serde(flatten)]
+    pub message: L3Message,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct L3Order {
+    pub order: AnonOrderId,
+    pub quantity: Quantity,
+}
+
+//one price level, queue front (next to trade) first
+#[derive(Debug, Clone, Serialize)]
+pub struct L3Level {
+    pub price: Price,
+    pub orders: Vec<L3Order>,
+}
+
+//the book after message `seq`; bids best (highest) first, asks best (lowest) first
+#[derive(Debug, Clone, Serialize)]
+pub struct L3Snapshot {
+    pub symbol: Symbol,
+    pub seq: u64,
+    pub bids: Vec<L3Level>,
+    pub asks: Vec<L3Level>,
+}
+
+#[derive(Deserialize)]
+pub struct L3ReplayQuery {
+    pub after: u64,
+    pub limit: Option<usize>,
+}
@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{Symbol, UserId};
+
+//cross: the whole balance backs every cross position. isolated: the position can only lose its own margin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarginMode {
+    #[default]
+    Cross,
+    Isolated,
+}
+
+impl MarginMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MarginMode::Cross => "cross",
+            MarginMode::Isolated => "isolated",
+        }
+    }
+}
+
+impl FromStr for MarginMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cross" => Ok(MarginMode::Cross),
+            "isolated" => Ok(MarginMode::Isolated),
+            _ => Err(format!("unknown margin mode {s}")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MarginQuery {
+    pub symbol: Symbol,
+}
+
+#[derive(Deserialize)]
+pub struct MarginModeRequest {
+    pub symbol: Symbol,
+    pub mode: MarginMode,
+}
+
+#[derive(Deserialize)]
+pub struct IsolatedMarginRequest {
+    pub symbol: Symbol,
+    pub amount: Decimal,  //> 0 adds, < 0 removes
+}
+
+#[derive(Serialize)]
+pub struct MarginView {
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub mode: MarginMode,
+    pub margin: Decimal,  //margin backing the open position, 0 when flat
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{CandleInterval, CandleKind, Price, Symbol};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsChannel {
+    Trades,
+    Depth,
+    Ticker,
+    Candles,
+    MarkPrice,
+}
+
+//`interval` and `kind` only apply to candles, where interval is required and kind defaults to trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Subscription {
+    pub channel: WsChannel,
+    pub symbol: Symbol,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<CandleInterval>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<CandleKind>,
+}
+
+impl Subscription {
+    pub fn new(channel: WsChannel, symbol: Symbol) -> Self {
+        Self { channel, symbol, interval: None, kind: None }
+    }
+
+    pub fn candles(symbol: Symbol, kind: CandleKind, interval: CandleInterval) -> Self {
+        Self { channel: WsChannel::Candles, symbol, interval: Some(interval), kind: Some(kind) }
+    }
+
+    //the form subscriptions are kept and matched in
+    pub fn validate(self) -> Result<Self, String> {
+        match (self.channel, self.interval) {
+            (WsChannel::Candles, Some(interval)) => Ok(Self::candles(self.symbol, self.kind.unwrap_or_default(), interval)),
+            (WsChannel::Candles, None) => Err("candles need an interval".to_string()),
+            (channel, _) => Ok(Self::new(channel, self.symbol)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsOp {
+    Subscribe,
+    Unsubscribe,
+}
+
+//{"op":"subscribe","channel":"candles","symbol":"BTC-PERP","interval":"1m"}
+#[derive(Debug, Deserialize)]
+pub struct WsRequest {
+    pub op: WsOp,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum WsResponse {
+    Subscribed {
+        #[serde(flatten)]
+        subscription: Subscription,
+    },
+    Unsubscribed {
+        #[serde(flatten)]
+        subscription: Subscription,
+    },
+    Error {
+        error: String,
+    },
+}
+
+//snapshot: the state at subscription time (depth, ticker, open candle); update: everything after
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsMessageType {
+    Snapshot,
+    Update,
+}
+
+#[derive(Serialize)]
+pub struct WsMessage<'a, T> {
+    #[serde(flatten)]
+    pub subscription: Subscription,
+    #[serde(rename = "type")]
+    pub type_: WsMessageType,
+    pub data: &'a T,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MarkPriceUpdate {
+    pub price: Price,
+    pub timestamp: u128,
+}
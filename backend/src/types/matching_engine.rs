@@ -1,10 +1,22 @@
-use crate::{Fill, OrderId, Price, Quantity, UserId, types::Side};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{DepthUpdate, EventBufferSnapshot, Fill, LaneSnapshot, Order, OrderId, Price, Quantity, Symbol, UserId, types::Side};
 
 #[derive(Clone)]
 pub enum Event {
+    //passed risk checks, about to match; always the first event of an order
+    OrderAccepted {
+        order : Order,
+        timestamp : u128
+    },
     OrderPlaced {
         order_id : OrderId,
         user_id : UserId,
+        symbol : Symbol,
         side : Side,
         price : Price,
         quantity : Quantity,
@@ -16,10 +28,154 @@ pub enum Event {
         user_id : UserId,
         timestamp : u128
     },
-    OrderRejected {
+    //unfilled part of an order that does not rest (market, liquidation close)
+    OrderExpired {
         order_id : OrderId,
         user_id : UserId,
+        remaining : Quantity,
+        timestamp : u128
+    },
+    OrderRejected {
+        order : Order,
         reason : String,
         timestamp : u128
+    },
+    //every mark price the engine receives, in command order
+    MarkPrice {
+        symbol : Symbol,
+        price : Price,
+        timestamp : u128
+    },
+    //price levels changed by the last batch
+    DepthUpdate(DepthUpdate),
+    Liquidation(Liquidation),
+    InsuranceFund(InsuranceFundEntry),
+    Adl(Adl),
+    Funding(Funding)
+}
+
+//a position taken over by the liquidation account at the bankruptcy price,
+//the liquidation order closing it follows as ordinary fills
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Liquidation {
+    pub liquidation_id : Uuid,
+    pub user_id : UserId,
+    pub symbol : Symbol,
+    pub side : Side,               //side of the liquidated position
+    pub quantity : Quantity,
+    pub mark_price : Price,
+    pub bankruptcy_price : Price,
+    pub fee : Decimal,             //margin left at the mark price, forfeited by the user
+    pub timestamp : u128
+}
+
+//closing a taken-over position better than bankruptcy pays into the fund (surplus),
+//closing it worse is paid out of it (deficit)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct InsuranceFundEntry {
+    pub liquidation_id : Uuid,
+    pub symbol : Symbol,
+    pub amount : Decimal,          //signed: > 0 surplus, < 0 deficit
+    pub timestamp : u128
+}
+
+//part of a profitable position closed against a liquidation at its bankruptcy price
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Adl {
+    pub liquidation_id : Uuid,
+    pub user_id : UserId,
+    pub symbol : Symbol,
+    pub side : Side,               //side of the deleveraged position
+    pub quantity : Quantity,
+    pub price : Price,
+    pub score : Decimal,
+    pub timestamp : u128
+}
+
+//what one position paid (< 0) or received (> 0) at a funding time
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Funding {
+    pub user_id : UserId,
+    pub symbol : Symbol,
+    pub funding_time : u64,        //unix millis
+    pub position_size : Quantity,
+    pub rate : Decimal,
+    pub amount : Decimal,
+    pub timestamp : u128
+}
+
+//how the engine waits while the event ring is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStrategy {
+    BusySpin,
+    Yield,
+    Park(Duration),
+}
+
+impl WaitStrategy {
+    pub fn wait(&self) {
+        match self {
+            WaitStrategy::BusySpin => std::hint::spin_loop(),
+            WaitStrategy::Yield => std::thread::yield_now(),
+            WaitStrategy::Park(timeout) => std::thread::park_timeout(*timeout),
+        }
     }
-}
\ No newline at end of file
+}
+
+//what the engine does when consumers lag and the event ring is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    Block,  // stall matching until consumers free a slot
+    Spill,  // keep events in an overflow queue and replay them in order
+    Halt,   // stop trading, nothing more is accepted until restart
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackpressureConfig {
+    pub wait: WaitStrategy,
+    pub overflow: OverflowPolicy,
+    pub max_spill: usize,            //spill queue size before the engine goes fatal
+    pub block_timeout: Duration,     //how long Block may stall before the engine goes fatal
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            wait: WaitStrategy::Yield,
+            overflow: OverflowPolicy::Block,
+            max_spill: 65_536,
+            block_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EngineState {
+    Running = 0,
+    Halted  = 1,  //fatal: event ring overflowed, trading stopped so no event is lost
+}
+
+#[derive(Serialize)]
+pub struct EngineHealth {
+    pub state: EngineState,
+    pub engine_alive: bool,
+    pub pipeline_alive: bool,
+    pub batches: u64,
+    pub commands: u64,
+    pub last_batch_age_ms: Option<u64>,
+    pub queued_commands: usize,
+    pub events: EventBufferSnapshot,
+    pub lanes: Vec<LaneSnapshot>,
+}
+
+//written on graceful shutdown, the next start books the orders again
+#[derive(Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub taken_at: u128,
+    pub fill_seq: u64,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub orders: Vec<Order>,  //resting orders in price-time order, bids first
+}
+
@@ -1,6 +1,32 @@
-pub mod auth;
+mod auth;
 pub use auth::*;
-pub mod order;
+mod order;
 pub use order::*;
-pub mod matching_engine;
+mod symbol;
+pub use symbol::*;
+mod matching_engine;
 pub use matching_engine::*;
+mod position;
+pub use position::*;
+mod risk;
+pub use risk::*;
+mod oracle;
+pub use oracle::*;
+mod funding;
+pub use funding::*;
+mod margin;
+pub use margin::*;
+mod transfer;
+pub use transfer::*;
+mod trade;
+pub use trade::*;
+mod candle;
+pub use candle::*;
+mod ticker;
+pub use ticker::*;
+mod depth;
+pub use depth::*;
+mod l3;
+pub use l3::*;
+mod market_data;
+pub use market_data::*;
@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+use crate::{Price, Symbol};
+
+//top of book as seen by the matching thread after its last batch
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct BookPrices {
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub impact_bid: Option<Price>,  //average price to sell the impact notional, None if the book is too thin
+    pub impact_ask: Option<Price>,  //average price to buy the impact notional
+    pub updated_at: u128,
+}
+
+impl BookPrices {
+    pub fn impact_mid(&self) -> Option<Price> {
+        Some((self.impact_bid? + self.impact_ask?) / Price::TWO)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceState {
+    Fresh,
+    Stale,
+    Outlier,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStatus {
+    pub name: String,
+    pub price: Option<Price>,
+    pub age_ms: Option<u64>,
+    pub state: SourceState,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketPrices {
+    pub symbol: Symbol,
+    pub index_price: Option<Price>,
+    pub mark_price: Option<Price>,
+    pub basis: Price,  //smoothed, mark = index + basis
+    pub book: BookPrices,
+    pub sources: Vec<SourceStatus>,
+    pub updated_at: u128,
+}
+
+impl MarketPrices {
+    pub fn new(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            index_price: None,
+            mark_price: None,
+            basis: Price::ZERO,
+            book: BookPrices::default(),
+            sources: Vec::new(),
+            updated_at: 0,
+        }
+    }
+}
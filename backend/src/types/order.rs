@@ -2,8 +2,11 @@ pub use serde::{Serialize,Deserialize};
 use tokio::sync::oneshot;
 use uuid::Uuid;
 use std::fmt;
+use std::str::FromStr;
+use db::OrderRow;
+use rust_decimal::Decimal;
 
-use crate::{Order, OrderId, Price, Quantity, UserId};
+use crate::{Order, OrderId, Price, Quantity, Symbol, UserId};
 
 
 
@@ -12,6 +15,7 @@ pub struct OrderRequest {
     #[serde(rename = "type")]
     pub type_: OrderType,
     pub user_id : Uuid,
+    pub symbol: Symbol,
     pub side: Side,
     pub quantity: f64,
     pub price: Option<f64>,
@@ -30,13 +34,125 @@ pub enum OrderType {
     Limit,
 }
 
-#[derive(Deserialize, Serialize,Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Side {
     Buy,
     Sell,
 }
 
+impl Side {
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buy" => Ok(Side::Buy),
+            "sell" => Ok(Side::Sell),
+            _ => Err(format!("unknown side {s}")),
+        }
+    }
+}
+
+impl OrderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Market => "market",
+            OrderType::Limit => "limit",
+        }
+    }
+}
+
+//lifecycle of an order as persisted, see the persistence consumer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderState {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+impl OrderState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderState::New => "new",
+            OrderState::PartiallyFilled => "partially_filled",
+            OrderState::Filled => "filled",
+            OrderState::Cancelled => "cancelled",
+            OrderState::Expired => "expired",
+            OrderState::Rejected => "rejected",
+        }
+    }
+}
+
+//a resting order as the engine holds it right now
+#[derive(Serialize)]
+pub struct OpenOrder {
+    pub order_id: OrderId,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub price: Option<Price>,
+    pub quantity: Quantity,
+    pub filled: Quantity,
+    pub remaining: Quantity,
+    pub leverage: Decimal,
+}
+
+impl From<&Order> for OpenOrder {
+    fn from(order: &Order) -> Self {
+        Self {
+            order_id: order.order_id,
+            symbol: order.symbol,
+            side: order.side,
+            price: order.price,
+            quantity: order.quantity,
+            filled: order.filled,
+            remaining: order.remaining(),
+            leverage: order.leverage,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OpenOrdersQuery {
+    pub symbol: Option<Symbol>,
+}
+
+//from / to are unix millis, cursor is the next_cursor of the previous page
+#[derive(Deserialize)]
+pub struct OrderHistoryQuery {
+    pub symbol: Option<Symbol>,
+    pub status: Option<OrderState>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct OrderHistoryPage {
+    pub orders: Vec<OrderRow>,
+    pub next_cursor: Option<String>,  //None on the last page
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Response {
     pub message: String,
@@ -44,7 +160,7 @@ pub struct Response {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum Priority {
     Critical = 0,
     High     = 1,
@@ -52,6 +168,17 @@ pub enum Priority {
     Low      = 3,
 }
 
+impl Priority {
+    pub fn from_lane(lane: usize) -> Self {
+        match lane {
+            0 => Priority::Critical,
+            1 => Priority::High,
+            2 => Priority::Normal,
+            _ => Priority::Low,
+        }
+    }
+}
+
 pub enum OrderStatus {
     Accepted,
     FullyFilled,
@@ -106,6 +233,26 @@ pub enum OrderBookMessage {
     UpdateMarkPrice {
         price: Price,
     },
+    //read only: the user's resting orders, answered in order with the user's other commands
+    OpenOrders {
+        user_id: UserId,
+        symbol: Option<Symbol>,
+        responder: oneshot::Sender<Vec<OpenOrder>>,
+    },
+    //sent by the liquidation engine, the engine re-checks the position before taking it over
+    Liquidate {
+        user_id: UserId,
+        symbol: Symbol,
+        mark_price: Price,
+        bankruptcy_price: Price,
+    },
+    //sent by the funding engine at a funding time, the engine pays it on the positions it holds
+    SettleFunding {
+        symbol: Symbol,
+        funding_time: u64,  //unix millis
+        rate: Decimal,
+        mark_price: Price,
+    },
 }
 
 impl OrderBookMessage {
@@ -114,6 +261,21 @@ impl OrderBookMessage {
             OrderBookMessage::PlaceOrder { priority, .. } => *priority,
             OrderBookMessage::CancelOrder { .. } => Priority::Critical,
             OrderBookMessage::UpdateMarkPrice { .. } => Priority::Critical,
+            OrderBookMessage::OpenOrders { .. } => Priority::Low,
+            OrderBookMessage::Liquidate { .. } => Priority::Critical,
+            OrderBookMessage::SettleFunding { .. } => Priority::Critical,
+        }
+    }
+
+    //commands of the same user are kept in arrival order by the engine
+    pub fn user_id(&self) -> Option<UserId> {
+        match self {
+            OrderBookMessage::PlaceOrder { order, .. } => Some(order.user_id),
+            OrderBookMessage::CancelOrder { user_id, .. } => Some(*user_id),
+            OrderBookMessage::UpdateMarkPrice { .. } => None,
+            OrderBookMessage::OpenOrders { user_id, .. } => Some(*user_id),
+            OrderBookMessage::Liquidate { user_id, .. } => Some(*user_id),
+            OrderBookMessage::SettleFunding { .. } => None,
         }
     }
 }
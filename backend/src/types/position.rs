@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+use crate::Symbol;
+
+#[derive(Deserialize)]
+pub struct PositionsQuery {
+    pub symbol: Option<Symbol>,
+}
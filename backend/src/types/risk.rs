@@ -0,0 +1,88 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{LeverageBracket, Symbol, UserId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountTier {
+    #[default]
+    Standard,
+    Pro,
+    Institutional,
+}
+
+impl AccountTier {
+    pub const ALL: [AccountTier; 3] = [AccountTier::Standard, AccountTier::Pro, AccountTier::Institutional];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountTier::Standard => "standard",
+            AccountTier::Pro => "pro",
+            AccountTier::Institutional => "institutional",
+        }
+    }
+}
+
+impl FromStr for AccountTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AccountTier::ALL
+            .into_iter()
+            .find(|tier| tier.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown account tier {s}"))
+    }
+}
+
+//one code per limit, clients match on the code, the detail is for humans
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RiskRejectCode {
+    MaxOpenOrders,
+    MaxPositionSize,
+    MaxOrderNotional,
+    MaxRestingNotional,
+    MaxLeverage,
+}
+
+impl RiskRejectCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RiskRejectCode::MaxOpenOrders => "RISK_MAX_OPEN_ORDERS",
+            RiskRejectCode::MaxPositionSize => "RISK_MAX_POSITION_SIZE",
+            RiskRejectCode::MaxOrderNotional => "RISK_MAX_ORDER_NOTIONAL",
+            RiskRejectCode::MaxRestingNotional => "RISK_MAX_RESTING_NOTIONAL",
+            RiskRejectCode::MaxLeverage => "RISK_MAX_LEVERAGE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskRejection {
+    pub code: RiskRejectCode,
+    pub detail: String,
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.detail)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BracketQuery {
+    pub symbol: Symbol,
+}
+
+#[derive(Serialize)]
+pub struct UserBracket {
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub position_notional: Decimal,  //at mark, entry price without one
+    pub leverage: Option<Decimal>,   //of the open position
+    pub maintenance_margin: Decimal,
+    pub bracket: LeverageBracket,
+}
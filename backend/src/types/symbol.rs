@@ -0,0 +1,67 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MAX_SYMBOL_LEN: usize = 15;
+
+//instrument name like "BTC-PERP", stored inline so Fill and Order stay Copy-friendly
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol {
+    len: u8,
+    bytes: [u8; MAX_SYMBOL_LEN],
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Result<Self, String> {
+        if name.is_empty() || name.len() > MAX_SYMBOL_LEN {
+            return Err(format!("symbol must be 1-{MAX_SYMBOL_LEN} characters"));
+        }
+        if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+            return Err("symbol may only contain letters, digits, '-' and '_'".to_string());
+        }
+        let mut bytes = [0u8; MAX_SYMBOL_LEN];
+        for (slot, b) in bytes.iter_mut().zip(name.bytes()) {
+            *slot = b.to_ascii_uppercase();
+        }
+        Ok(Self { len: name.len() as u8, bytes })
+    }
+
+    pub fn as_str(&self) -> &str {
+        //only ascii goes in, see new()
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl FromStr for Symbol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Symbol::new(s)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({})", self.as_str())
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Symbol::new(&name).map_err(serde::de::Error::custom)
+    }
+}
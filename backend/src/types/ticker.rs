@@ -0,0 +1,26 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{Price, Quantity, Symbol};
+
+//rolling 24h statistics, built from one-minute buckets
+#[derive(Debug, Clone, Serialize)]
+pub struct Ticker {
+    pub symbol: Symbol,
+    pub open: Option<Price>,             //first trade of the window
+    pub high: Option<Price>,
+    pub low: Option<Price>,
+    pub last: Option<Price>,             //last trade, also when it is older than the window
+    pub volume: Quantity,                //base
+    pub quote_volume: Decimal,
+    pub trades: u64,
+    pub price_change: Option<Decimal>,   //last - open
+    pub price_change_percent: Option<Decimal>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub mark_price: Option<Price>,
+    pub index_price: Option<Price>,
+    pub open_interest: Quantity,         //sum of long position sizes
+    pub window_start: i64,               //unix millis
+    pub updated_at: i64,
+}
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Utc};
+use db::{FillRecord, TradeRecord};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::Symbol;
+
+//a print as the public sees it: no order ids, no users
+#[derive(Serialize)]
+pub struct PublicTrade {
+    pub trade_id: i64,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub side: String,  //aggressor (taker) side
+    pub time: DateTime<Utc>,
+}
+
+impl From<TradeRecord> for PublicTrade {
+    fn from(t: TradeRecord) -> Self {
+        Self { trade_id: t.trade_id, price: t.price, quantity: t.quantity, side: t.taker_side, time: t.traded_at }
+    }
+}
+
+//cursor is the next_cursor of the previous page
+#[derive(Deserialize)]
+pub struct TradesQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct TradePage {
+    pub trades: Vec<PublicTrade>,
+    pub next_cursor: Option<String>,  //None on the last page
+}
+
+#[derive(Deserialize)]
+pub struct FillsQuery {
+    pub symbol: Option<Symbol>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct FillPage {
+    pub fills: Vec<FillRecord>,
+    pub next_cursor: Option<String>,
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::UserId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WithdrawalStatus {
+    Pending,
+    Approved,
+    Processing,
+    Completed,
+    Rejected,
+}
+
+impl WithdrawalStatus {
+    pub const ALL: [WithdrawalStatus; 5] = [
+        WithdrawalStatus::Pending,
+        WithdrawalStatus::Approved,
+        WithdrawalStatus::Processing,
+        WithdrawalStatus::Completed,
+        WithdrawalStatus::Rejected,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WithdrawalStatus::Pending => "pending",
+            WithdrawalStatus::Approved => "approved",
+            WithdrawalStatus::Processing => "processing",
+            WithdrawalStatus::Completed => "completed",
+            WithdrawalStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromStr for WithdrawalStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WithdrawalStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("unknown withdrawal status {s}"))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WithdrawalRequest {
+    pub amount: Decimal,
+    pub address: String,
+}
+
+#[derive(Deserialize)]
+pub struct WithdrawalQuery {
+    pub status: Option<WithdrawalStatus>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct DepositQuery {
+    pub limit: Option<i64>,
+}
+
+//admin views, all users unless one is given
+#[derive(Deserialize)]
+pub struct AdminWithdrawalQuery {
+    pub user_id: Option<UserId>,
+    pub status: Option<WithdrawalStatus>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct AdminDepositQuery {
+    pub user_id: Option<UserId>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct RejectWithdrawalRequest {
+    pub reason: String,
+}
+
+#[derive(Deserialize)]
+pub struct SimulateDepositRequest {
+    pub user_id: UserId,
+    pub amount: Decimal,
+}
+
+#[derive(Serialize)]
+pub struct SimulateDepositResponse {
+    pub custody_ref: String,
+}
@@ -0,0 +1,2 @@
+-- risk limits are configured per tier, see backend risk_engine
+ALTER TABLE users ADD COLUMN tier text NOT NULL DEFAULT 'standard';
@@ -0,0 +1,24 @@
+-- one row per symbol and funding interval
+CREATE TABLE funding_rates (
+    id BIGSERIAL PRIMARY KEY,
+    symbol text NOT NULL,
+    funding_time timestamptz NOT NULL,
+    rate numeric NOT NULL,
+    premium_index numeric NOT NULL,
+    index_price numeric NOT NULL,
+    mark_price numeric NOT NULL,
+    UNIQUE (symbol, funding_time)
+);
+
+-- what every open position paid (negative) or received at a funding time
+CREATE TABLE funding_payments (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL,
+    symbol text NOT NULL,
+    funding_time timestamptz NOT NULL,
+    position_size numeric NOT NULL,
+    rate numeric NOT NULL,
+    amount numeric NOT NULL
+);
+
+CREATE INDEX funding_payments_user_idx ON funding_payments (user_id, funding_time);
@@ -0,0 +1,54 @@
+-- double-entry ledger: every balance change is a journal entry whose postings sum to zero per asset.
+-- user money lives in (user id, 'wallet'), the other side in system accounts owned by the nil uuid
+CREATE TABLE ledger_accounts (
+    id BIGSERIAL PRIMARY KEY,
+    owner_id UUID NOT NULL,
+    name text NOT NULL,
+    asset text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (owner_id, name, asset)
+);
+
+-- reference is the caller's idempotency key, posting the same reference twice is a no-op
+CREATE TABLE journal_entries (
+    id BIGSERIAL PRIMARY KEY,
+    reference text NOT NULL UNIQUE,
+    kind text NOT NULL,
+    description text NOT NULL DEFAULT '',
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE postings (
+    id BIGSERIAL PRIMARY KEY,
+    entry_id BIGINT NOT NULL REFERENCES journal_entries (id),
+    account_id BIGINT NOT NULL REFERENCES ledger_accounts (id),
+    asset text NOT NULL,
+    amount numeric NOT NULL
+);
+
+CREATE INDEX postings_entry_idx ON postings (entry_id);
+CREATE INDEX postings_account_idx ON postings (account_id);
+
+-- checked at commit, so an entry can be written posting by posting inside one transaction
+CREATE FUNCTION check_entry_balanced() RETURNS trigger AS $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM postings WHERE entry_id = NEW.entry_id
+        GROUP BY asset HAVING SUM(amount) <> 0
+    ) THEN
+        RAISE EXCEPTION 'journal entry % does not balance', NEW.entry_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER postings_balanced
+    AFTER INSERT OR UPDATE ON postings
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE FUNCTION check_entry_balanced();
+
+CREATE VIEW ledger_balances AS
+SELECT a.id AS account_id, a.owner_id, a.name, a.asset, COALESCE(SUM(p.amount), 0) AS balance
+FROM ledger_accounts a
+LEFT JOIN postings p ON p.account_id = a.id
+GROUP BY a.id;
@@ -0,0 +1,29 @@
+-- money coming in through custody, custody_ref makes crediting idempotent
+CREATE TABLE deposits (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    asset text NOT NULL,
+    amount numeric NOT NULL CHECK (amount > 0),
+    custody_ref text NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX deposits_user_idx ON deposits (user_id, created_at);
+
+-- pending -> approved -> processing -> completed, or rejected from any state before completed
+CREATE TABLE withdrawals (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    asset text NOT NULL,
+    amount numeric NOT NULL CHECK (amount > 0),
+    address text NOT NULL,
+    status text NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'processing', 'completed', 'rejected')),
+    custody_ref text,
+    reason text,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX withdrawals_user_idx ON withdrawals (user_id, created_at);
+CREATE INDEX withdrawals_status_idx ON withdrawals (status);
@@ -0,0 +1,71 @@
+-- written from the engine's event stream by the persistence consumer.
+-- every order the engine accepted or rejected; filled/status follow its fills, cancels and expiry
+CREATE TABLE orders (
+    order_id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    symbol text NOT NULL,
+    side text NOT NULL,
+    order_type text NOT NULL,
+    price numeric,
+    quantity numeric NOT NULL,
+    leverage numeric NOT NULL,
+    filled numeric NOT NULL DEFAULT 0,
+    filled_notional numeric NOT NULL DEFAULT 0,
+    status text NOT NULL
+        CHECK (status IN ('new', 'partially_filled', 'filled', 'cancelled', 'expired', 'rejected')),
+    reason text,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+CREATE INDEX orders_user_idx ON orders (user_id, created_at);
+
+-- one row per state change, keyed by the event that caused it
+CREATE TABLE order_updates (
+    event_seq BIGINT NOT NULL,
+    order_id UUID NOT NULL REFERENCES orders (order_id),
+    status text NOT NULL,
+    filled_qty numeric NOT NULL DEFAULT 0,
+    reason text,
+    at timestamptz NOT NULL,
+    PRIMARY KEY (event_seq, order_id)
+);
+
+-- public prints, trade_id is the sequence of the fill event
+CREATE TABLE trades (
+    trade_id BIGINT PRIMARY KEY,
+    symbol text NOT NULL,
+    price numeric NOT NULL,
+    quantity numeric NOT NULL,
+    taker_side text NOT NULL,
+    maker_order_id UUID NOT NULL,
+    taker_order_id UUID NOT NULL,
+    traded_at timestamptz NOT NULL
+);
+
+CREATE INDEX trades_symbol_idx ON trades (symbol, trade_id);
+
+-- both sides of every trade
+CREATE TABLE fills (
+    trade_id BIGINT NOT NULL REFERENCES trades (trade_id),
+    user_id UUID NOT NULL,
+    order_id UUID NOT NULL,
+    symbol text NOT NULL,
+    side text NOT NULL,
+    liquidity text NOT NULL CHECK (liquidity IN ('maker', 'taker')),
+    price numeric NOT NULL,
+    quantity numeric NOT NULL,
+    fee numeric NOT NULL,
+    fee_asset text,
+    filled_at timestamptz NOT NULL,
+    PRIMARY KEY (trade_id, liquidity)
+);
+
+CREATE INDEX fills_user_idx ON fills (user_id, trade_id);
+
+-- last event sequence each consumer has durably written, it resumes after it
+CREATE TABLE event_offsets (
+    consumer text PRIMARY KEY,
+    last_seq BIGINT NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
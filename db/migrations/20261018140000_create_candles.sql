@@ -0,0 +1,15 @@
+-- closed OHLCV bars; kind 'trade' is built from fills, 'mark' from mark price updates
+CREATE TABLE candles (
+    symbol text NOT NULL,
+    kind text NOT NULL CHECK (kind IN ('trade', 'mark')),
+    interval text NOT NULL,
+    open_time BIGINT NOT NULL,  -- unix millis
+    open numeric NOT NULL,
+    high numeric NOT NULL,
+    low numeric NOT NULL,
+    close numeric NOT NULL,
+    volume numeric NOT NULL,        -- base quantity, 0 for mark candles
+    quote_volume numeric NOT NULL,
+    trades BIGINT NOT NULL,
+    PRIMARY KEY (symbol, kind, interval, open_time)
+);
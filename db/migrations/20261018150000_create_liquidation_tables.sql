@@ -0,0 +1,31 @@
+-- written from the engine's event stream by the persistence consumer, keyed by the event's sequence.
+-- positions taken over by the liquidation account
+CREATE TABLE liquidations (
+    event_seq BIGINT PRIMARY KEY,
+    liquidation_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    symbol text NOT NULL,
+    side text NOT NULL,
+    quantity numeric NOT NULL,
+    mark_price numeric NOT NULL,
+    bankruptcy_price numeric NOT NULL,
+    fee numeric NOT NULL,
+    liquidated_at timestamptz NOT NULL
+);
+
+CREATE INDEX liquidations_user_idx ON liquidations (user_id, event_seq);
+
+-- profitable positions closed against a liquidation the insurance fund could not cover
+CREATE TABLE adl (
+    event_seq BIGINT PRIMARY KEY,
+    liquidation_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    symbol text NOT NULL,
+    side text NOT NULL,
+    quantity numeric NOT NULL,
+    price numeric NOT NULL,
+    score numeric NOT NULL,
+    deleveraged_at timestamptz NOT NULL
+);
+
+CREATE INDEX adl_user_idx ON adl (user_id, event_seq);
@@ -0,0 +1,3 @@
+-- funding is settled by the engine and payments are written from its event stream like fills;
+-- rows from before that have no sequence
+ALTER TABLE funding_payments ADD COLUMN event_seq BIGINT UNIQUE;
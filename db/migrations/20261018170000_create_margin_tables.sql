@@ -0,0 +1,20 @@
+-- margin settings made over HTTP, replayed with the stored fills to rebuild positions at startup.
+-- symbols without a row are cross
+CREATE TABLE margin_modes (
+    user_id UUID NOT NULL,
+    symbol text NOT NULL,
+    mode text NOT NULL CHECK (mode IN ('cross', 'isolated')),
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, symbol)
+);
+
+-- margin added to (> 0) or removed from (< 0) an isolated position
+CREATE TABLE isolated_margin_adjustments (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL,
+    symbol text NOT NULL,
+    amount numeric NOT NULL,
+    adjusted_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX isolated_margin_adjustments_symbol_idx ON isolated_margin_adjustments (symbol, adjusted_at);
@@ -0,0 +1,92 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{Db, TradeRecord};
+
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct Candle {
+    pub symbol : String,
+    pub kind : String,      //trade / mark
+    pub interval : String,  //1m, 5m, 15m, 1h, 4h, 1d
+    pub open_time : i64,    //unix millis
+    pub open : Decimal,
+    pub high : Decimal,
+    pub low : Decimal,
+    pub close : Decimal,
+    pub volume : Decimal,
+    pub quote_volume : Decimal,
+    pub trades : i64
+}
+
+impl Db {
+    //a bar rebuilt after a restart replaces the stored one
+    pub async fn upsert_candles(&self, candles:&[Candle])->Result<()>{
+        if candles.is_empty() {
+            return Ok(());
+        }
+        sqlx::query!(
+            "INSERT INTO candles (symbol,kind,interval,open_time,open,high,low,close,volume,quote_volume,trades)
+             SELECT * FROM UNNEST($1::text[],$2::text[],$3::text[],$4::bigint[],$5::numeric[],$6::numeric[],$7::numeric[],$8::numeric[],$9::numeric[],$10::numeric[],$11::bigint[])
+             ON CONFLICT (symbol,kind,interval,open_time) DO UPDATE SET
+                open=EXCLUDED.open, high=EXCLUDED.high, low=EXCLUDED.low, close=EXCLUDED.close,
+                volume=EXCLUDED.volume, quote_volume=EXCLUDED.quote_volume, trades=EXCLUDED.trades",
+            &candles.iter().map(|c| c.symbol.clone()).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.kind.clone()).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.interval.clone()).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.open_time).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.open).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.high).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.low).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.close).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.volume).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.quote_volume).collect::<Vec<_>>(),
+            &candles.iter().map(|c| c.trades).collect::<Vec<_>>()
+        )
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    //the latest `limit` bars with from <= open_time < to, oldest first
+    pub async fn get_candles(&self, symbol:&str, kind:&str, interval:&str, from:Option<i64>, to:Option<i64>, limit:i64)->Result<Vec<Candle>>{
+        let mut rows = sqlx::query_as!(
+            Candle,
+            "SELECT symbol,kind,interval,open_time,open,high,low,close,volume,quote_volume,trades FROM candles
+             WHERE symbol=$1 AND kind=$2 AND interval=$3
+               AND ($4::bigint IS NULL OR open_time>=$4) AND ($5::bigint IS NULL OR open_time<$5)
+             ORDER BY open_time DESC LIMIT $6",
+            symbol, kind, interval, from, to, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        rows.reverse();
+        Ok(rows)
+    }
+
+    //open time of the newest stored bar per interval
+    pub async fn get_last_candle_times(&self, symbol:&str, kind:&str)->Result<Vec<(String, i64)>>{
+        let rows = sqlx::query!(
+            r#"SELECT interval, max(open_time) as "open_time!" FROM candles WHERE symbol=$1 AND kind=$2 GROUP BY interval"#,
+            symbol, kind
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.interval, r.open_time)).collect())
+    }
+
+    //oldest first, for rebuilding bars
+    pub async fn get_trades_since(&self, symbol:&str, since:Option<DateTime<Utc>>)->Result<Vec<TradeRecord>>{
+        let rows = sqlx::query_as!(
+            TradeRecord,
+            "SELECT trade_id,symbol,price,quantity,taker_side,maker_order_id,taker_order_id,traded_at FROM trades
+             WHERE symbol=$1 AND ($2::timestamptz IS NULL OR traded_at>=$2)
+             ORDER BY trade_id",
+            symbol, since
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Db;
+
+#[derive(Serialize,Deserialize)]
+pub struct FundingRate {
+    pub symbol : String,
+    pub funding_time : DateTime<Utc>,
+    pub rate : Decimal,
+    pub premium_index : Decimal,
+    pub index_price : Decimal,
+    pub mark_price : Decimal
+}
+
+//written from the engine's Funding events, keyed by the event's sequence
+pub struct FundingPayment {
+    pub event_seq : i64,
+    pub user_id : Uuid,
+    pub symbol : String,
+    pub funding_time : DateTime<Utc>,
+    pub position_size : Decimal,
+    pub rate : Decimal,
+    pub amount : Decimal
+}
+
+impl Db {
+    //the payments follow through the event stream, see persist_events
+    pub async fn record_funding(&self, rate:&FundingRate)->Result<()>{
+        sqlx::query!(
+            "INSERT INTO funding_rates (symbol,funding_time,rate,premium_index,index_price,mark_price) VALUES ($1,$2,$3,$4,$5,$6)
+             ON CONFLICT (symbol,funding_time) DO NOTHING",
+            rate.symbol, rate.funding_time, rate.rate, rate.premium_index, rate.index_price, rate.mark_price
+        )
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_funding_history(&self, symbol:&str, limit:i64)->Result<Vec<FundingRate>>{
+        let rows = sqlx::query_as!(
+            FundingRate,
+            "SELECT symbol,funding_time,rate,premium_index,index_price,mark_price FROM funding_rates WHERE symbol=$1 ORDER BY funding_time DESC LIMIT $2",
+            symbol, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+}
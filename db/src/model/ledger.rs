@@ -0,0 +1,240 @@
+use anyhow::{Result, bail};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::Db;
+
+//owner of the system accounts
+pub const SYSTEM_OWNER: Uuid = Uuid::nil();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerAccount {
+    Wallet(Uuid),     //a user's collateral
+    Custody,          //money that came in from / went out to the outside world
+    FeeRevenue,
+    FundingClearing,  //funding is zero-sum across users, this nets to zero per funding time
+    PnlClearing,      //same for realized PnL across counterparties
+}
+
+impl LedgerAccount {
+    pub fn key(&self) -> (Uuid, &'static str) {
+        match self {
+            LedgerAccount::Wallet(user_id) => (*user_id, "wallet"),
+            LedgerAccount::Custody => (SYSTEM_OWNER, "custody"),
+            LedgerAccount::FeeRevenue => (SYSTEM_OWNER, "fee_revenue"),
+            LedgerAccount::FundingClearing => (SYSTEM_OWNER, "funding_clearing"),
+            LedgerAccount::PnlClearing => (SYSTEM_OWNER, "pnl_clearing"),
+        }
+    }
+}
+
+pub struct JournalEntry {
+    pub reference: String,  //idempotency key
+    pub kind: &'static str,
+    pub description: String,
+    pub asset: String,
+    pub postings: Vec<(LedgerAccount, Decimal)>,
+}
+
+impl JournalEntry {
+    //double entry: at least two postings that sum to zero
+    pub fn check(&self) -> Result<()> {
+        if self.postings.len() < 2 {
+            bail!("journal entry {} needs at least two postings", self.reference);
+        }
+        let total: Decimal = self.postings.iter().map(|(_, amount)| amount).sum();
+        if !total.is_zero() {
+            bail!("journal entry {} does not balance: {total}", self.reference);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LedgerBalance {
+    pub name: String,
+    pub asset: String,
+    pub balance: Decimal,
+}
+
+impl Db {
+    //Some(entry id) when posted, None when the reference was already posted before
+    pub async fn post_entry(&self, entry: &JournalEntry) -> Result<Option<i64>> {
+        let mut tx = self.pool.begin().await?;
+        let id = insert_entry(&mut tx, entry).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    pub async fn post_deposit(&self, reference: &str, user_id: Uuid, asset: &str, amount: Decimal) -> Result<Option<i64>> {
+        self.post_transfer(reference, "deposit", user_id, LedgerAccount::Custody, asset, amount).await
+    }
+
+    pub async fn post_withdrawal(&self, reference: &str, user_id: Uuid, asset: &str, amount: Decimal) -> Result<Option<i64>> {
+        self.post_transfer(reference, "withdrawal", user_id, LedgerAccount::Custody, asset, -amount).await
+    }
+
+    //entries already posted are skipped; returns how many were new
+    pub async fn post_entries(&self, entries: &[JournalEntry]) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        let mut posted = 0;
+        for entry in entries {
+            if insert_entry(&mut tx, entry).await?.is_some() {
+                posted += 1;
+            }
+        }
+        tx.commit().await?;
+        Ok(posted)
+    }
+
+    //fee paid by the user, a negative fee is a rebate
+    pub async fn post_fee(&self, reference: &str, user_id: Uuid, asset: &str, fee: Decimal) -> Result<Option<i64>> {
+        self.post_entry(&fee_entry(reference, user_id, asset, fee)).await
+    }
+
+    //funding received by the user, negative when paid
+    pub async fn post_funding(&self, reference: &str, user_id: Uuid, asset: &str, amount: Decimal) -> Result<Option<i64>> {
+        self.post_entry(&funding_entry(reference, user_id, asset, amount)).await
+    }
+
+    pub async fn post_realized_pnl(&self, reference: &str, user_id: Uuid, asset: &str, pnl: Decimal) -> Result<Option<i64>> {
+        self.post_entry(&realized_pnl_entry(reference, user_id, asset, pnl)).await
+    }
+
+    async fn post_transfer(&self, reference: &str, kind: &'static str, user_id: Uuid, counter: LedgerAccount, asset: &str, amount: Decimal) -> Result<Option<i64>> {
+        self.post_entry(&transfer_entry(reference, kind, user_id, counter, asset, amount)).await
+    }
+
+    pub async fn get_ledger_balances(&self, owner_id: Uuid) -> Result<Vec<LedgerBalance>> {
+        let rows = sqlx::query_as!(
+            LedgerBalance,
+            r#"SELECT name as "name!", asset as "asset!", balance as "balance!" FROM ledger_balances WHERE owner_id=$1 ORDER BY name, asset"#,
+            owner_id
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    //every user's wallet balance in `asset`, what the in-memory wallet is rebuilt from
+    pub async fn get_wallet_balances(&self, asset: &str) -> Result<Vec<(Uuid, Decimal)>> {
+        let rows = sqlx::query!(
+            r#"SELECT owner_id as "owner_id!", balance as "balance!" FROM ledger_balances WHERE name='wallet' AND asset=$1"#,
+            asset
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.owner_id, r.balance)).collect())
+    }
+
+    pub async fn get_ledger_balance(&self, account: LedgerAccount, asset: &str) -> Result<Decimal> {
+        let (owner_id, name) = account.key();
+        let balance = sqlx::query_scalar!(
+            r#"SELECT balance as "balance!" FROM ledger_balances WHERE owner_id=$1 AND name=$2 AND asset=$3"#,
+            owner_id, name, asset
+        )
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(balance.unwrap_or_default())
+    }
+}
+
+//for callers that post inside their own transaction, e.g. a deposit row and its entry together
+pub async fn insert_entry(conn: &mut PgConnection, entry: &JournalEntry) -> Result<Option<i64>> {
+    entry.check()?;
+
+    let inserted = sqlx::query!(
+        "INSERT INTO journal_entries (reference,kind,description) VALUES ($1,$2,$3) ON CONFLICT (reference) DO NOTHING RETURNING id",
+        entry.reference, entry.kind, entry.description
+    )
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some(inserted) = inserted else {
+        return Ok(None);
+    };
+    for (account, amount) in &entry.postings {
+        let (owner_id, name) = account.key();
+        let account_id = sqlx::query_scalar!(
+            "INSERT INTO ledger_accounts (owner_id,name,asset) VALUES ($1,$2,$3)
+             ON CONFLICT (owner_id,name,asset) DO UPDATE SET name=EXCLUDED.name RETURNING id",
+            owner_id, name, entry.asset
+        )
+            .fetch_one(&mut *conn)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO postings (entry_id,account_id,asset,amount) VALUES ($1,$2,$3,$4)",
+            inserted.id, account_id, entry.asset, amount
+        )
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(Some(inserted.id))
+}
+
+//`amount` into the user's wallet, out of `counter`
+pub fn transfer_entry(reference: &str, kind: &'static str, user_id: Uuid, counter: LedgerAccount, asset: &str, amount: Decimal) -> JournalEntry {
+    JournalEntry {
+        reference: reference.to_string(),
+        kind,
+        description: String::new(),
+        asset: asset.to_string(),
+        postings: vec![(LedgerAccount::Wallet(user_id), amount), (counter, -amount)],
+    }
+}
+
+pub fn fee_entry(reference: &str, user_id: Uuid, asset: &str, fee: Decimal) -> JournalEntry {
+    transfer_entry(reference, "fee", user_id, LedgerAccount::FeeRevenue, asset, -fee)
+}
+
+pub fn funding_entry(reference: &str, user_id: Uuid, asset: &str, amount: Decimal) -> JournalEntry {
+    transfer_entry(reference, "funding", user_id, LedgerAccount::FundingClearing, asset, amount)
+}
+
+pub fn realized_pnl_entry(reference: &str, user_id: Uuid, asset: &str, pnl: Decimal) -> JournalEntry {
+    transfer_entry(reference, "realized_pnl", user_id, LedgerAccount::PnlClearing, asset, pnl)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    fn deposit(reference: &str, user_id: Uuid, amount: i64) -> JournalEntry {
+        transfer_entry(reference, "deposit", user_id, LedgerAccount::Custody, "USDT", Decimal::from(amount))
+    }
+
+    #[test]
+    fn an_unbalanced_entry_is_rejected() {
+        let user_id = Uuid::from_u128(7);
+        assert!(deposit("dep-1", user_id, 5).check().is_ok());
+
+        let mut unbalanced = deposit("dep-2", user_id, 5);
+        unbalanced.postings[1].1 = Decimal::from(-4);
+        assert!(unbalanced.check().is_err());
+
+        let mut one_sided = deposit("dep-3", user_id, 5);
+        one_sided.postings.pop();
+        assert!(one_sided.check().is_err());
+    }
+
+    #[sqlx::test]
+    async fn a_reference_is_posted_once(pool: PgPool) {
+        let db = Db { pool };
+        let user_id = Uuid::from_u128(7);
+
+        assert!(db.post_entry(&deposit("dep-1", user_id, 5)).await.unwrap().is_some());
+        assert!(db.post_entry(&deposit("dep-1", user_id, 5)).await.unwrap().is_none());
+        assert_eq!(db.post_entries(&[deposit("dep-1", user_id, 5), deposit("dep-2", user_id, 3)]).await.unwrap(), 1);
+
+        let mut unbalanced = deposit("dep-3", user_id, 5);
+        unbalanced.postings[1].1 = Decimal::from(-4);
+        assert!(db.post_entry(&unbalanced).await.is_err());
+
+        let wallet = db.get_ledger_balance(LedgerAccount::Wallet(user_id), "USDT").await.unwrap();
+        let custody = db.get_ledger_balance(LedgerAccount::Custody, "USDT").await.unwrap();
+        assert_eq!((wallet, custody), (Decimal::from(8), Decimal::from(-8)));
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::Db;
+
+pub struct MarginAdjustment {
+    pub user_id : Uuid,
+    pub amount : Decimal,
+    pub adjusted_at : DateTime<Utc>
+}
+
+impl Db {
+    //cross is the default and is stored by removing the row
+    pub async fn set_margin_mode(&self, user_id:Uuid, symbol:&str, mode:&str)->Result<()>{
+        if mode == "cross" {
+            sqlx::query!("DELETE FROM margin_modes WHERE user_id=$1 AND symbol=$2", user_id, symbol)
+                .execute(&self.pool)
+                .await?;
+            return Ok(());
+        }
+        sqlx::query!(
+            "INSERT INTO margin_modes (user_id,symbol,mode) VALUES ($1,$2,$3)
+             ON CONFLICT (user_id,symbol) DO UPDATE SET mode=EXCLUDED.mode, updated_at=now()",
+            user_id, symbol, mode
+        )
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_margin_modes(&self, symbol:&str)->Result<Vec<(Uuid,String)>>{
+        let rows = sqlx::query!("SELECT user_id, mode FROM margin_modes WHERE symbol=$1", symbol)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.user_id, r.mode)).collect())
+    }
+
+    pub async fn record_margin_adjustment(&self, user_id:Uuid, symbol:&str, amount:Decimal)->Result<()>{
+        sqlx::query!("INSERT INTO isolated_margin_adjustments (user_id,symbol,amount) VALUES ($1,$2,$3)", user_id, symbol, amount)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    //oldest first
+    pub async fn get_margin_adjustments(&self, symbol:&str)->Result<Vec<MarginAdjustment>>{
+        let rows = sqlx::query_as!(
+            MarginAdjustment,
+            "SELECT user_id,amount,adjusted_at FROM isolated_margin_adjustments WHERE symbol=$1 ORDER BY adjusted_at, id",
+            symbol
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+}
@@ -1,2 +1,14 @@
 pub mod user;
-pub use user::*;
\ No newline at end of file
+pub use user::*;
+pub mod funding;
+pub use funding::*;
+pub mod ledger;
+pub use ledger::*;
+pub mod transfer;
+pub use transfer::*;
+pub mod order;
+pub use order::*;
+pub mod candle;
+pub use candle::*;
+pub mod margin;
+pub use margin::*;
\ No newline at end of file
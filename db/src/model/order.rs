@@ -0,0 +1,505 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Db, FundingPayment};
+
+//an order as the engine accepted (status "new") or rejected it
+#[derive(Serialize,Deserialize,Clone)]
+pub struct OrderRecord {
+    pub event_seq : i64,
+    pub order_id : Uuid,
+    pub user_id : Uuid,
+    pub symbol : String,
+    pub side : String,
+    pub order_type : String,
+    pub price : Option<Decimal>,
+    pub quantity : Decimal,
+    pub leverage : Decimal,
+    pub status : String,
+    pub reason : Option<String>,
+    pub created_at : DateTime<Utc>
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+pub struct TradeRecord {
+    pub trade_id : i64,  //sequence of the fill event
+    pub symbol : String,
+    pub price : Decimal,
+    pub quantity : Decimal,
+    pub taker_side : String,
+    pub maker_order_id : Uuid,
+    pub taker_order_id : Uuid,
+    pub traded_at : DateTime<Utc>
+}
+
+//one side of a trade
+#[derive(Serialize,Deserialize,Clone)]
+pub struct FillRecord {
+    pub trade_id : i64,
+    pub user_id : Uuid,
+    pub order_id : Uuid,
+    pub symbol : String,
+    pub side : String,
+    pub liquidity : String,  //maker / taker
+    pub price : Decimal,
+    pub quantity : Decimal,
+    pub fee : Decimal,
+    pub fee_asset : Option<String>,
+    pub filled_at : DateTime<Utc>
+}
+
+//an order as stored, average_price is None until something filled
+#[derive(Serialize,Deserialize)]
+pub struct OrderRow {
+    pub order_id : Uuid,
+    pub user_id : Uuid,
+    pub symbol : String,
+    pub side : String,
+    pub order_type : String,
+    pub price : Option<Decimal>,
+    pub quantity : Decimal,
+    pub leverage : Decimal,
+    pub filled : Decimal,
+    pub average_price : Option<Decimal>,
+    pub status : String,
+    pub reason : Option<String>,
+    pub created_at : DateTime<Utc>,
+    pub updated_at : DateTime<Utc>
+}
+
+//a position taken over by the liquidation account
+#[derive(Serialize,Deserialize,Clone)]
+pub struct LiquidationRecord {
+    pub event_seq : i64,
+    pub liquidation_id : Uuid,
+    pub user_id : Uuid,
+    pub symbol : String,
+    pub side : String,
+    pub quantity : Decimal,
+    pub mark_price : Decimal,
+    pub bankruptcy_price : Decimal,
+    pub fee : Decimal,
+    pub liquidated_at : DateTime<Utc>
+}
+
+//part of a position closed against a liquidation at its bankruptcy price
+#[derive(Serialize,Deserialize,Clone)]
+pub struct AdlRecord {
+    pub event_seq : i64,
+    pub liquidation_id : Uuid,
+    pub user_id : Uuid,
+    pub symbol : String,
+    pub side : String,
+    pub quantity : Decimal,
+    pub price : Decimal,
+    pub score : Decimal,
+    pub deleveraged_at : DateTime<Utc>
+}
+
+//a stored event that moved a position. kind is maker / taker for the two sides of a fill (with the
+//leverage of the order), liquidation (price = bankruptcy price), adl, or funding (amount only)
+pub struct PositionEventRecord {
+    pub event_seq : i64,
+    pub kind : String,
+    pub user_id : Uuid,
+    pub side : Option<String>,
+    pub price : Option<Decimal>,
+    pub quantity : Option<Decimal>,
+    pub leverage : Option<Decimal>,
+    pub amount : Option<Decimal>,
+    pub at : DateTime<Utc>
+}
+
+#[derive(Default)]
+pub struct OrderFilter<'a> {
+    pub symbol : Option<&'a str>,
+    pub status : Option<&'a str>,
+    pub from : Option<DateTime<Utc>>,
+    pub to : Option<DateTime<Utc>>,
+    pub before : Option<(DateTime<Utc>, Uuid)>,  //keyset cursor: the last row of the previous page
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum OrderChange {
+    Fill { quantity : Decimal, price : Decimal },
+    Cancelled,
+    Expired,
+}
+
+pub struct OrderTransition {
+    pub event_seq : i64,
+    pub order_id : Uuid,
+    pub change : OrderChange,
+    pub at : DateTime<Utc>
+}
+
+//everything one pipeline batch produced, `last_seq` is the sequence of its last event
+#[derive(Default)]
+pub struct EventBatch {
+    pub last_seq : i64,
+    pub orders : Vec<OrderRecord>,
+    pub trades : Vec<TradeRecord>,
+    pub fills : Vec<FillRecord>,
+    pub transitions : Vec<OrderTransition>,
+    pub liquidations : Vec<LiquidationRecord>,
+    pub adl : Vec<AdlRecord>,
+    pub funding : Vec<FundingPayment>
+}
+
+impl EventBatch {
+    pub fn is_empty(&self)->bool{
+        self.orders.is_empty() && self.trades.is_empty() && self.fills.is_empty() && self.transitions.is_empty()
+            && self.liquidations.is_empty() && self.adl.is_empty() && self.funding.is_empty()
+    }
+}
+
+impl Db {
+    pub async fn get_order(&self, order_id:Uuid)->Result<Option<OrderRow>>{
+        let row = sqlx::query_as!(
+            OrderRow,
+            "SELECT order_id,user_id,symbol,side,order_type,price,quantity,leverage,filled,
+                    CASE WHEN filled>0 THEN trim_scale(round(filled_notional/filled,8)) END as average_price,
+                    status,reason,created_at,updated_at
+             FROM orders WHERE order_id=$1",
+            order_id
+        )
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    //newest first
+    pub async fn get_order_history(&self, user_id:Uuid, filter:&OrderFilter<'_>, limit:i64)->Result<Vec<OrderRow>>{
+        let (before_at, before_id) = filter.before.unzip();
+        let rows = sqlx::query_as!(
+            OrderRow,
+            "SELECT order_id,user_id,symbol,side,order_type,price,quantity,leverage,filled,
+                    CASE WHEN filled>0 THEN trim_scale(round(filled_notional/filled,8)) END as average_price,
+                    status,reason,created_at,updated_at
+             FROM orders
+             WHERE user_id=$1
+               AND ($2::text IS NULL OR symbol=$2)
+               AND ($3::text IS NULL OR status=$3)
+               AND ($4::timestamptz IS NULL OR created_at>=$4)
+               AND ($5::timestamptz IS NULL OR created_at<$5)
+               AND ($6::timestamptz IS NULL OR (created_at,order_id)<($6,$7::uuid))
+             ORDER BY created_at DESC, order_id DESC LIMIT $8",
+            user_id, filter.symbol, filter.status, filter.from, filter.to, before_at, before_id, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    //newest first, `before` is an exclusive trade id
+    pub async fn get_trades(&self, symbol:&str, before:Option<i64>, limit:i64)->Result<Vec<TradeRecord>>{
+        let rows = sqlx::query_as!(
+            TradeRecord,
+            "SELECT trade_id,symbol,price,quantity,taker_side,maker_order_id,taker_order_id,traded_at FROM trades
+             WHERE symbol=$1 AND ($2::bigint IS NULL OR trade_id<$2)
+             ORDER BY trade_id DESC LIMIT $3",
+            symbol, before, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    //newest first; a self-trade gives two rows with the same trade id, so the cursor is (trade id, liquidity)
+    pub async fn get_fills(&self, user_id:Uuid, symbol:Option<&str>, before:Option<(i64, &str)>, limit:i64)->Result<Vec<FillRecord>>{
+        let (before_id, before_liquidity) = before.unzip();
+        let rows = sqlx::query_as!(
+            FillRecord,
+            "SELECT trade_id,user_id,order_id,symbol,side,liquidity,price,quantity,fee,fee_asset,filled_at FROM fills
+             WHERE user_id=$1 AND ($2::text IS NULL OR symbol=$2)
+               AND ($3::bigint IS NULL OR (trade_id,liquidity)<($3,$4::text))
+             ORDER BY trade_id DESC, liquidity DESC LIMIT $5",
+            user_id, symbol, before_id, before_liquidity, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    //traded notional per user and day (days since the epoch, UTC) from `since_day` on, both sides
+    //of every fill, oldest day first
+    pub async fn get_daily_volumes(&self, since_day:i64)->Result<Vec<(Uuid, i64, Decimal)>>{
+        let rows = sqlx::query!(
+            r#"SELECT user_id, floor(extract(epoch FROM filled_at)/86400)::bigint as "day!", sum(price*quantity) as "notional!"
+               FROM fills WHERE filled_at >= to_timestamp($1::bigint*86400)
+               GROUP BY 1,2 ORDER BY 2"#,
+            since_day
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.user_id, r.day, r.notional)).collect())
+    }
+
+    //every stored event of the symbol that moved a position, in the order the engine emitted them;
+    //the maker side of a fill comes before the taker side. Funding paid before funding went through
+    //the event stream has no sequence and is left out
+    pub async fn get_position_events(&self, symbol:&str)->Result<Vec<PositionEventRecord>>{
+        let rows = sqlx::query_as!(
+            PositionEventRecord,
+            r#"SELECT event_seq as "event_seq!", kind as "kind!", user_id as "user_id!", side, price, quantity, leverage, amount, at as "at!" FROM (
+                 SELECT f.trade_id as event_seq, f.liquidity as kind, f.user_id, f.side, f.price, f.quantity, o.leverage,
+                        NULL::numeric as amount, f.filled_at as at
+                 FROM fills f JOIN orders o ON o.order_id=f.order_id WHERE f.symbol=$1
+                 UNION ALL
+                 SELECT event_seq, 'liquidation', user_id, side, bankruptcy_price, quantity, NULL, NULL, liquidated_at
+                 FROM liquidations WHERE symbol=$1
+                 UNION ALL
+                 SELECT event_seq, 'adl', user_id, side, price, quantity, NULL, NULL, deleveraged_at
+                 FROM adl WHERE symbol=$1
+                 UNION ALL
+                 SELECT event_seq, 'funding', user_id, NULL, NULL, NULL, NULL, amount, funding_time
+                 FROM funding_payments WHERE symbol=$1 AND event_seq IS NOT NULL
+               ) e ORDER BY 1, 2"#,
+            symbol
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    //0 when the consumer never wrote anything
+    pub async fn get_event_offset(&self, consumer:&str)->Result<i64>{
+        let last_seq = sqlx::query_scalar!("SELECT last_seq FROM event_offsets WHERE consumer=$1", consumer)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(last_seq.unwrap_or(0))
+    }
+
+    //writes the batch and moves the consumer's offset in one transaction. Anything at or below
+    //the stored offset was written before and is skipped, so a retried batch is applied once
+    pub async fn persist_events(&self, consumer:&str, batch:&EventBatch)->Result<()>{
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("INSERT INTO event_offsets (consumer,last_seq) VALUES ($1,0) ON CONFLICT (consumer) DO NOTHING", consumer)
+            .execute(&mut *tx)
+            .await?;
+        let offset = sqlx::query_scalar!("SELECT last_seq FROM event_offsets WHERE consumer=$1 FOR UPDATE", consumer)
+            .fetch_one(&mut *tx)
+            .await?;
+        if batch.last_seq <= offset {
+            return Ok(());
+        }
+
+        let orders: Vec<&OrderRecord> = batch.orders.iter().filter(|o| o.event_seq > offset).collect();
+        if !orders.is_empty() {
+            let seqs: Vec<i64> = orders.iter().map(|o| o.event_seq).collect();
+            let ids: Vec<Uuid> = orders.iter().map(|o| o.order_id).collect();
+            let statuses: Vec<String> = orders.iter().map(|o| o.status.clone()).collect();
+            let reasons: Vec<Option<String>> = orders.iter().map(|o| o.reason.clone()).collect();
+            let created: Vec<DateTime<Utc>> = orders.iter().map(|o| o.created_at).collect();
+            sqlx::query!(
+                "INSERT INTO orders (order_id,user_id,symbol,side,order_type,price,quantity,leverage,status,reason,created_at,updated_at)
+                 SELECT o.id,o.user_id,o.symbol,o.side,o.order_type,o.price,o.quantity,o.leverage,o.status,o.reason,o.at,o.at
+                 FROM UNNEST($1::uuid[],$2::uuid[],$3::text[],$4::text[],$5::text[],$6::numeric[],$7::numeric[],$8::numeric[],$9::text[],$10::text[],$11::timestamptz[])
+                    AS o(id,user_id,symbol,side,order_type,price,quantity,leverage,status,reason,at)
+                 ON CONFLICT (order_id) DO NOTHING",
+                &ids,
+                &orders.iter().map(|o| o.user_id).collect::<Vec<_>>(),
+                &orders.iter().map(|o| o.symbol.clone()).collect::<Vec<_>>(),
+                &orders.iter().map(|o| o.side.clone()).collect::<Vec<_>>(),
+                &orders.iter().map(|o| o.order_type.clone()).collect::<Vec<_>>(),
+                &orders.iter().map(|o| o.price).collect::<Vec<_>>() as &[Option<Decimal>],
+                &orders.iter().map(|o| o.quantity).collect::<Vec<_>>(),
+                &orders.iter().map(|o| o.leverage).collect::<Vec<_>>(),
+                &statuses,
+                &reasons as &[Option<String>],
+                &created
+            )
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!(
+                "INSERT INTO order_updates (event_seq,order_id,status,reason,at)
+                 SELECT * FROM UNNEST($1::bigint[],$2::uuid[],$3::text[],$4::text[],$5::timestamptz[])
+                 ON CONFLICT DO NOTHING",
+                &seqs, &ids, &statuses, &reasons as &[Option<String>], &created
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let trades: Vec<&TradeRecord> = batch.trades.iter().filter(|t| t.trade_id > offset).collect();
+        if !trades.is_empty() {
+            sqlx::query!(
+                "INSERT INTO trades (trade_id,symbol,price,quantity,taker_side,maker_order_id,taker_order_id,traded_at)
+                 SELECT * FROM UNNEST($1::bigint[],$2::text[],$3::numeric[],$4::numeric[],$5::text[],$6::uuid[],$7::uuid[],$8::timestamptz[])
+                 ON CONFLICT (trade_id) DO NOTHING",
+                &trades.iter().map(|t| t.trade_id).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.symbol.clone()).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.price).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.quantity).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.taker_side.clone()).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.maker_order_id).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.taker_order_id).collect::<Vec<_>>(),
+                &trades.iter().map(|t| t.traded_at).collect::<Vec<_>>()
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let fills: Vec<&FillRecord> = batch.fills.iter().filter(|f| f.trade_id > offset).collect();
+        if !fills.is_empty() {
+            sqlx::query!(
+                "INSERT INTO fills (trade_id,user_id,order_id,symbol,side,liquidity,price,quantity,fee,fee_asset,filled_at)
+                 SELECT * FROM UNNEST($1::bigint[],$2::uuid[],$3::uuid[],$4::text[],$5::text[],$6::text[],$7::numeric[],$8::numeric[],$9::numeric[],$10::text[],$11::timestamptz[])
+                 ON CONFLICT (trade_id,liquidity) DO NOTHING",
+                &fills.iter().map(|f| f.trade_id).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.user_id).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.order_id).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.symbol.clone()).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.side.clone()).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.liquidity.clone()).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.price).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.quantity).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.fee).collect::<Vec<_>>(),
+                &fills.iter().map(|f| f.fee_asset.clone()).collect::<Vec<_>>() as &[Option<String>],
+                &fills.iter().map(|f| f.filled_at).collect::<Vec<_>>()
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        //state changes go in event order, each one sees the order as the previous one left it
+        for t in batch.transitions.iter().filter(|t| t.event_seq > offset) {
+            match t.change {
+                OrderChange::Fill { quantity, price } => {
+                    sqlx::query!(
+                        "WITH o AS (
+                            UPDATE orders SET filled=filled+$3, filled_notional=filled_notional+$3*$4,
+                                status=CASE WHEN filled+$3>=quantity THEN 'filled' ELSE 'partially_filled' END, updated_at=$5
+                            WHERE order_id=$2 RETURNING order_id,status
+                         )
+                         INSERT INTO order_updates (event_seq,order_id,status,filled_qty,at)
+                         SELECT $1,order_id,status,$3,$5 FROM o ON CONFLICT DO NOTHING",
+                        t.event_seq, t.order_id, quantity, price, t.at
+                    )
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                OrderChange::Cancelled | OrderChange::Expired => {
+                    let status = if t.change == OrderChange::Cancelled { "cancelled" } else { "expired" };
+                    sqlx::query!(
+                        "WITH o AS (
+                            UPDATE orders SET status=$3, updated_at=$4 WHERE order_id=$2 RETURNING order_id
+                         )
+                         INSERT INTO order_updates (event_seq,order_id,status,at)
+                         SELECT $1,order_id,$3,$4 FROM o ON CONFLICT DO NOTHING",
+                        t.event_seq, t.order_id, status, t.at
+                    )
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+        }
+
+        let liquidations: Vec<&LiquidationRecord> = batch.liquidations.iter().filter(|l| l.event_seq > offset).collect();
+        if !liquidations.is_empty() {
+            sqlx::query!(
+                "INSERT INTO liquidations (event_seq,liquidation_id,user_id,symbol,side,quantity,mark_price,bankruptcy_price,fee,liquidated_at)
+                 SELECT * FROM UNNEST($1::bigint[],$2::uuid[],$3::uuid[],$4::text[],$5::text[],$6::numeric[],$7::numeric[],$8::numeric[],$9::numeric[],$10::timestamptz[])
+                 ON CONFLICT (event_seq) DO NOTHING",
+                &liquidations.iter().map(|l| l.event_seq).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.liquidation_id).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.user_id).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.symbol.clone()).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.side.clone()).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.quantity).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.mark_price).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.bankruptcy_price).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.fee).collect::<Vec<_>>(),
+                &liquidations.iter().map(|l| l.liquidated_at).collect::<Vec<_>>()
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let adl: Vec<&AdlRecord> = batch.adl.iter().filter(|a| a.event_seq > offset).collect();
+        if !adl.is_empty() {
+            sqlx::query!(
+                "INSERT INTO adl (event_seq,liquidation_id,user_id,symbol,side,quantity,price,score,deleveraged_at)
+                 SELECT * FROM UNNEST($1::bigint[],$2::uuid[],$3::uuid[],$4::text[],$5::text[],$6::numeric[],$7::numeric[],$8::numeric[],$9::timestamptz[])
+                 ON CONFLICT (event_seq) DO NOTHING",
+                &adl.iter().map(|a| a.event_seq).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.liquidation_id).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.user_id).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.symbol.clone()).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.side.clone()).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.quantity).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.price).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.score).collect::<Vec<_>>(),
+                &adl.iter().map(|a| a.deleveraged_at).collect::<Vec<_>>()
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let funding: Vec<&FundingPayment> = batch.funding.iter().filter(|f| f.event_seq > offset).collect();
+        if !funding.is_empty() {
+            sqlx::query!(
+                "INSERT INTO funding_payments (event_seq,user_id,symbol,funding_time,position_size,rate,amount)
+                 SELECT * FROM UNNEST($1::bigint[],$2::uuid[],$3::text[],$4::timestamptz[],$5::numeric[],$6::numeric[],$7::numeric[])
+                 ON CONFLICT (event_seq) DO NOTHING",
+                &funding.iter().map(|f| f.event_seq).collect::<Vec<_>>(),
+                &funding.iter().map(|f| f.user_id).collect::<Vec<_>>(),
+                &funding.iter().map(|f| f.symbol.clone()).collect::<Vec<_>>(),
+                &funding.iter().map(|f| f.funding_time).collect::<Vec<_>>(),
+                &funding.iter().map(|f| f.position_size).collect::<Vec<_>>(),
+                &funding.iter().map(|f| f.rate).collect::<Vec<_>>(),
+                &funding.iter().map(|f| f.amount).collect::<Vec<_>>()
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query!("UPDATE event_offsets SET last_seq=$2, updated_at=now() WHERE consumer=$1", consumer, batch.last_seq)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_open_order_ids(&self, symbol:&str)->Result<Vec<Uuid>>{
+        let ids = sqlx::query_scalar!("SELECT order_id FROM orders WHERE symbol=$1 AND status IN ('new','partially_filled')", symbol)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(ids)
+    }
+
+    //orders still open in the store that the restarted book does not hold (`keep`) are gone.
+    //they are marked expired under one sequence taken from the consumer's offset, which numbering
+    //then continues after; returns how many were expired
+    pub async fn expire_open_orders(&self, consumer:&str, at:DateTime<Utc>, keep:&[Uuid])->Result<u64>{
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("INSERT INTO event_offsets (consumer,last_seq) VALUES ($1,0) ON CONFLICT (consumer) DO NOTHING", consumer)
+            .execute(&mut *tx)
+            .await?;
+        let seq = sqlx::query_scalar!("SELECT last_seq FROM event_offsets WHERE consumer=$1 FOR UPDATE", consumer)
+            .fetch_one(&mut *tx)
+            .await? + 1;
+        let expired = sqlx::query!(
+            "WITH o AS (
+                UPDATE orders SET status='expired', reason=$3, updated_at=$2
+                WHERE status IN ('new','partially_filled') AND order_id <> ALL($4) RETURNING order_id
+             )
+             INSERT INTO order_updates (event_seq,order_id,status,reason,at)
+             SELECT $1,order_id,'expired',$3,$2 FROM o",
+            seq, at, "engine restarted", keep
+        )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if expired > 0 {
+            sqlx::query!("UPDATE event_offsets SET last_seq=$2, updated_at=now() WHERE consumer=$1", consumer, seq)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(expired)
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Db, LedgerAccount, insert_entry, transfer_entry};
+
+#[derive(Serialize,Deserialize)]
+pub struct Deposit {
+    pub id : Uuid,
+    pub user_id : Uuid,
+    pub asset : String,
+    pub amount : Decimal,
+    pub custody_ref : String,
+    pub created_at : DateTime<Utc>
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct Withdrawal {
+    pub id : Uuid,
+    pub user_id : Uuid,
+    pub asset : String,
+    pub amount : Decimal,
+    pub address : String,
+    pub status : String,
+    pub custody_ref : Option<String>,
+    pub reason : Option<String>,
+    pub created_at : DateTime<Utc>,
+    pub updated_at : DateTime<Utc>
+}
+
+impl Db {
+    //deposit row and ledger entry together; None when the custody ref was credited before
+    pub async fn record_deposit(&self, user_id:Uuid, asset:&str, amount:Decimal, custody_ref:&str)->Result<Option<Deposit>>{
+        let mut tx = self.pool.begin().await?;
+        let deposit = sqlx::query_as!(
+            Deposit,
+            "INSERT INTO deposits (user_id,asset,amount,custody_ref) VALUES ($1,$2,$3,$4)
+             ON CONFLICT (custody_ref) DO NOTHING
+             RETURNING id,user_id,asset,amount,custody_ref,created_at",
+            user_id, asset, amount, custody_ref
+        )
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(deposit) = deposit else {
+            return Ok(None);
+        };
+        let reference = format!("deposit:{custody_ref}");
+        insert_entry(&mut tx, &transfer_entry(&reference, "deposit", user_id, LedgerAccount::Custody, asset, amount)).await?;
+        tx.commit().await?;
+        Ok(Some(deposit))
+    }
+
+    pub async fn get_deposits(&self, user_id:Option<Uuid>, limit:i64)->Result<Vec<Deposit>>{
+        let rows = sqlx::query_as!(
+            Deposit,
+            "SELECT id,user_id,asset,amount,custody_ref,created_at FROM deposits
+             WHERE ($1::uuid IS NULL OR user_id=$1) ORDER BY created_at DESC LIMIT $2",
+            user_id, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    pub async fn create_withdrawal(&self, user_id:Uuid, asset:&str, amount:Decimal, address:&str)->Result<Withdrawal>{
+        let w = sqlx::query_as!(
+            Withdrawal,
+            "INSERT INTO withdrawals (user_id,asset,amount,address) VALUES ($1,$2,$3,$4)
+             RETURNING id,user_id,asset,amount,address,status,custody_ref,reason,created_at,updated_at",
+            user_id, asset, amount, address
+        )
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(w)
+    }
+
+    pub async fn get_withdrawal(&self, id:Uuid)->Result<Option<Withdrawal>>{
+        let w = sqlx::query_as!(
+            Withdrawal,
+            "SELECT id,user_id,asset,amount,address,status,custody_ref,reason,created_at,updated_at FROM withdrawals WHERE id=$1",
+            id
+        )
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(w)
+    }
+
+    //either filter may be left out, newest first
+    pub async fn get_withdrawals(&self, user_id:Option<Uuid>, status:Option<&str>, limit:i64)->Result<Vec<Withdrawal>>{
+        let rows = sqlx::query_as!(
+            Withdrawal,
+            "SELECT id,user_id,asset,amount,address,status,custody_ref,reason,created_at,updated_at FROM withdrawals
+             WHERE ($1::uuid IS NULL OR user_id=$1) AND ($2::text IS NULL OR status=$2)
+             ORDER BY created_at DESC LIMIT $3",
+            user_id, status, limit
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    //per user, the amount of withdrawals that are neither completed nor rejected
+    pub async fn get_open_withdrawal_totals(&self, asset:&str)->Result<Vec<(Uuid, Decimal)>>{
+        let rows = sqlx::query!(
+            r#"SELECT user_id, SUM(amount) as "amount!" FROM withdrawals
+             WHERE asset=$1 AND status NOT IN ('completed','rejected') GROUP BY user_id"#,
+            asset
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.user_id, r.amount)).collect())
+    }
+
+    //moves the withdrawal to `to` only if it is in one of `from`; None when it was not
+    pub async fn transition_withdrawal(&self, id:Uuid, from:&[&str], to:&str, custody_ref:Option<&str>, reason:Option<&str>)->Result<Option<Withdrawal>>{
+        let from: Vec<String> = from.iter().map(|s| s.to_string()).collect();
+        let w = sqlx::query_as!(
+            Withdrawal,
+            "UPDATE withdrawals SET status=$3, custody_ref=COALESCE($4,custody_ref), reason=COALESCE($5,reason), updated_at=now()
+             WHERE id=$1 AND status=ANY($2)
+             RETURNING id,user_id,asset,amount,address,status,custody_ref,reason,created_at,updated_at",
+            id, &from, to, custody_ref, reason
+        )
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(w)
+    }
+
+    //processing -> completed and the ledger entry for the money leaving, in one transaction
+    pub async fn complete_withdrawal(&self, id:Uuid)->Result<Option<Withdrawal>>{
+        let mut tx = self.pool.begin().await?;
+        let w = sqlx::query_as!(
+            Withdrawal,
+            "UPDATE withdrawals SET status='completed', updated_at=now() WHERE id=$1 AND status='processing'
+             RETURNING id,user_id,asset,amount,address,status,custody_ref,reason,created_at,updated_at",
+            id
+        )
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(w) = w else {
+            return Ok(None);
+        };
+        let reference = format!("withdrawal:{id}");
+        insert_entry(&mut tx, &transfer_entry(&reference, "withdrawal", w.user_id, LedgerAccount::Custody, &w.asset, -w.amount)).await?;
+        tx.commit().await?;
+        Ok(Some(w))
+    }
+}
@@ -34,4 +34,12 @@ impl Db {
             .await?;
         Ok(u)
     }
+
+    //only accounts above the default tier
+    pub async fn get_account_tiers(&self)->Result<Vec<(Uuid,String)>>{
+        let rows = sqlx::query!("SELECT id, tier FROM users WHERE tier <> 'standard'")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.id, r.tier)).collect())
+    }
 }
\ No newline at end of file